@@ -388,6 +388,9 @@ pub async fn download_file_mirrors(
 }
 
 /// Downloads a file with retry and checksum functionality
+///
+/// Brotli or gzip transfer is negotiated through `Accept-Encoding` and the
+/// body is decoded before the checksum is verified.
 pub async fn download_file(
     url: &str,
     sha1: Option<&str>,
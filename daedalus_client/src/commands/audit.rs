@@ -0,0 +1,137 @@
+//! Verify that everything a root manifest references exists in the bucket
+//!
+//! Checks the loader manifests of the live root (or a history snapshot) and
+//! every CAS object they reference. With `--deep`, JSON documents (version
+//! JSONs) are downloaded as well and the objects they reference (libraries,
+//! installers, ...) are checked too.
+//!
+//! Exits with an error if anything is missing, so it can gate CI or alerting.
+
+use crate::infrastructure::error::{invalid_input, Error};
+use crate::services::bucket::{get_object, object_exists};
+use crate::services::cas::CAS_VERSION;
+use crate::services::history::{load_loader_manifest, load_root};
+use crate::services::migration::{manifest_hashes, object_path, referenced_hashes};
+use clap::Args;
+use futures::stream::{self, StreamExt};
+use s3::Bucket;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use tracing::{info, instrument};
+
+/// Number of concurrent existence checks
+const MAX_CONCURRENT_CHECKS: usize = 20;
+
+#[derive(Debug, Args)]
+pub struct AuditArgs {
+    /// Audit a history snapshot instead of the live root manifest
+    #[arg(long)]
+    pub snapshot: Option<String>,
+    /// Also download JSON documents and check the objects they reference
+    #[arg(long)]
+    pub deep: bool,
+}
+
+/// Run the audit subcommand
+#[instrument(skip(s3_client))]
+pub async fn run(args: AuditArgs, s3_client: &Bucket) -> Result<(), Error> {
+    let root = load_root(s3_client, args.snapshot.as_deref()).await?;
+    let mut missing = Vec::new();
+    let mut hashes = BTreeSet::new();
+
+    let mut loaders: Vec<_> = root.loaders.iter().collect();
+    loaders.sort_by(|a, b| a.0.cmp(b.0));
+
+    for (loader, reference) in loaders {
+        match load_loader_manifest(s3_client, &reference.url).await {
+            Ok(manifest) => {
+                let referenced = manifest_hashes(&manifest.versions, CAS_VERSION);
+                println!("{:<10} {} ({} objects)", loader, reference.timestamp, referenced.len());
+                hashes.extend(referenced);
+            }
+            Err(err) => {
+                println!("{:<10} {} (unreadable: {})", loader, reference.timestamp, err);
+                missing.push(reference.url.clone());
+            }
+        }
+    }
+
+    let (found, mut missing_objects) = check_objects(s3_client, &hashes).await?;
+    missing.append(&mut missing_objects);
+    let mut checked = hashes.len();
+
+    if args.deep {
+        let nested = nested_hashes(s3_client, &found).await;
+        let nested: BTreeSet<String> = nested.difference(&hashes).cloned().collect();
+        let (_, mut missing_nested) = check_objects(s3_client, &nested).await?;
+        missing.append(&mut missing_nested);
+        checked += nested.len();
+    }
+
+    info!(checked, missing = missing.len(), "Audit finished");
+
+    if missing.is_empty() {
+        println!("All {} referenced objects exist", checked);
+        return Ok(());
+    }
+
+    for path in &missing {
+        println!("missing: {}", path);
+    }
+
+    Err(invalid_input(format!(
+        "{} of {} referenced objects are missing",
+        missing.len(),
+        checked
+    )))
+}
+
+/// Check which hashes exist
+///
+/// # Returns
+///
+/// The hashes that exist and the bucket paths of those that do not
+async fn check_objects(
+    s3_client: &Bucket,
+    hashes: &BTreeSet<String>,
+) -> Result<(Vec<String>, Vec<String>), Error> {
+    let results: Vec<(String, Result<bool, Error>)> = stream::iter(hashes)
+        .map(|hash| async move {
+            (hash.clone(), object_exists(s3_client, &object_path(CAS_VERSION, hash)).await)
+        })
+        .buffer_unordered(MAX_CONCURRENT_CHECKS)
+        .collect()
+        .await;
+
+    let mut found = Vec::new();
+    let mut missing = Vec::new();
+    for (hash, result) in results {
+        if result? {
+            found.push(hash);
+        } else {
+            missing.push(object_path(CAS_VERSION, &hash));
+        }
+    }
+
+    found.sort();
+    missing.sort();
+    Ok((found, missing))
+}
+
+/// Hashes referenced from inside the JSON documents among `hashes`
+async fn nested_hashes(s3_client: &Bucket, hashes: &[String]) -> BTreeSet<String> {
+    let nested: Vec<BTreeSet<String>> = stream::iter(hashes)
+        .map(|hash| async move {
+            match get_object(s3_client, &object_path(CAS_VERSION, hash)).await {
+                Ok(Some(bytes)) => serde_json::from_slice::<Value>(&bytes)
+                    .map(|document| referenced_hashes(&document, CAS_VERSION))
+                    .unwrap_or_default(),
+                _ => BTreeSet::new(),
+            }
+        })
+        .buffer_unordered(MAX_CONCURRENT_CHECKS)
+        .collect()
+        .await;
+
+    nested.into_iter().flatten().collect()
+}
@@ -0,0 +1,85 @@
+//! Show the version changes between two root manifests
+//!
+//! Unlike `rollback diff`, which only compares loader manifest timestamps,
+//! this loads the loader manifests on both sides and lists the added, removed
+//! and changed versions, in the same format as the per-cycle changelogs.
+
+use crate::infrastructure::error::Error;
+use crate::services::changelog::{Changelog, LoaderChanges};
+use crate::services::history::{load_loader_manifest, load_root, LoaderChange, RootDiff};
+use clap::Args;
+use s3::Bucket;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use tracing::instrument;
+
+#[derive(Debug, Args)]
+pub struct DiffArgs {
+    /// Snapshot id (e.g. `2024-01-15T10-30-00Z`) to compare from
+    pub from: String,
+    /// Snapshot id to compare to (default: the live root manifest)
+    pub to: Option<String>,
+    /// Print the changes as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// Run the diff subcommand
+#[instrument(skip(s3_client))]
+pub async fn run(args: DiffArgs, s3_client: &Bucket) -> Result<(), Error> {
+    let from = load_root(s3_client, Some(&args.from)).await?;
+    let to = load_root(s3_client, args.to.as_deref()).await?;
+    let root_diff = RootDiff::between(Some(&from), &to);
+
+    let mut loaders = BTreeMap::new();
+    for (loader, change) in &root_diff.loaders {
+        let (old, new) = match change {
+            LoaderChange::Unchanged { .. } => continue,
+            LoaderChange::Added { .. } => (Value::Array(Vec::new()), versions(s3_client, &to, loader).await?),
+            LoaderChange::Removed { .. } => (versions(s3_client, &from, loader).await?, Value::Array(Vec::new())),
+            LoaderChange::Changed { .. } => (
+                versions(s3_client, &from, loader).await?,
+                versions(s3_client, &to, loader).await?,
+            ),
+        };
+
+        let changes = LoaderChanges::between(&old, &new);
+        if !changes.is_empty() {
+            loaders.insert(loader.clone(), changes);
+        }
+    }
+
+    let changelog = Changelog {
+        schema_version: 1,
+        timestamp: args.to.unwrap_or_else(|| "live".to_string()),
+        loaders,
+    };
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&changelog)?);
+        return Ok(());
+    }
+
+    print!("{}", root_diff);
+    println!();
+
+    if changelog.is_empty() {
+        println!("No version changes");
+    } else {
+        print!("{}", changelog.body());
+    }
+
+    Ok(())
+}
+
+/// `versions` of the manifest a root references for `loader`
+async fn versions(
+    s3_client: &Bucket,
+    root: &crate::services::cas::RootManifest,
+    loader: &str,
+) -> Result<Value, Error> {
+    match root.loaders.get(loader) {
+        Some(reference) => Ok(load_loader_manifest(s3_client, &reference.url).await?.versions),
+        None => Ok(Value::Array(Vec::new())),
+    }
+}
@@ -0,0 +1,111 @@
+//! Delete expired history snapshots and loader manifests on demand
+//!
+//! Applies the same retention policy the daemon enforces after each commit
+//! (see `services::retention`), configured through the `retention` config
+//! section. The policy defaults are used when retention is disabled, since
+//! running `gc` is an explicit request to clean up.
+//!
+//! It also deletes the `.br`/`.gz` variants of the root and loader manifests
+//! that earlier versions published next to them. They are no longer written,
+//! so the root variants would otherwise keep serving an outdated root.
+
+use crate::infrastructure::config::Config;
+use crate::infrastructure::error::Error;
+use crate::services::bucket::{delete_object, list_objects};
+use crate::services::cas::{root_manifest_path, CAS_VERSION};
+use crate::services::history::load_root;
+use crate::services::lock::with_lock;
+use crate::services::retention::{enforce, resolve, RetentionPolicy};
+use clap::Args;
+use s3::Bucket;
+use tracing::instrument;
+
+#[derive(Debug, Args)]
+pub struct GcArgs {
+    /// Only list what would be deleted
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+/// Run the gc subcommand
+#[instrument(skip(config, s3_client))]
+pub async fn run(args: GcArgs, config: &Config, s3_client: &Bucket) -> Result<(), Error> {
+    let policy = RetentionPolicy::from_config(&config.retention).unwrap_or_default();
+
+    if args.dry_run {
+        let live_root = load_root(s3_client, None).await?;
+        let plan = resolve(s3_client, &policy, &live_root).await?;
+        let variants = compressed_variants(s3_client).await?;
+        for key in plan.snapshots.iter().chain(&plan.loader_manifests).chain(&variants) {
+            println!("{}", key);
+        }
+        println!(
+            "Would delete {} history snapshots, {} loader manifests and {} compressed variants",
+            plan.snapshots.len(),
+            plan.loader_manifests.len(),
+            variants.len()
+        );
+        return Ok(());
+    }
+
+    // The live root must not move while its unreferenced manifests are deleted
+    let (plan, variants) = with_lock(s3_client, &config.lock, async {
+        let live_root = load_root(s3_client, None).await?;
+        let plan = enforce(s3_client, &policy, &live_root).await?;
+
+        let variants = compressed_variants(s3_client).await?;
+        for key in &variants {
+            delete_object(s3_client, key).await?;
+        }
+        Ok((plan, variants))
+    })
+    .await?;
+
+    let urls: Vec<String> = variants.iter().map(|key| crate::format_url(key)).collect();
+    crate::services::cloudflare::purge_if_enabled(config, &urls).await;
+
+    println!(
+        "Deleted {} history snapshots, {} loader manifests and {} compressed variants",
+        plan.snapshots.len(),
+        plan.loader_manifests.len(),
+        variants.len()
+    );
+
+    Ok(())
+}
+
+/// Keys of the compressed root and loader manifest variants in the bucket
+async fn compressed_variants(s3_client: &Bucket) -> Result<Vec<String>, Error> {
+    let mut keys = Vec::new();
+
+    for prefix in [root_manifest_path(), format!("v{}/manifests/", CAS_VERSION)] {
+        keys.extend(
+            list_objects(s3_client, &prefix)
+                .await?
+                .into_iter()
+                .map(|object| object.key)
+                .filter(|key| is_compressed_variant(key)),
+        );
+    }
+
+    keys.sort();
+    Ok(keys)
+}
+
+/// Whether `key` is a `.br` or `.gz` variant of a JSON manifest
+fn is_compressed_variant(key: &str) -> bool {
+    key.ends_with(".json.br") || key.ends_with(".json.gz")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_compressed_variant() {
+        assert!(is_compressed_variant(&format!("{}.br", root_manifest_path())));
+        assert!(is_compressed_variant("v3/manifests/forge/2024-01-15T10-30-00Z.json.gz"));
+        assert!(!is_compressed_variant(&root_manifest_path()));
+        assert!(!is_compressed_variant("v3/manifests/forge/2024-01-15T10-30-00Z.json"));
+    }
+}
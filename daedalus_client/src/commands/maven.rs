@@ -0,0 +1,28 @@
+//! Publish the libraries missing from the maven repository
+//!
+//! Cycles only publish the libraries they rehost, so versions left unchanged
+//! since before the maven view existed (or whose publication failed) are
+//! filled in by running this once, see `services::maven::backfill`. It does
+//! not touch the root manifest and can be run while the daemon runs.
+
+use crate::infrastructure::config::Config;
+use crate::infrastructure::error::{invalid_input, Error};
+use crate::services::history::load_current_root;
+use s3::Bucket;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tracing::instrument;
+
+/// Run the maven-backfill subcommand
+#[instrument(skip_all)]
+pub async fn backfill(config: &Config, s3_client: &Bucket, semaphore: Arc<Semaphore>) -> Result<(), Error> {
+    let root = load_current_root(s3_client)
+        .await?
+        .ok_or_else(|| invalid_input("No root manifest has been published yet"))?;
+
+    let urls = crate::services::maven::backfill(s3_client, &root, semaphore).await?;
+    crate::services::cloudflare::purge_if_enabled(config, &urls).await;
+    println!("Published {} maven files", urls.len());
+
+    Ok(())
+}
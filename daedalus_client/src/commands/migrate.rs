@@ -0,0 +1,377 @@
+//! Migrate a published CAS tree to the current `CAS_VERSION`
+//!
+//! Bumping `CAS_VERSION` normally leaves the old tree orphaned and makes the
+//! next cycle re-download every upstream artifact. This command instead
+//! builds the new tree from the old one:
+//!
+//! 1. every object under `v{from}/objects/` is copied to `v{to}/objects/`
+//!    (server-side, skipping objects that already exist)
+//! 2. JSON documents referenced by the old loader manifests, and the documents
+//!    those reference in turn, are rewritten to point into the new tree and
+//!    re-uploaded under their new hash, referenced documents first
+//! 3. the loader manifests are rewritten accordingly (including the `size` of
+//!    entries whose document changed) and a new root manifest is published
+//! 4. the per-game-version indexes (see `services::index`) are derived from
+//!    the new loader manifests and published under `v{to}/index/`
+//!
+//! Nothing is fetched from Mojang, Forge or the Fabric maven. The command is
+//! resumable: re-running it skips objects that were already copied. The new
+//! root is only published if the one read at the start is still live.
+
+use crate::infrastructure::config::Config;
+use crate::infrastructure::error::{invalid_input, s3_error, Error, ErrorKind};
+use crate::services::bucket::{copy_object, get_object, get_object_with_etag, list_objects, WriteCondition};
+use crate::services::cas::{root_manifest_path, LoaderManifest, LoaderReference, RootManifest, CAS_VERSION};
+use crate::services::index::{self, IndexSet};
+use crate::services::lock::with_lock;
+use crate::services::migration::{
+    manifest_hashes, object_path, objects_prefix, referenced_hashes, rewrite_order, rewrite_value,
+    update_sizes,
+};
+use crate::services::upload::BatchUploader;
+use clap::Args;
+use futures::stream::{self, StreamExt};
+use s3::Bucket;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tracing::{error, info, instrument, warn};
+
+/// Number of concurrent object copies
+const MAX_CONCURRENT_COPIES: usize = 20;
+
+#[derive(Debug, Args)]
+pub struct MigrateArgs {
+    /// CAS version to migrate from (must be older than the current one)
+    #[arg(long)]
+    pub from: u32,
+    /// Download and re-upload objects instead of server-side copies, for
+    /// storage backends without CopyObject support
+    #[arg(long)]
+    pub no_server_side_copy: bool,
+    /// Only report what would be migrated
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+/// Summary of an object copy pass
+#[derive(Debug, Default)]
+struct CopyStats {
+    copied: usize,
+    existing: usize,
+    failed: usize,
+}
+
+/// Run the migration, holding the run lock unless it is a dry run
+#[instrument(skip(config, s3_client, semaphore))]
+pub async fn run(
+    args: MigrateArgs,
+    config: &Config,
+    s3_client: &Bucket,
+    semaphore: Arc<Semaphore>,
+) -> Result<(), Error> {
+    if args.dry_run {
+        migrate(args, config, s3_client, semaphore).await
+    } else {
+        with_lock(s3_client, &config.lock, migrate(args, config, s3_client, semaphore)).await
+    }
+}
+
+async fn migrate(
+    args: MigrateArgs,
+    config: &Config,
+    s3_client: &Bucket,
+    semaphore: Arc<Semaphore>,
+) -> Result<(), Error> {
+    let from = args.from;
+    let to = CAS_VERSION;
+
+    if from >= to {
+        return Err(invalid_input(format!(
+            "Can only migrate from an older CAS version (from v{}, current v{})",
+            from, to
+        )));
+    }
+
+    info!(from, to, "🚚 Starting CAS migration");
+
+    // Old root and loader manifests
+    let root_path = format!("v{}/manifest.json", from);
+    let root_bytes = get_object(s3_client, &root_path)
+        .await?
+        .ok_or_else(|| invalid_input(format!("Root manifest {} does not exist", root_path)))?;
+    let old_root: RootManifest = serde_json::from_slice(&root_bytes)?;
+
+    let mut old_manifests = HashMap::new();
+    for (loader, reference) in &old_root.loaders {
+        let bytes = get_object(s3_client, &reference.url).await?.ok_or_else(|| {
+            invalid_input(format!(
+                "Loader manifest {} referenced by {} does not exist",
+                reference.url, root_path
+            ))
+        })?;
+        old_manifests.insert(loader.clone(), serde_json::from_slice::<LoaderManifest>(&bytes)?);
+    }
+
+    let documents: BTreeSet<String> = old_manifests
+        .values()
+        .flat_map(|manifest| manifest_hashes(&manifest.versions, from))
+        .collect();
+
+    // The new root is published on top of whatever is live now, and only if
+    // that has not changed by then
+    let live = get_object_with_etag(s3_client, &root_manifest_path()).await?;
+    let condition = match &live {
+        None => WriteCondition::IfAbsent,
+        Some((_, Some(etag))) => WriteCondition::IfMatch(etag.clone()),
+        Some((_, None)) => WriteCondition::Always,
+    };
+    let live_root = match live {
+        Some((bytes, _)) => Some(serde_json::from_slice::<RootManifest>(&bytes)?),
+        None => None,
+    };
+
+    let objects = list_objects(s3_client, &objects_prefix(from)).await?;
+    let total_size: u64 = objects.iter().map(|object| object.size).sum();
+
+    info!(
+        loaders = old_manifests.len(),
+        documents = documents.len(),
+        objects = objects.len(),
+        total_size,
+        "Resolved old CAS tree"
+    );
+
+    if args.dry_run {
+        println!(
+            "Would migrate v{} -> v{}: {} objects ({} bytes), {} referenced documents, {} loader manifests",
+            from,
+            to,
+            objects.len(),
+            total_size,
+            documents.len(),
+            old_manifests.len()
+        );
+        return Ok(());
+    }
+
+    // 1. Copy the object tree
+    let existing: HashSet<String> = list_objects(s3_client, &objects_prefix(to))
+        .await?
+        .into_iter()
+        .map(|object| object.key)
+        .collect();
+    let stats = copy_objects(
+        s3_client,
+        objects.into_iter().map(|object| object.key).collect(),
+        &existing,
+        from,
+        to,
+        !args.no_server_side_copy,
+    )
+    .await;
+
+    info!(
+        copied = stats.copied,
+        existing = stats.existing,
+        failed = stats.failed,
+        "Object copy finished"
+    );
+
+    if stats.failed > 0 {
+        return Err(invalid_input(format!(
+            "{} objects failed to copy, not publishing the migrated root. Re-run to resume.",
+            stats.failed
+        )));
+    }
+
+    // 2. Rewrite documents that point into the old tree, including the
+    // documents referenced from other documents
+    let mut parsed = HashMap::new();
+    let mut pending: Vec<String> = documents.iter().cloned().collect();
+    let mut visited = BTreeSet::new();
+
+    while let Some(hash) = pending.pop() {
+        if !visited.insert(hash.clone()) {
+            continue;
+        }
+
+        let path = object_path(from, &hash);
+        let Some(bytes) = get_object(s3_client, &path).await? else {
+            warn!(path = %path, "Referenced object does not exist, leaving reference as is");
+            continue;
+        };
+
+        let Ok(document) = serde_json::from_slice::<serde_json::Value>(&bytes) else {
+            // Not JSON (e.g. a jar), copied verbatim
+            continue;
+        };
+
+        pending.extend(referenced_hashes(&document, from));
+        parsed.insert(hash, document);
+    }
+
+    let uploader = BatchUploader::new(&config.upload);
+    let mut rewritten_hashes = HashMap::new();
+    let mut sizes = HashMap::new();
+
+    for hash in rewrite_order(&parsed, from) {
+        let Some(document) = parsed.remove(&hash) else {
+            continue;
+        };
+
+        let (mut document, changed) = rewrite_value(document, from, to, &rewritten_hashes);
+        if !changed {
+            continue;
+        }
+        update_sizes(&mut document, to, &sizes);
+
+        let bytes = crate::common::to_canonical_vec(&document)?;
+        let size = bytes.len() as u64;
+        let new_hash = uploader
+            .upload_cas(bytes, Some("application/json".to_string()), s3_client, semaphore.clone())
+            .await?;
+
+        sizes.insert(new_hash.clone(), size);
+        rewritten_hashes.insert(hash, new_hash);
+    }
+
+    info!(rewritten = rewritten_hashes.len(), "Rewrote documents into the new tree");
+
+    // 3. Rewrite and publish loader manifests, then the root
+    let mut references = HashMap::new();
+    let mut migrated = HashMap::new();
+    let mut loaders: Vec<_> = old_manifests.into_iter().collect();
+    loaders.sort_by(|a, b| a.0.cmp(&b.0));
+
+    for (loader, manifest) in loaders {
+        let (mut versions, _) = rewrite_value(manifest.versions, from, to, &rewritten_hashes);
+        update_sizes(&mut versions, to, &sizes);
+        let manifest = LoaderManifest::new(loader.clone(), versions);
+        let path = LoaderReference::new(&loader, manifest.timestamp.clone()).url;
+        let bytes = crate::common::to_canonical_vec(&manifest)?;
+
+        crate::upload_file_to_bucket(
+            path.clone(),
+            bytes.clone(),
+            Some("application/json".to_string()),
+            &tokio::sync::Mutex::new(Vec::new()),
+            semaphore.clone(),
+        )
+        .await?;
+
+        info!(loader = %loader, path = %path, "Migrated loader manifest");
+        references.insert(loader.clone(), LoaderReference::new(&loader, manifest.timestamp.clone()));
+        migrated.insert(loader, manifest);
+    }
+
+    let indexes = IndexSet::build(&migrated.iter().map(|(loader, manifest)| (loader.clone(), manifest)).collect())?;
+    let mut root = RootManifest::new(references);
+    root.indexes = Some(index::index_reference());
+    let timestamp = crate::services::history::commit_timestamp();
+    let mut urls = crate::services::history::publish_root_manifest(&root, &timestamp, &condition, &config.upload, semaphore.clone())
+        .await
+        .map_err(|err| match err {
+            ErrorKind::Conflict { .. } => invalid_input(format!(
+                "The v{} root manifest changed during the migration, not overwriting it",
+                to
+            )),
+            err => err,
+        })?;
+
+    // 4. Indexes live at fixed paths, so they follow the committed root
+    let previous = match live_root.filter(|live_root| live_root.indexes.is_some()) {
+        Some(live_root) => index::load(s3_client, &live_root).await,
+        None => Ok(IndexSet::default()),
+    };
+    match previous {
+        Ok(previous) => match index::publish(s3_client, &indexes, &previous, semaphore).await {
+            Ok(index_urls) => urls.extend(index_urls),
+            Err(err) => {
+                warn!(error = %err, "Failed to publish the indexes of the migrated tree");
+                println!("Failed to publish indexes: {}", err);
+            }
+        },
+        Err(err) => {
+            warn!(error = %err, "Failed to load the indexes of the replaced root");
+            println!("Failed to publish indexes: {}", err);
+        }
+    }
+
+    crate::services::cloudflare::purge_if_enabled(config, &urls).await;
+
+    info!(from, to, "✅ CAS migration completed");
+    println!("Migrated v{} -> v{} and published the new root manifest", from, to);
+
+    Ok(())
+}
+
+/// Copy every object key from the `from` tree into the `to` tree
+///
+/// Keys whose destination is in `existing` are skipped.
+async fn copy_objects(
+    s3_client: &Bucket,
+    keys: Vec<String>,
+    existing: &HashSet<String>,
+    from: u32,
+    to: u32,
+    server_side: bool,
+) -> CopyStats {
+    let old_prefix = objects_prefix(from);
+    let new_prefix = objects_prefix(to);
+
+    let results: Vec<Result<bool, Error>> = stream::iter(keys)
+        .map(|key| {
+            let destination = key.replacen(&old_prefix, &new_prefix, 1);
+            async move {
+                if existing.contains(&destination) {
+                    return Ok(false);
+                }
+
+                let result = if server_side {
+                    copy_object(s3_client, &key, &destination).await
+                } else {
+                    copy_via_client(s3_client, &key, &destination).await
+                };
+
+                if let Err(err) = &result {
+                    error!(from = %key, to = %destination, error = %err, "Failed to copy object");
+                }
+
+                result.map(|_| true)
+            }
+        })
+        .buffer_unordered(MAX_CONCURRENT_COPIES)
+        .collect()
+        .await;
+
+    let mut stats = CopyStats::default();
+    for result in results {
+        match result {
+            Ok(true) => stats.copied += 1,
+            Ok(false) => stats.existing += 1,
+            Err(_) => stats.failed += 1,
+        }
+    }
+    stats
+}
+
+/// Copy an object by downloading and re-uploading it
+async fn copy_via_client(s3_client: &Bucket, from: &str, to: &str) -> Result<(), Error> {
+    let (head, _) = s3_client
+        .head_object(from)
+        .await
+        .map_err(|err| s3_error(err, from))?;
+    let bytes = get_object(s3_client, from)
+        .await?
+        .ok_or_else(|| invalid_input(format!("Object {} disappeared during migration", from)))?;
+
+    crate::upload_file_to_bucket(
+        to.to_string(),
+        bytes,
+        head.content_type,
+        &tokio::sync::Mutex::new(Vec::new()),
+        Arc::new(Semaphore::new(1)),
+    )
+    .await
+}
@@ -0,0 +1,15 @@
+//! Operator subcommands
+//!
+//! Without a subcommand the client runs as the long-lived update daemon
+//! (`run`). The other commands are one-shot tools that reuse the same bucket
+//! client and configuration.
+
+pub mod audit;
+pub mod diff;
+pub mod gc;
+pub mod maven;
+pub mod migrate;
+pub mod quarantine;
+pub mod rollback;
+pub mod run;
+pub mod trigger;
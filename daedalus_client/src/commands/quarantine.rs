@@ -0,0 +1,114 @@
+//! Inspect and edit the quarantine of failing versions
+//!
+//! Changes are written conditionally on the stored quarantine, so they can be
+//! made while the daemon runs; it picks them up at the start of the next cycle
+//! of each loader.
+
+use crate::infrastructure::config::Config;
+use crate::infrastructure::error::{invalid_input, Error, ErrorKind};
+use crate::services::quarantine;
+use clap::{Args, Subcommand};
+use s3::Bucket;
+use tracing::{info, instrument, warn};
+
+/// Writes attempted before giving up on concurrent changes
+const MAX_WRITE_ATTEMPTS: u32 = 5;
+
+#[derive(Debug, Args)]
+pub struct QuarantineArgs {
+    #[command(subcommand)]
+    pub action: QuarantineAction,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum QuarantineAction {
+    /// List quarantined and skipped versions with their state and last error
+    List {
+        /// Only show this loader
+        #[arg(long)]
+        loader: Option<String>,
+    },
+    /// Skip a version until it is cleared
+    Skip { loader: String, version: String },
+    /// Process a quarantined or skipped version again
+    Clear { loader: String, version: String },
+}
+
+/// Run a quarantine subcommand
+pub async fn run(args: QuarantineArgs, config: &Config, s3_client: &Bucket) -> Result<(), Error> {
+    match args.action {
+        QuarantineAction::List { loader } => list(s3_client, loader.as_deref()).await,
+        QuarantineAction::Skip { loader, version } => change(config, s3_client, &loader, &version, true).await,
+        QuarantineAction::Clear { loader, version } => change(config, s3_client, &loader, &version, false).await,
+    }
+}
+
+#[instrument(skip(s3_client))]
+async fn list(s3_client: &Bucket, only: Option<&str>) -> Result<(), Error> {
+    let (quarantine, _) = quarantine::fetch(s3_client).await?;
+    let only = only.map(str::to_lowercase);
+
+    let mut shown = 0;
+    for (loader, entries) in &quarantine.loaders {
+        if only.as_ref().is_some_and(|only| only != loader) {
+            continue;
+        }
+
+        println!("{}", loader);
+        for (version, entry) in entries {
+            let state = serde_json::to_value(entry.state)?;
+            let mut line = format!("  {:<30} {}", version, state.as_str().unwrap_or_default());
+            if entry.attempts > 0 {
+                line.push_str(&format!(", {} failed attempts", entry.attempts));
+            }
+            if let Some(at) = entry.next_retry_at {
+                line.push_str(&format!(", retried after {}", at.to_rfc3339()));
+            }
+            println!("{}", line);
+            if let Some(error) = &entry.error {
+                println!("  {:<30} {}", "", error);
+            }
+        }
+        shown += 1;
+    }
+
+    if shown == 0 {
+        println!("No quarantined or skipped versions");
+    }
+    Ok(())
+}
+
+#[instrument(skip(config, s3_client))]
+async fn change(config: &Config, s3_client: &Bucket, loader: &str, version: &str, skip: bool) -> Result<(), Error> {
+    let loader = loader.to_lowercase();
+    if config.loaders.get(&loader).is_none() {
+        return Err(invalid_input(format!("`{}` has no quarantine", loader)));
+    }
+
+    for attempt in 1..=MAX_WRITE_ATTEMPTS {
+        let (mut quarantine, etag) = quarantine::fetch(s3_client).await?;
+        if skip {
+            quarantine.skip(&loader, version);
+        } else if !quarantine.clear(&loader, version) {
+            println!("{} {} is not quarantined", loader, version);
+            return Ok(());
+        }
+
+        match quarantine::publish(s3_client, &quarantine, etag.as_deref()).await {
+            Ok(_) => {
+                info!(loader = %loader, version = %version, skipped = skip, "Quarantine changed");
+                println!("{} {} {}", if skip { "Skipping" } else { "Cleared" }, loader, version);
+                return Ok(());
+            }
+            Err(ErrorKind::Conflict { .. }) => {
+                warn!(attempt, "Quarantine changed since it was read, retrying");
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(invalid_input(format!(
+        "The quarantine kept changing, gave up after {} attempts",
+        MAX_WRITE_ATTEMPTS
+    )))
+}
@@ -0,0 +1,295 @@
+//! Roll the root manifest back to a historical snapshot
+//!
+//! Loader manifests and CAS objects are never overwritten, so rolling back is
+//! just re-publishing an older root manifest (or a per-loader mix of several)
+//! as `v{CAS_VERSION}/manifest.json`, followed by a CDN purge. The indexes
+//! live at fixed paths, so they are rebuilt from the restored loader
+//! manifests once the root is published.
+
+use crate::infrastructure::config::Config;
+use crate::infrastructure::error::{invalid_input, Error};
+use crate::services::bucket::{object_exists, WriteCondition};
+use crate::services::cas::{LoaderReference, RootManifest};
+use crate::services::history::{
+    commit_timestamp, compose_root, list_snapshots, load_current_root, load_snapshot,
+    publish_root_manifest, snapshot_id, RootDiff,
+};
+use crate::services::index;
+use crate::services::lock::with_lock;
+use clap::{Args, Subcommand};
+use s3::Bucket;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tracing::{info, instrument, warn};
+
+#[derive(Debug, Args)]
+pub struct RollbackArgs {
+    #[command(subcommand)]
+    pub action: RollbackAction,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum RollbackAction {
+    /// List history snapshots with their loader manifest timestamps
+    List {
+        /// Only show the most recent N snapshots
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Show how a snapshot differs from the live root manifest
+    Diff {
+        /// Snapshot id (e.g. `2024-01-15T10-30-00Z`) or history key
+        snapshot: String,
+    },
+    /// Publish a snapshot, or a per-loader mix, as the live root manifest
+    Apply {
+        /// Snapshot to restore. With `--loader`, only the listed loaders are
+        /// taken from it and all others keep their live manifest.
+        snapshot: Option<String>,
+        /// Restore a single loader, as `NAME` (from SNAPSHOT) or
+        /// `NAME=SNAPSHOT`. Can be repeated.
+        #[arg(long = "loader", value_name = "NAME[=SNAPSHOT]")]
+        loaders: Vec<String>,
+        /// Print the resulting diff without publishing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+/// Run a rollback subcommand
+pub async fn run(
+    args: RollbackArgs,
+    config: &Config,
+    s3_client: &Bucket,
+    semaphore: Arc<Semaphore>,
+) -> Result<(), Error> {
+    match args.action {
+        RollbackAction::List { limit } => list(s3_client, limit).await,
+        RollbackAction::Diff { snapshot } => diff(s3_client, &snapshot).await,
+        RollbackAction::Apply {
+            snapshot,
+            loaders,
+            dry_run,
+        } if dry_run => apply(config, s3_client, snapshot, loaders, true, semaphore).await,
+        RollbackAction::Apply {
+            snapshot, loaders, ..
+        } => {
+            with_lock(
+                s3_client,
+                &config.lock,
+                apply(config, s3_client, snapshot, loaders, false, semaphore),
+            )
+            .await
+        }
+    }
+}
+
+#[instrument(skip(s3_client))]
+async fn list(s3_client: &Bucket, limit: usize) -> Result<(), Error> {
+    let snapshots = list_snapshots(s3_client).await?;
+    let current = load_current_root(s3_client).await?;
+
+    if snapshots.is_empty() {
+        println!("No history snapshots found");
+        return Ok(());
+    }
+
+    let shown = &snapshots[snapshots.len().saturating_sub(limit)..];
+    println!(
+        "Showing {} of {} snapshots (newest last)",
+        shown.len(),
+        snapshots.len()
+    );
+
+    for entry in shown {
+        match load_snapshot(s3_client, &entry.id).await {
+            Ok(root) => {
+                let live = current
+                    .as_ref()
+                    .map(|current| RootDiff::between(Some(current), &root).is_empty())
+                    .unwrap_or(false);
+
+                let mut loaders: Vec<_> = root.loaders.iter().collect();
+                loaders.sort_by(|a, b| a.0.cmp(b.0));
+
+                println!("{}{}", entry.id, if live { "  (live)" } else { "" });
+                for (loader, reference) in loaders {
+                    println!("  {:<10} {}", loader, reference.timestamp);
+                }
+            }
+            Err(err) => {
+                warn!(snapshot = %entry.id, error = %err, "Failed to read history snapshot");
+                println!("{}  (unreadable: {})", entry.id, err);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[instrument(skip(s3_client))]
+async fn diff(s3_client: &Bucket, snapshot: &str) -> Result<(), Error> {
+    let target = load_snapshot(s3_client, snapshot).await?;
+    let current = load_current_root(s3_client).await?;
+
+    print_diff(snapshot_id(snapshot), &RootDiff::between(current.as_ref(), &target));
+    Ok(())
+}
+
+#[instrument(skip(config, s3_client, semaphore))]
+async fn apply(
+    config: &Config,
+    s3_client: &Bucket,
+    snapshot: Option<String>,
+    loaders: Vec<String>,
+    dry_run: bool,
+    semaphore: Arc<Semaphore>,
+) -> Result<(), Error> {
+    let current = load_current_root(s3_client).await?;
+
+    let target = if loaders.is_empty() {
+        let snapshot = snapshot.ok_or_else(|| {
+            invalid_input("Either a snapshot or at least one --loader is required")
+        })?;
+        let root = load_snapshot(s3_client, &snapshot).await?;
+        let mut target = RootManifest::new(root.loaders);
+        target.indexes = root
+            .indexes
+            .or_else(|| current.as_ref().and_then(|current| current.indexes.clone()));
+        target
+    } else {
+        let base = current.clone().ok_or_else(|| {
+            invalid_input("No live root manifest to apply per-loader rollbacks onto")
+        })?;
+
+        let mut snapshots: HashMap<String, RootManifest> = HashMap::new();
+        let mut overrides: Vec<(String, LoaderReference)> = Vec::new();
+
+        for spec in &loaders {
+            let (loader, source) = parse_loader_spec(spec, snapshot.as_deref())?;
+
+            if !snapshots.contains_key(&source) {
+                let root = load_snapshot(s3_client, &source).await?;
+                snapshots.insert(source.clone(), root);
+            }
+
+            let reference = snapshots[&source].loaders.get(&loader).ok_or_else(|| {
+                invalid_input(format!("Snapshot {} has no {} manifest", source, loader))
+            })?;
+
+            overrides.push((loader, reference.clone()));
+        }
+
+        compose_root(&base, overrides)
+    };
+
+    // Never point the root at something that is not there
+    for (loader, reference) in &target.loaders {
+        if !object_exists(s3_client, &reference.url).await? {
+            return Err(invalid_input(format!(
+                "Loader manifest {} for {} does not exist, refusing to publish",
+                reference.url, loader
+            )));
+        }
+    }
+
+    let diff = RootDiff::between(current.as_ref(), &target);
+    print_diff("target", &diff);
+
+    if diff.is_empty() {
+        println!("Live root manifest already matches the target, nothing to do");
+        return Ok(());
+    }
+
+    if dry_run {
+        println!("Dry run, nothing published");
+        return Ok(());
+    }
+
+    let timestamp = commit_timestamp();
+    let mut urls =
+        publish_root_manifest(&target, &timestamp, &WriteCondition::Always, &config.upload, semaphore.clone()).await?;
+
+    info!(snapshot = %timestamp, "Rollback published");
+    println!("Published rollback as snapshot {}", timestamp);
+
+    if target.indexes.is_some() {
+        match republish_indexes(s3_client, current.as_ref(), &target, semaphore).await {
+            Ok(index_urls) => urls.extend(index_urls),
+            Err(err) => {
+                warn!(error = %err, "Failed to republish indexes, they still describe the previous root");
+                println!("Failed to republish indexes: {}", err);
+            }
+        }
+    }
+
+    crate::services::cloudflare::purge_if_enabled(config, &urls).await;
+
+    Ok(())
+}
+
+/// Rebuild the indexes for the restored loader manifests
+///
+/// Only indexes that differ from the ones of the previously live root are
+/// uploaded.
+async fn republish_indexes(
+    s3_client: &Bucket,
+    current: Option<&RootManifest>,
+    target: &RootManifest,
+    semaphore: Arc<Semaphore>,
+) -> Result<Vec<String>, Error> {
+    let indexes = index::load(s3_client, target).await?;
+    let previous = match current.filter(|current| current.indexes.is_some()) {
+        Some(current) => index::load(s3_client, current).await?,
+        None => index::IndexSet::default(),
+    };
+
+    index::publish(s3_client, &indexes, &previous, semaphore).await
+}
+
+/// Parse `NAME` or `NAME=SNAPSHOT` into a loader name and snapshot id
+fn parse_loader_spec(spec: &str, default: Option<&str>) -> Result<(String, String), Error> {
+    let (loader, snapshot) = match spec.split_once('=') {
+        Some((loader, snapshot)) => (loader, snapshot),
+        None => (
+            spec,
+            default.ok_or_else(|| {
+                invalid_input(format!(
+                    "--loader {} needs a snapshot, use {}=<SNAPSHOT> or pass SNAPSHOT",
+                    spec, spec
+                ))
+            })?,
+        ),
+    };
+
+    if loader.is_empty() || snapshot.is_empty() {
+        return Err(invalid_input(format!("Invalid --loader value: {}", spec)));
+    }
+
+    Ok((loader.to_string(), snapshot_id(snapshot).to_string()))
+}
+
+fn print_diff(label: &str, diff: &RootDiff) {
+    println!("live -> {}", label);
+    print!("{}", diff);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_loader_spec() {
+        assert_eq!(
+            parse_loader_spec("forge=2024-01-15T10-30-00Z", None).unwrap(),
+            ("forge".to_string(), "2024-01-15T10-30-00Z".to_string())
+        );
+        assert_eq!(
+            parse_loader_spec("forge", Some("manifest-2024-01-15T10-30-00Z.json")).unwrap(),
+            ("forge".to_string(), "2024-01-15T10-30-00Z".to_string())
+        );
+        assert!(parse_loader_spec("forge", None).is_err());
+        assert!(parse_loader_spec("=2024-01-15T10-30-00Z", None).is_err());
+    }
+}
@@ -0,0 +1,783 @@
+//! Processing cycles: the update daemon and one-shot runs
+//!
+//! A cycle fetches upstream metadata for the selected loaders, uploads new
+//! CAS objects as it goes, then publishes the loader manifests and commits
+//! them with a new root manifest. `run` repeats it on an interval, `once` and
+//! `only` run a single cycle.
+
+use crate::infrastructure::config::Config;
+use crate::infrastructure::admin::Admin;
+use crate::infrastructure::circuit_breaker::{CircuitBreaker, CircuitBreakerError};
+use crate::infrastructure::health::{self, Readiness};
+use crate::infrastructure::http;
+use crate::infrastructure::metrics::{self, Outcome};
+use crate::infrastructure::scheduler::{self, Schedule, Triggers};
+use crate::infrastructure::shutdown;
+use crate::services;
+use crate::services::lock::with_lock;
+use crate::services::report::CycleReport;
+use crate::services::retention::RetentionPolicy;
+use crate::{
+    fabric, forge, minecraft, neoforge, quilt, CLIENT, FABRIC_BREAKER, FORGE_BREAKER,
+    MINECRAFT_BREAKER, NEOFORGE_BREAKER, QUILT_BREAKER,
+};
+use crate::infrastructure::error::Error;
+use clap::{Args, ValueEnum};
+use daedalus::minecraft::VersionManifest;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{watch, Mutex, Semaphore};
+use tracing::{error, info, warn, Instrument};
+
+#[derive(Debug, Default, Args)]
+pub struct RunArgs {
+    /// Seconds between processing cycles (default: `daemon.interval_secs`)
+    #[arg(long)]
+    pub interval: Option<u64>,
+}
+
+#[derive(Debug, Args)]
+pub struct OnceArgs {
+    #[command(flatten)]
+    pub dry_run: DryRunArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct OnlyArgs {
+    /// Loaders to process (minecraft is always processed, every other loader
+    /// depends on its version list)
+    #[arg(required = true, value_enum)]
+    pub loaders: Vec<Loader>,
+    #[command(flatten)]
+    pub dry_run: DryRunArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct DryRunArgs {
+    /// Do not touch the bucket or the CDN, write the output to `--out`
+    #[arg(long, requires = "out")]
+    pub dry_run: bool,
+    /// Directory the dry run writes every object and manifest to
+    #[arg(long, value_name = "DIR", requires = "dry_run")]
+    pub out: Option<PathBuf>,
+}
+
+impl DryRunArgs {
+    /// Enable dry-run mode if requested
+    fn apply(self) -> Result<(), Error> {
+        match self.out {
+            Some(out) => services::dry_run::enable(out),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Serializes root manifest commits of concurrently running cycles
+static COMMIT_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// Root manifest commits attempted before a cycle gives up on conflicts
+const MAX_COMMIT_ATTEMPTS: u32 = 5;
+
+/// A loader that can be selected for processing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Loader {
+    Minecraft,
+    Forge,
+    Fabric,
+    Quilt,
+    #[value(name = "neoforge")]
+    NeoForge,
+}
+
+impl Loader {
+    /// Name used in manifests, config sections and triggers
+    pub fn name(self) -> &'static str {
+        match self {
+            Loader::Minecraft => "minecraft",
+            Loader::Forge => "forge",
+            Loader::Fabric => "fabric",
+            Loader::Quilt => "quilt",
+            Loader::NeoForge => "neoforge",
+        }
+    }
+
+    /// Circuit breaker guarding the upstream of this loader
+    fn breaker(self) -> &'static CircuitBreaker {
+        match self {
+            Loader::Minecraft => &MINECRAFT_BREAKER,
+            Loader::Forge => &FORGE_BREAKER,
+            Loader::Fabric => &FABRIC_BREAKER,
+            Loader::Quilt => &QUILT_BREAKER,
+            Loader::NeoForge => &NEOFORGE_BREAKER,
+        }
+    }
+}
+
+/// Loaders processed by a cycle
+#[derive(Debug, Clone)]
+pub struct LoaderSelection {
+    loaders: Option<BTreeSet<Loader>>,
+}
+
+impl LoaderSelection {
+    /// Every loader enabled at compile time
+    pub fn all() -> Self {
+        Self { loaders: None }
+    }
+
+    pub fn only(loaders: impl IntoIterator<Item = Loader>) -> Self {
+        Self {
+            loaders: Some(loaders.into_iter().collect()),
+        }
+    }
+
+    /// Whether `loader` was selected
+    ///
+    /// Minecraft is processed whenever no version list is available yet,
+    /// even if it was not selected (see [`cycle`]).
+    pub fn includes(&self, loader: Loader) -> bool {
+        self.loaders
+            .as_ref()
+            .is_none_or(|loaders| loaders.contains(&loader))
+    }
+}
+
+/// Run the update daemon until a shutdown signal is received
+///
+/// Every loader runs on its own schedule and commits its manifest into the
+/// root manifest independently. Loaders reuse the Minecraft version list of
+/// the latest Minecraft run, so they only start once it has succeeded. The
+/// run lock is held for the lifetime of the daemon, the HTTP routes are
+/// served while waiting for it so a standby instance can be probed.
+pub async fn run(args: RunArgs, config: &Config, semaphore: Arc<Semaphore>) -> Result<(), Error> {
+    let default_interval = args.interval.unwrap_or(config.daemon.interval_secs).max(1);
+    let jobs = scheduled_loaders(config, default_interval)?;
+    let triggers = Arc::new(Triggers::new(jobs.iter().map(|(loader, _)| loader.name())));
+    // Commits are only overdue once the slowest loader had its turn
+    let longest_interval = jobs
+        .iter()
+        .filter_map(|(_, schedule)| schedule.longest_gap(chrono::Utc::now()))
+        .max()
+        .unwrap_or(Duration::from_secs(default_interval));
+
+    let http = async {
+        if let Some(addr) = config.http.listen {
+            let admin = config.http.admin_token.clone().map(|token| {
+                let schedules = jobs
+                    .iter()
+                    .map(|(loader, schedule)| (loader.name(), schedule.to_string()))
+                    .collect();
+                Admin::new(token, Arc::clone(&triggers), schedules, config.loaders.clone())
+            });
+            http::serve(addr, Readiness::new(&config.http, longest_interval), admin).await;
+        }
+        std::future::pending::<()>().await
+    };
+
+    let locked = async {
+        health::record_lock_wait();
+        with_lock(&CLIENT, &config.lock, async {
+            health::record_lock_acquired();
+            daemon(config, &jobs, &triggers, semaphore).await
+        })
+        .await
+    };
+
+    tokio::select! {
+        result = locked => result,
+        _ = http => Ok(()),
+    }
+}
+
+async fn daemon(
+    config: &Config,
+    jobs: &[(Loader, Schedule)],
+    triggers: &Triggers,
+    semaphore: Arc<Semaphore>,
+) -> Result<(), Error> {
+    {
+        let uploaded_files = Arc::new(Mutex::new(Vec::new()));
+
+        match crate::upload_static_files(&config.cdn_upload_dir, &uploaded_files, semaphore.clone())
+            .await
+        {
+            Ok(()) => {}
+            Err(err) => {
+                error!("{:?}", err);
+            }
+        }
+    }
+
+    let retention_policy = RetentionPolicy::from_config(&config.retention);
+    let (minecraft_tx, minecraft_rx) = watch::channel(None);
+
+    let listeners = async {
+        match &config.daemon.trigger_socket {
+            Some(path) => {
+                tokio::join!(
+                    scheduler::listen_signal(triggers),
+                    scheduler::listen_socket(path, triggers)
+                );
+            }
+            None => scheduler::listen_signal(triggers).await,
+        }
+        std::future::pending::<()>().await
+    };
+
+    let loops = futures::future::join_all(jobs.iter().map(|(loader, schedule)| {
+        schedule_loop(
+            *loader,
+            schedule,
+            config,
+            retention_policy.as_ref(),
+            triggers,
+            &minecraft_tx,
+            minecraft_rx.clone(),
+            semaphore.clone(),
+        )
+    }));
+
+    // Running cycles drain before the loops exit
+    tokio::select! {
+        _ = loops => {}
+        _ = listeners => {}
+    }
+
+    info!("Application shutdown complete");
+    Ok(())
+}
+
+/// Loaders run by the daemon with their schedules
+fn scheduled_loaders(config: &Config, default_interval: u64) -> Result<Vec<(Loader, Schedule)>, Error> {
+    let loaders = &config.loaders;
+    let candidates = [
+        (Loader::Minecraft, true, loaders.minecraft.schedule(default_interval)),
+        (Loader::Fabric, cfg!(feature = "fabric") && loaders.fabric.enabled, loaders.fabric.schedule(default_interval)),
+        (Loader::Forge, cfg!(feature = "forge") && loaders.forge.enabled, loaders.forge.schedule(default_interval)),
+        (Loader::Quilt, cfg!(feature = "quilt") && loaders.quilt.enabled, loaders.quilt.schedule(default_interval)),
+        (Loader::NeoForge, cfg!(feature = "neoforge") && loaders.neoforge.enabled, loaders.neoforge.schedule(default_interval)),
+    ];
+
+    let mut jobs = Vec::new();
+    for (loader, enabled, schedule) in candidates {
+        if enabled {
+            let schedule = schedule.map_err(crate::infrastructure::error::ErrorKind::Config)?;
+            info!(loader = loader.name(), schedule = %schedule, "Scheduled loader");
+            jobs.push((loader, schedule));
+        }
+    }
+    Ok(jobs)
+}
+
+/// Run one loader on its schedule, or earlier when triggered
+#[allow(clippy::too_many_arguments)]
+async fn schedule_loop(
+    loader: Loader,
+    schedule: &Schedule,
+    config: &Config,
+    retention_policy: Option<&RetentionPolicy>,
+    triggers: &Triggers,
+    minecraft_tx: &watch::Sender<Option<Arc<VersionManifest>>>,
+    mut minecraft_rx: watch::Receiver<Option<Arc<VersionManifest>>>,
+    semaphore: Arc<Semaphore>,
+) {
+    let selection = LoaderSelection::only([loader]);
+    let mut is_first_run = true;
+
+    if loader != Loader::Minecraft {
+        info!(loader = loader.name(), "Waiting for the Minecraft version list");
+        tokio::select! {
+            ready = minecraft_rx.wait_for(Option::is_some) => if ready.is_err() { return },
+            _ = shutdown::requested() => return,
+        }
+    }
+
+    let mut triggered = false;
+
+    loop {
+        let started = chrono::Utc::now();
+        // Paused loaders still run when triggered
+        if triggers.is_paused(loader.name()) && !triggered {
+            info!(loader = loader.name(), "⏸️  Paused, skipping the scheduled run");
+        } else {
+            let minecraft = minecraft_rx.borrow().clone();
+            let versions = cycle(
+                &selection,
+                config,
+                retention_policy,
+                minecraft,
+                is_first_run,
+                semaphore.clone(),
+            )
+            .await;
+            is_first_run = false;
+
+            if loader == Loader::Minecraft && versions.is_some() {
+                minecraft_tx.send_replace(versions);
+            }
+        }
+
+        let delay = schedule.delay(started, chrono::Utc::now());
+        info!(loader = loader.name(), next_run_secs = delay.as_secs(), "Waiting for next scheduled run or trigger");
+        triggered = tokio::select! {
+            _ = tokio::time::sleep(delay) => false,
+            _ = triggers.triggered(loader.name()) => {
+                info!(loader = loader.name(), "⚡ Triggered, running now");
+                true
+            }
+            _ = shutdown::requested() => return,
+        };
+    }
+}
+
+/// Run a single cycle for all loaders
+pub async fn once(args: OnceArgs, config: &Config, semaphore: Arc<Semaphore>) -> Result<(), Error> {
+    args.dry_run.apply()?;
+
+    let retention_policy = RetentionPolicy::from_config(&config.retention);
+    with_lock(&CLIENT, &config.lock, async {
+        cycle(&LoaderSelection::all(), config, retention_policy.as_ref(), None, true, semaphore).await;
+        Ok(())
+    })
+    .await?;
+    shutdown::check()?;
+
+    info!("Single cycle finished");
+    Ok(())
+}
+
+/// Run a single cycle for the selected loaders
+///
+/// The root manifest keeps the published manifests of the other loaders.
+pub async fn only(args: OnlyArgs, config: &Config, semaphore: Arc<Semaphore>) -> Result<(), Error> {
+    args.dry_run.apply()?;
+
+    let retention_policy = RetentionPolicy::from_config(&config.retention);
+    let selection = LoaderSelection::only(args.loaders);
+    with_lock(&CLIENT, &config.lock, async {
+        cycle(&selection, config, retention_policy.as_ref(), None, true, semaphore).await;
+        Ok(())
+    })
+    .await?;
+    shutdown::check()?;
+
+    info!("Single cycle finished");
+    Ok(())
+}
+
+/// One processing cycle
+///
+/// Loaders disabled in the config are skipped even when selected. Minecraft
+/// is processed when selected or when no `minecraft` version list is passed
+/// in; otherwise the given list is used for the other loaders.
+///
+/// Returns the Minecraft version list the loaders were processed against.
+pub async fn cycle(
+    selection: &LoaderSelection,
+    config: &Config,
+    retention_policy: Option<&RetentionPolicy>,
+    minecraft: Option<Arc<VersionManifest>>,
+    is_first_run: bool,
+    semaphore: Arc<Semaphore>,
+) -> Option<Arc<VersionManifest>> {
+    let mut report = CycleReport::start();
+    let loop_span = tracing::info_span!("processing_cycle", cycle_id = %report.id, is_first_run);
+    let byte_counters = report.byte_counters();
+    let cycle = async {
+        let uploader = services::upload::BatchUploader::new(&config.upload);
+        let manifest_builder = services::cas::ManifestBuilder::new();
+
+        let previous = match services::previous_state::PreviousState::load(&CLIENT).await {
+            Ok(previous) => previous,
+            Err(err) => {
+                warn!(error = %err, "Failed to load previous state, reprocessing everything");
+                services::previous_state::PreviousState::empty()
+            }
+        };
+        if let Err(err) = services::quarantine::refresh(&CLIENT).await {
+            warn!(error = %err, "Failed to load the quarantine, retrying next cycle");
+        }
+
+        let versions = match minecraft.filter(|_| !selection.includes(Loader::Minecraft)) {
+            Some(versions) => Some(versions),
+            None => run_loader(
+                config,
+                &mut report,
+                Loader::Minecraft,
+                minecraft::retrieve_data(
+                    &uploader,
+                    &manifest_builder,
+                    config,
+                    &CLIENT,
+                    semaphore.clone(),
+                    &previous,
+                    is_first_run,
+                ),
+            )
+            .await
+            .map(|res| {
+                info!(version_count = res.versions.len(), "Minecraft data retrieved");
+                Arc::new(res)
+            }),
+        };
+
+        // Timestamp of the root manifest once committed
+        let mut committed = None;
+
+        if let Some(manifest) = &versions {
+            if cfg!(feature = "fabric") && selection.includes(Loader::Fabric) && config.loaders.fabric.enabled {
+                run_loader(
+                    config,
+                    &mut report,
+                    Loader::Fabric,
+                    fabric::retrieve_data(manifest, &uploader, &manifest_builder, config, &CLIENT, semaphore.clone(), &previous),
+                )
+                .await;
+            }
+
+            if cfg!(feature = "forge") && selection.includes(Loader::Forge) && config.loaders.forge.enabled {
+                run_loader(
+                    config,
+                    &mut report,
+                    Loader::Forge,
+                    forge::retrieve_data(manifest, &uploader, &manifest_builder, config, &CLIENT, semaphore.clone(), &previous),
+                )
+                .await;
+            }
+
+            if cfg!(feature = "quilt") && selection.includes(Loader::Quilt) && config.loaders.quilt.enabled {
+                run_loader(
+                    config,
+                    &mut report,
+                    Loader::Quilt,
+                    quilt::retrieve_data(manifest, &uploader, &manifest_builder, config, &CLIENT, semaphore.clone(), &previous),
+                )
+                .await;
+            }
+
+            if cfg!(feature = "neoforge") && selection.includes(Loader::NeoForge) && config.loaders.neoforge.enabled {
+                run_loader(
+                    config,
+                    &mut report,
+                    Loader::NeoForge,
+                    neoforge::retrieve_data(manifest, &uploader, &manifest_builder, config, &CLIENT, semaphore.clone(), &previous),
+                )
+                .await;
+            }
+
+            // Committing now would publish loaders that stopped halfway, the
+            // objects uploaded so far are picked up by the next cycle
+            if shutdown::is_requested() {
+                warn!("Shutdown requested during the cycle, skipping the commit of the incomplete cycle");
+                save_quarantine().await;
+                report.finish(None, semaphore.clone()).await;
+                return versions;
+            }
+
+            // All CAS objects have been uploaded immediately during processing
+            // Now we upload the loader manifests and root manifest atomically
+            let mut loader_references = std::collections::HashMap::new();
+            // Loader manifests are written to fresh timestamped paths and never
+            // cached before, so only the root manifest needs a purge
+            let mut uploaded_manifest_urls = Vec::new();
+
+            // Expose the rehosted libraries as a maven repository
+            match services::maven::publish(&CLIENT, uploader.maven_artifacts(), semaphore.clone()).await {
+                Ok(urls) => uploaded_manifest_urls.extend(urls),
+                Err(e) => warn!(error = %e, "Failed to publish maven repository (non-fatal)"),
+            }
+            let mut built_manifests = std::collections::HashMap::new();
+
+            let all_loaders = manifest_builder.get_loaders();
+            info!(loader_count = all_loaders.len(), "Building loader manifests");
+
+            for loader in &all_loaders {
+                if let Some(loader_manifest) = manifest_builder.build_loader_manifest(loader) {
+                    let manifest_path = format!("v{}/manifests/{}/{}.json", crate::services::cas::CAS_VERSION, loader, loader_manifest.timestamp);
+
+                    info!(
+                        loader = %loader,
+                        version_count = loader_manifest.versions.as_array().map(|a| a.len()).unwrap_or(0),
+                        path = %manifest_path,
+                        "Uploading loader manifest"
+                    );
+
+                    match crate::common::to_canonical_vec(&loader_manifest) {
+                        Ok(manifest_bytes) => {
+                            match crate::upload_file_to_bucket(
+                                manifest_path.clone(),
+                                manifest_bytes,
+                                Some("application/json".to_string()),
+                                &tokio::sync::Mutex::new(Vec::new()),
+                                semaphore.clone(),
+                            ).await {
+                                Ok(_) => {
+                                    info!(loader = %loader, "Loader manifest uploaded successfully");
+                                    loader_references.insert(
+                                        loader.clone(),
+                                        services::cas::LoaderReference::new(loader, loader_manifest.timestamp.clone())
+                                    );
+                                    built_manifests.insert(loader.clone(), loader_manifest);
+                                }
+                                Err(e) => {
+                                    error!(loader = %loader, error = %e, "Failed to upload loader manifest");
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            error!(loader = %loader, error = %e, "Failed to serialize loader manifest");
+                        }
+                    }
+                }
+            }
+
+            if !loader_references.is_empty() {
+                // Scheduled loaders commit one at a time. Every commit is
+                // conditional on the root it was composed on, a conflict
+                // means it moved and the commit is redone on top of it
+                let commit_guard = COMMIT_LOCK.lock().await;
+                // Taken under the lock so commits are named in the order they happen
+                let timestamp = services::history::commit_timestamp();
+                let mut reloaded = None;
+                let mut attempt = 1;
+                // Sent once the commit lock is released
+                let mut new_versions = None;
+                // Published once the root pointing at them is committed
+                let mut committed_indexes = None;
+
+                loop {
+                    let previous = reloaded.as_ref().unwrap_or(&previous);
+
+                    // Loaders without a new manifest (not selected, disabled
+                    // or failed) keep their published one
+                    let mut root_manifest = match previous.root() {
+                        Some(published) => services::history::compose_root(published, loader_references.clone()),
+                        None => services::cas::RootManifest::new(loader_references.clone()),
+                    };
+
+                    // Derive the per-game-version indexes from the manifests the new
+                    // root points at
+                    let live_manifests: std::collections::HashMap<_, _> = root_manifest
+                        .loaders
+                        .keys()
+                        .filter_map(|loader| {
+                            built_manifests
+                                .get(loader)
+                                .or_else(|| previous.loader_manifest(loader))
+                                .map(|manifest| (loader.clone(), manifest))
+                        })
+                        .collect();
+                    let previous_indexes = match previous.root().and_then(|root| root.indexes.as_ref()) {
+                        Some(_) => {
+                            let manifests = root_manifest
+                                .loaders
+                                .keys()
+                                .chain(previous.root().map(|root| root.loaders.keys()).into_iter().flatten())
+                                .filter_map(|loader| previous.loader_manifest(loader).map(|manifest| (loader.clone(), manifest)))
+                                .collect();
+                            services::index::IndexSet::build(&manifests)
+                        }
+                        None => Ok(services::index::IndexSet::default()),
+                    };
+                    let indexes = services::index::IndexSet::build(&live_manifests);
+
+                    let indexes = match (indexes, previous_indexes) {
+                        (Ok(indexes), Ok(previous_indexes)) => {
+                            root_manifest.indexes = Some(services::index::index_reference());
+                            Some((indexes, previous_indexes))
+                        }
+                        (Err(e), _) | (_, Err(e)) => {
+                            warn!(error = %e, "Failed to build indexes (non-fatal)");
+                            None
+                        }
+                    };
+
+                    info!(attempt, "Uploading root manifest (atomic commit point)");
+
+                    match services::history::publish_root_manifest(
+                        &root_manifest,
+                        &timestamp,
+                        &previous.root_condition(),
+                        &config.upload,
+                        semaphore.clone(),
+                    ).await {
+                        Ok(urls) => {
+                            info!("Root manifest uploaded successfully - all changes are now live");
+                            uploaded_manifest_urls.extend(urls);
+                            if !services::dry_run::is_enabled() {
+                                metrics::LAST_COMMIT.set(chrono::Utc::now().timestamp());
+                                health::record_commit();
+                            }
+                            committed = Some(timestamp.clone());
+                            committed_indexes = indexes;
+
+                            let changelog = services::changelog::Changelog::between(&timestamp, previous, &built_manifests);
+                            if services::dry_run::is_enabled() {
+                                services::dry_run::report(previous.root(), &root_manifest, &changelog);
+                            }
+                            match services::changelog::publish(&CLIENT, &changelog, semaphore.clone()).await {
+                                Ok(urls) => uploaded_manifest_urls.extend(urls),
+                                Err(e) => warn!(error = %e, "Failed to publish changelog (non-fatal)"),
+                            }
+                            new_versions = services::webhooks::Event::new_versions(&changelog);
+
+                            if let Some(policy) = retention_policy.filter(|_| !services::dry_run::is_enabled()) {
+                                if let Err(e) = services::retention::enforce(&CLIENT, policy, &root_manifest).await {
+                                    warn!(error = %e, "Failed to apply retention policy (non-fatal)");
+                                }
+                            }
+                        }
+                        Err(crate::infrastructure::error::ErrorKind::Conflict { .. }) if attempt < MAX_COMMIT_ATTEMPTS => {
+                            warn!(attempt, "Root manifest changed since it was read, committing on top of the new one");
+                            attempt += 1;
+
+                            match services::previous_state::PreviousState::load(&CLIENT).await {
+                                Ok(live) => {
+                                    reloaded = Some(live);
+                                    continue;
+                                }
+                                Err(e) => {
+                                    error!(error = %e, "Failed to reload the live root manifest - changes NOT committed");
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            error!(error = %e, "Failed to upload root manifest - changes NOT committed");
+                        }
+                    }
+
+                    break;
+                }
+
+                // Indexes live at fixed paths, so they are only rewritten for
+                // a committed root, and before a later commit can get in
+                if let Some((indexes, previous_indexes)) = committed_indexes {
+                    match services::index::publish(&CLIENT, &indexes, &previous_indexes, semaphore.clone()).await {
+                        Ok(urls) => uploaded_manifest_urls.extend(urls),
+                        Err(e) => warn!(error = %e, "Failed to publish indexes (non-fatal)"),
+                    }
+                }
+
+                drop(commit_guard);
+                info!("Processing cycle completed successfully");
+
+                services::cloudflare::purge_if_enabled(config, &uploaded_manifest_urls).await;
+                if let Some(event) = new_versions {
+                    services::webhooks::notify(config, event).await;
+                }
+            } else {
+                warn!("No loader manifests were built - skipping root manifest upload");
+            }
+        }
+
+        save_quarantine().await;
+        report.finish(committed, semaphore.clone()).await;
+        versions
+    };
+    services::report::count_bytes(byte_counters, cycle.instrument(loop_span)).await
+}
+
+/// Save the quarantine changes of a cycle, a failed save is retried by the
+/// next one
+async fn save_quarantine() {
+    if let Err(err) = services::quarantine::save(&CLIENT).await {
+        warn!(error = %err, "Failed to save the quarantine (non-fatal)");
+    }
+}
+
+/// Run one loader through its circuit breaker and record how it ended
+///
+/// Returns the result of the loader, or `None` when it failed or its breaker
+/// is open.
+async fn run_loader<T, E>(
+    config: &Config,
+    report: &mut CycleReport,
+    loader: Loader,
+    run: impl Future<Output = Result<T, E>>,
+) -> Option<T>
+where
+    E: Into<Error>,
+{
+    let name = loader.name();
+    let started = Instant::now();
+
+    async {
+        match loader.breaker().call(run).await {
+            Ok(value) => {
+                info!(loader = name, "Loader processing completed");
+                record_run(config, report, name, started, Outcome::Success, None).await;
+                Some(value)
+            }
+            Err(CircuitBreakerError::Open) => {
+                warn!(loader = name, "Circuit breaker is open, skipping");
+                record_run(config, report, name, started, Outcome::Skipped, None).await;
+                None
+            }
+            Err(CircuitBreakerError::Failed(err)) if err.is_cancelled() => {
+                warn!(loader = name, "Loader processing interrupted by shutdown");
+                record_run(config, report, name, started, Outcome::Cancelled, None).await;
+                None
+            }
+            Err(CircuitBreakerError::Failed(err)) => {
+                error!(loader = name, error = %err, "Loader processing failed");
+                record_run(config, report, name, started, Outcome::Failure, Some(&err)).await;
+                None
+            }
+            Err(CircuitBreakerError::Opened { error: err, failures }) => {
+                error!(loader = name, error = %err, "Loader processing failed");
+                record_run(config, report, name, started, Outcome::Failure, Some(&err)).await;
+                services::webhooks::notify(config, services::webhooks::Event::BreakerOpen {
+                    breaker: name.to_string(),
+                    failures,
+                    error: err.to_string(),
+                })
+                .await;
+                None
+            }
+        }
+    }
+    .instrument(tracing::info_span!("loader_processing", loader = name))
+    .await
+}
+
+/// Record how a loader run ended in the metrics, the health state and the
+/// cycle report, notifying the webhooks once a loader keeps failing
+async fn record_run(config: &Config, report: &mut CycleReport, loader: &str, started: Instant, outcome: Outcome, error: Option<&Error>) {
+    metrics::record_loader_run(loader, started, outcome);
+    report.record_loader(loader, started, outcome, error.map(ToString::to_string));
+    match error {
+        Some(err) => {
+            let failures = health::record_failure(loader, err);
+            if failures == config.notifications.failure_threshold {
+                services::webhooks::notify(config, services::webhooks::Event::LoaderFailing {
+                    loader: loader.to_string(),
+                    consecutive_failures: failures,
+                    error: err.to_string(),
+                })
+                .await;
+            }
+        }
+        None if outcome == Outcome::Success => health::record_success(loader),
+        None => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_loader_selection() {
+        let all = LoaderSelection::all();
+        assert!(all.includes(Loader::NeoForge));
+
+        let only = LoaderSelection::only([Loader::Forge]);
+        assert!(only.includes(Loader::Forge));
+        // Minecraft only runs unselected when no version list is available
+        assert!(!only.includes(Loader::Minecraft));
+        assert!(!only.includes(Loader::Fabric));
+        assert!(LoaderSelection::only([Loader::Minecraft]).includes(Loader::Minecraft));
+    }
+}
@@ -0,0 +1,49 @@
+//! Ask a running daemon to run a loader now
+//!
+//! Talks to the daemon's trigger socket (`daemon.trigger_socket`), see
+//! `infrastructure::scheduler`.
+
+use crate::infrastructure::config::Config;
+use crate::infrastructure::error::{invalid_input, Error};
+use clap::Args;
+use std::path::PathBuf;
+
+#[derive(Debug, Args)]
+pub struct TriggerArgs {
+    /// Loader to run (`minecraft`, `forge`, `fabric`, `quilt`, `neoforge`) or `all`
+    pub loader: String,
+    /// Trigger socket of the daemon (default: `daemon.trigger_socket`)
+    #[arg(long, value_name = "PATH")]
+    pub socket: Option<PathBuf>,
+}
+
+/// Run the trigger subcommand
+#[cfg(unix)]
+pub async fn run(args: TriggerArgs, config: &Config) -> Result<(), Error> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let socket = args
+        .socket
+        .or_else(|| config.daemon.trigger_socket.clone())
+        .ok_or_else(|| invalid_input("No trigger socket configured, set `daemon.trigger_socket` or pass --socket"))?;
+
+    let mut stream = tokio::net::UnixStream::connect(&socket).await?;
+    stream.write_all(format!("{}\n", args.loader).as_bytes()).await?;
+    stream.shutdown().await?;
+
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply).await?;
+    let reply = reply.trim();
+
+    if reply == "ok" {
+        println!("Triggered {}", args.loader);
+        Ok(())
+    } else {
+        Err(invalid_input(reply.trim_start_matches("error: ").to_string()))
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn run(_args: TriggerArgs, _config: &Config) -> Result<(), Error> {
+    Err(invalid_input("Trigger sockets are only supported on Unix"))
+}
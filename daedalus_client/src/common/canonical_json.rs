@@ -0,0 +1,189 @@
+//! Canonical JSON serialization for hashed content
+//!
+//! Version JSONs, loader manifests and root manifests are content-addressed, so
+//! the bytes we hash and upload must only depend on the content itself. Several
+//! of the upstream types (`VersionInfo.arguments`, `downloads`, `logging`,
+//! `data`, `Library.version_hashes`, ...) are `HashMap`s whose iteration order
+//! changes between runs, which would otherwise produce a different hash for
+//! identical content.
+//!
+//! The canonical form is:
+//! - object keys sorted lexicographically at every nesting level
+//! - array order preserved (arrays are ordered data)
+//! - compact `serde_json` formatting, or the standard two-space pretty printer
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+/// Recursively sort all object keys of a JSON value
+///
+/// Keys are re-inserted in sorted order, so the result is canonical regardless
+/// of whether `serde_json` is built with `preserve_order` or not.
+pub fn canonicalize(value: Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let sorted: BTreeMap<String, Value> = map
+                .into_iter()
+                .map(|(key, value)| (key, canonicalize(value)))
+                .collect();
+
+            Value::Object(sorted.into_iter().collect::<Map<String, Value>>())
+        }
+        Value::Array(values) => {
+            Value::Array(values.into_iter().map(canonicalize).collect())
+        }
+        other => other,
+    }
+}
+
+/// Serialize a value to canonical compact JSON
+///
+/// Use this for everything that is hashed and uploaded to CAS.
+pub fn to_canonical_vec<T: Serialize + ?Sized>(
+    value: &T,
+) -> Result<Vec<u8>, crate::infrastructure::error::Error> {
+    let value = canonicalize(serde_json::to_value(value)?);
+    Ok(serde_json::to_vec(&value)?)
+}
+
+/// Serialize a value to canonical pretty-printed JSON
+///
+/// Same key ordering as [`to_canonical_vec`], for human-inspected files such
+/// as loader and root manifests.
+pub fn to_canonical_vec_pretty<T: Serialize + ?Sized>(
+    value: &T,
+) -> Result<Vec<u8>, crate::infrastructure::error::Error> {
+    let value = canonicalize(serde_json::to_value(value)?);
+    Ok(serde_json::to_vec_pretty(&value)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::upload::BatchUploader;
+    use daedalus::minecraft::{Argument, ArgumentType, Library, VersionType};
+    use daedalus::modded::{PartialVersionInfo, Processor, SidedDataEntry};
+    use std::collections::HashMap;
+
+    /// Build the same partial version, inserting map entries in the given order
+    fn build_version(reverse: bool) -> PartialVersionInfo {
+        let mut data_keys = vec!["MAPPINGS", "MOJMAPS", "PATCHED", "BINPATCH", "SIDE"];
+        let mut hash_keys = vec!["1.20.1", "1.20.2", "1.20.4", "1.21", "1.21.1"];
+        if reverse {
+            data_keys.reverse();
+            hash_keys.reverse();
+        }
+
+        let mut data = HashMap::new();
+        for key in &data_keys {
+            data.insert(
+                key.to_string(),
+                SidedDataEntry {
+                    client: format!("[{}:client]", key),
+                    server: format!("[{}:server]", key),
+                },
+            );
+        }
+
+        let mut version_hashes = HashMap::new();
+        for key in &hash_keys {
+            version_hashes.insert(key.to_string(), format!("hash-{}", key));
+        }
+
+        let mut arguments = HashMap::new();
+        let mut argument_types = vec![ArgumentType::Game, ArgumentType::Jvm];
+        if reverse {
+            argument_types.reverse();
+        }
+        for argument_type in argument_types {
+            arguments.insert(
+                argument_type,
+                vec![Argument::Normal("--flag".to_string())],
+            );
+        }
+
+        let mut outputs = HashMap::new();
+        for key in &data_keys {
+            outputs.insert(format!("{{{}}}", key), format!("'{}'", key));
+        }
+
+        PartialVersionInfo {
+            id: "1.20.1-47.1.0".to_string(),
+            inherits_from: "1.20.1".to_string(),
+            release_time: chrono::DateTime::default(),
+            time: chrono::DateTime::default(),
+            main_class: Some("cpw.mods.bootstraplauncher.BootstrapLauncher".to_string()),
+            minecraft_arguments: None,
+            arguments: Some(arguments),
+            libraries: vec![Library {
+                downloads: None,
+                extract: None,
+                name: "net.fabricmc:intermediary:1.20.1".parse().unwrap(),
+                url: None,
+                natives: None,
+                rules: None,
+                checksums: None,
+                include_in_classpath: true,
+                patched: false,
+                version_hashes: Some(version_hashes),
+            }],
+            type_: VersionType::Release,
+            logging: None,
+            data: Some(data),
+            processors: Some(vec![Processor {
+                jar: "net.minecraftforge:installertools:1.3.0".to_string(),
+                classpath: vec![],
+                args: vec!["--task".to_string()],
+                outputs: Some(outputs),
+                sides: None,
+            }]),
+        }
+    }
+
+    #[test]
+    fn test_canonicalize_sorts_nested_keys() {
+        let value = serde_json::json!({
+            "b": { "z": 1, "a": 2 },
+            "a": [ { "y": 1, "x": 2 } ],
+        });
+
+        let bytes = serde_json::to_vec(&canonicalize(value)).unwrap();
+
+        assert_eq!(
+            String::from_utf8(bytes).unwrap(),
+            r#"{"a":[{"x":2,"y":1}],"b":{"a":2,"z":1}}"#
+        );
+    }
+
+    #[test]
+    fn test_reprocessing_yields_identical_bytes() {
+        let first = to_canonical_vec(&build_version(false)).unwrap();
+
+        // Separate HashMaps get separate random states, so their raw
+        // iteration order differs between instances
+        for reverse in [false, true, false, true] {
+            let again = to_canonical_vec(&build_version(reverse)).unwrap();
+            assert_eq!(first, again);
+            assert_eq!(
+                BatchUploader::compute_hash(&first),
+                BatchUploader::compute_hash(&again)
+            );
+        }
+    }
+
+    #[test]
+    fn test_pretty_and_compact_share_key_order() {
+        let version = build_version(true);
+        let compact: Value =
+            serde_json::from_slice(&to_canonical_vec(&version).unwrap()).unwrap();
+        let pretty: Value =
+            serde_json::from_slice(&to_canonical_vec_pretty(&version).unwrap()).unwrap();
+
+        assert_eq!(compact, pretty);
+        assert_eq!(
+            to_canonical_vec_pretty(&version).unwrap(),
+            to_canonical_vec_pretty(&build_version(false)).unwrap()
+        );
+    }
+}
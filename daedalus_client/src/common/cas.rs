@@ -44,6 +44,7 @@ pub fn extract_hash_from_cas_url(url: &str) -> Option<String> {
 ///
 /// # Arguments
 ///
+/// * `base_url` - Public URL of the bucket (`base_url`)
 /// * `hash` - The content hash to build a URL for (must be at least 2 characters)
 ///
 /// # Returns
@@ -54,17 +55,16 @@ pub fn extract_hash_from_cas_url(url: &str) -> Option<String> {
 ///
 /// ```
 /// let hash = "abcdef123456";
-/// let url = build_cas_url(hash)?;
+/// let url = build_cas_url(&config.base_url, hash)?;
 /// // Returns: "{BASE_URL}/v{CAS_VERSION}/objects/ab/cdef123456"
 /// ```
-pub fn build_cas_url(hash: &str) -> Result<String, crate::infrastructure::error::Error> {
+pub fn build_cas_url(base_url: &str, hash: &str) -> Result<String, crate::infrastructure::error::Error> {
     if hash.len() < 2 {
         return Err(crate::infrastructure::error::invalid_input(format!(
             "Hash too short for CAS URL: '{}' (must be at least 2 characters)",
             hash
         )));
     }
-    let base_url = dotenvy::var("BASE_URL").expect("BASE_URL must be set");
     Ok(format!(
         "{}/v{}/objects/{}/{}",
         base_url,
@@ -3,11 +3,13 @@
 //! This module contains shared functionality used by multiple loader implementations
 //! (Forge, NeoForge, etc.) to avoid code duplication.
 
+pub mod canonical_json;
 pub mod cas;
 pub mod change_detection;
 pub mod manifest_merge;
 
 // Re-export commonly used items for convenience
+pub use canonical_json::{to_canonical_vec, to_canonical_vec_pretty};
 pub use cas::{build_cas_url, extract_hash_from_cas_url};
 pub use change_detection::{detect_version_change, ChangeResult};
 pub use manifest_merge::{
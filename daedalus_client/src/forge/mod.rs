@@ -15,6 +15,7 @@ pub use types::{
 use crate::{
     download_file, download_file_mirrors, format_url,
 };
+use crate::infrastructure::config::Config;
 use crate::services::upload::BatchUploader;
 use dashmap::DashSet;
 use daedalus::minecraft::{
@@ -23,8 +24,8 @@ use daedalus::minecraft::{
 use daedalus::modded::{
     LoaderVersion, PartialVersionInfo,
 };
-use daedalus::{get_hash, GradleSpecifier};
-use tracing::{info, warn};
+use daedalus::GradleSpecifier;
+use tracing::{info, warn, Instrument};
 use semver::{Version, VersionReq};
 use std::collections::HashMap;
 use std::convert::{TryInto, TryFrom};
@@ -58,26 +59,16 @@ pub async fn retrieve_data(
     minecraft_versions: &VersionManifest,
     uploader: &BatchUploader,
     manifest_builder: &crate::services::cas::ManifestBuilder,
+    config: &Config,
     s3_client: &s3::Bucket,
     semaphore: Arc<Semaphore>,
+    previous: &crate::services::previous_state::PreviousState,
 ) -> Result<(), crate::infrastructure::error::Error> {
     info!("Retrieving Forge data ...");
 
     let maven_metadata = fetch_maven_metadata(None, semaphore.clone()).await?;
 
-    let old_manifest = daedalus::modded::fetch_manifest(&format_url(&format!(
-        "forge/v{}/manifest.json",
-        crate::services::cas::CAS_VERSION,
-    )))
-    .await
-    .ok();
-
-    let old_versions =
-        Arc::new(Mutex::new(if let Some(old_manifest) = old_manifest {
-            old_manifest.game_versions
-        } else {
-            Vec::new()
-        }));
+    let old_versions = Arc::new(Mutex::new(previous.game_versions("forge")));
 
     let mc_library_cache_mutex =
         Arc::new(Mutex::new(MinecraftVersionLibraryCache::new()));
@@ -140,23 +131,12 @@ pub async fn retrieve_data(
                         let visited_assets = Arc::clone(&visited_assets);
                         let semaphore = Arc::clone(&semaphore);
                         let minecraft_version = minecraft_version.clone();
+                        let span = tracing::info_span!("forge_version", version = %loader_version_full);
+                        let version_id = loader_version_full.clone();
 
-                        async move {
-                            /// These forge versions are not worth supporting!
-                            const FORGE_SKIP_LIST : &[&str] = &[
-                                // Not supported due to `data` field being `[]` even though the type is a map
-                                "1.12.2-14.23.5.2851",
-                                // Malformed Archives
-                                "1.6.1-8.9.0.749",
-                                "1.6.1-8.9.0.751",
-                                "1.6.4-9.11.1.960",
-                                "1.6.4-9.11.1.961",
-                                "1.6.4-9.11.1.963",
-                                "1.6.4-9.11.1.964",
-                            ];
-
-                            if FORGE_SKIP_LIST.contains(&&*loader_version_full) {
-                                info!("⏭️  Forge - Skipping excluded version: {}", loader_version_full);
+                        let result = async move {
+                            if crate::services::quarantine::skips("forge", &loader_version_full) {
+                                info!("⏭️  Forge - Skipping quarantined version: {}", loader_version_full);
                                 return Ok::<Option<LoaderVersion>, crate::infrastructure::error::Error>(None);
                             }
 
@@ -213,7 +193,7 @@ pub async fn retrieve_data(
                                             // Check if we've already processed this artifact (lock-free)
                                             if !visited_assets.insert(lib.name.clone()) {
                                                 // Already processed, skip download
-                                                let base_url = dotenvy::var("BASE_URL").unwrap();
+                                                let base_url = &config.base_url;
                                                 lib.url = Some(format!(
                                                     "{}/v{}/objects/",
                                                     base_url,
@@ -237,15 +217,15 @@ pub async fn retrieve_data(
                                             };
 
                                             // Upload to CAS and get hash
-                                            let hash = uploader.upload_cas(
+                                            let hash = uploader.upload_maven_artifact(
                                                 artifact.to_vec(),
-                                                Some("application/java-archive".to_string()),
+                                                &artifact_path,
                                                 s3_client,
                                                 semaphore.clone(),
                                             ).await?;
 
                                             // Store full CAS URL
-                                            let base_url = dotenvy::var("BASE_URL").unwrap();
+                                            let base_url = &config.base_url;
                                             lib.url = Some(format!(
                                                 "{}/v{}/objects/{}/{}",
                                                 base_url,
@@ -279,8 +259,8 @@ pub async fn retrieve_data(
                                         processors: None
                                     };
 
-                                    let version_bytes = serde_json::to_vec(&new_profile)?;
-                                    let new_hash = get_hash(bytes::Bytes::from(version_bytes.clone())).await?;
+                                    let version_bytes = crate::common::to_canonical_vec(&new_profile)?;
+                                    let new_hash = BatchUploader::compute_hash(&version_bytes);
 
                                     let old_loader_version = {
                                         let versions = versions_mutex.lock().await;
@@ -310,7 +290,7 @@ pub async fn retrieve_data(
                                         new_hash.clone()
                                     };
 
-                                    let base_url = dotenvy::var("BASE_URL").unwrap();
+                                    let base_url = &config.base_url;
                                     let cas_url = format!(
                                         "{}/v{}/objects/{}/{}",
                                         base_url,
@@ -319,6 +299,7 @@ pub async fn retrieve_data(
                                         &version_hash[2..]
                                     );
 
+                                    crate::services::report::record_processed("forge", should_upload);
                                     return Ok(Some(LoaderVersion {
                                         id: loader_version_full,
                                         url: cas_url,
@@ -546,15 +527,15 @@ pub async fn retrieve_data(
 
                                         if let Some(bytes) = artifact_bytes {
                                             // Upload to CAS and get hash
-                                            let hash = uploader.upload_cas(
+                                            let hash = uploader.upload_maven_artifact(
                                                 bytes.to_vec(),
-                                                Some("application/java-archive".to_string()),
+                                                &artifact_path,
                                                 s3_client,
                                                 semaphore.clone(),
                                             ).await?;
 
                                             // Store full CAS URL
-                                            let base_url = dotenvy::var("BASE_URL").unwrap();
+                                            let base_url = &config.base_url;
                                             let cas_url = format!(
                                                 "{}/v{}/objects/{}/{}",
                                                 base_url,
@@ -594,8 +575,8 @@ pub async fn retrieve_data(
                                         processors: Some(profile.processors),
                                     };
 
-                                    let version_bytes = serde_json::to_vec(&new_profile)?;
-                                    let new_hash = get_hash(bytes::Bytes::from(version_bytes.clone())).await?;
+                                    let version_bytes = crate::common::to_canonical_vec(&new_profile)?;
+                                    let new_hash = BatchUploader::compute_hash(&version_bytes);
 
                                     let old_loader_version = {
                                         let versions = versions_mutex.lock().await;
@@ -625,7 +606,7 @@ pub async fn retrieve_data(
                                         new_hash.clone()
                                     };
 
-                                    let base_url = dotenvy::var("BASE_URL").unwrap();
+                                    let base_url = &config.base_url;
                                     let cas_url = format!(
                                         "{}/v{}/objects/{}/{}",
                                         base_url,
@@ -634,6 +615,7 @@ pub async fn retrieve_data(
                                         &version_hash[2..]
                                     );
 
+                                    crate::services::report::record_processed("forge", should_upload);
                                     return Ok(Some(LoaderVersion {
                                         id: loader_version_full,
                                         url: cas_url,
@@ -643,7 +625,17 @@ pub async fn retrieve_data(
                             }
 
                             Ok(None)
-                        }.await
+                        }.instrument(span).await;
+
+                        match &result {
+                            Ok(Some(_)) => crate::services::quarantine::record_success("forge", &version_id),
+                            Ok(None) => {}
+                            Err(err) => {
+                                crate::services::report::record_failure("forge", &version_id, err);
+                                crate::services::quarantine::record_failure(&config.quarantine, "forge", &version_id, err);
+                            }
+                        }
+                        result
                     });
 
                     {
@@ -654,6 +646,9 @@ pub async fn retrieve_data(
                         let mut failed = 0;
 
                         while versions.peek().is_some() {
+                            // Stop starting versions once a shutdown was requested
+                            crate::infrastructure::shutdown::check()?;
+
                             let now = Instant::now();
 
                             let chunk: Vec<_> = versions.by_ref().take(1).collect();
@@ -682,6 +677,7 @@ pub async fn retrieve_data(
                         }
 
                         info!("📊 Forge - Loader processing complete: {} successful, {} failed", successful, failed);
+                        crate::infrastructure::metrics::record_versions("forge", successful, failed);
                     }
                     //futures::future::try_join_all(loaders_futures).await?;
                 }
@@ -705,6 +701,9 @@ pub async fn retrieve_data(
         let mut failed = 0;
 
         while versions.peek().is_some() {
+            // Stop starting versions once a shutdown was requested
+            crate::infrastructure::shutdown::check()?;
+
             let now = Instant::now();
 
             let chunk: Vec<_> = versions.by_ref().take(1).collect();
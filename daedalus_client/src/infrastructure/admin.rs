@@ -0,0 +1,255 @@
+//! Admin API of the daemon, under `/admin` when `http.admin_token` is set
+//!
+//! Every request needs `Authorization: Bearer <http.admin_token>`. Changes
+//! made through it last until the process exits, except those to the
+//! quarantine, which is saved right away.
+//!
+//! | Method   | Route                                      | Action                                   |
+//! |----------|--------------------------------------------|------------------------------------------|
+//! | `GET`    | `/admin/loaders`                           | Loaders with schedule, pause and breaker |
+//! | `POST`   | `/admin/loaders/{loader}/trigger`          | Run the loader now                       |
+//! | `POST`   | `/admin/loaders/{loader}/pause`            | Skip its scheduled runs                  |
+//! | `POST`   | `/admin/loaders/{loader}/resume`           | Resume its scheduled runs                |
+//! | `POST`   | `/admin/loaders/{loader}/breaker/{action}` | `open`, `close` or `reset` its breaker   |
+//! | `GET`    | `/admin/report`                            | Report of the last finished cycle        |
+//! | `GET`    | `/admin/quarantine`                        | Quarantine of every loader               |
+//! | `GET`    | `/admin/quarantine/{loader}`               | Quarantine of a loader                   |
+//! | `PUT`    | `/admin/quarantine/{loader}/{version}`     | Skip a version until it is cleared       |
+//! | `DELETE` | `/admin/quarantine/{loader}/{version}`     | Process a quarantined version again      |
+
+use crate::infrastructure::config::LoadersConfig;
+use crate::infrastructure::error::ErrorKind;
+use crate::infrastructure::health::{self, LoaderHealth};
+use crate::infrastructure::scheduler::Triggers;
+use crate::services::{quarantine, report};
+use axum::extract::{Path, Request, State};
+use axum::http::header::AUTHORIZATION;
+use axum::http::StatusCode;
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post, put};
+use axum::{Json, Router};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// What the admin API operates on
+pub struct Admin {
+    token: String,
+    triggers: Arc<Triggers>,
+    /// Scheduled loaders with a description of their schedule
+    schedules: Vec<(&'static str, String)>,
+    /// Loader sections, every one of them has a quarantine
+    loaders: LoadersConfig,
+}
+
+impl Admin {
+    pub fn new(
+        token: String,
+        triggers: Arc<Triggers>,
+        schedules: Vec<(&'static str, String)>,
+        loaders: LoadersConfig,
+    ) -> Self {
+        Self {
+            token,
+            triggers,
+            schedules,
+            loaders,
+        }
+    }
+}
+
+type Reply = (StatusCode, Json<Value>);
+
+fn ok(value: impl Serialize) -> Reply {
+    (StatusCode::OK, Json(json!(value)))
+}
+
+fn failure(status: StatusCode, message: String) -> Reply {
+    (status, Json(json!({ "error": message })))
+}
+
+fn unknown_loader(loader: &str) -> Reply {
+    failure(StatusCode::NOT_FOUND, format!("`{}` is not a scheduled loader", loader))
+}
+
+pub fn router(admin: Admin) -> Router {
+    let admin = Arc::new(admin);
+
+    Router::new()
+        .route("/loaders", get(loaders))
+        .route("/loaders/:loader/trigger", post(trigger))
+        .route("/loaders/:loader/pause", post(pause))
+        .route("/loaders/:loader/resume", post(resume))
+        .route("/loaders/:loader/breaker/:action", post(breaker))
+        .route("/report", get(last_report))
+        .route("/quarantine", get(all_quarantined))
+        .route("/quarantine/:loader", get(quarantined))
+        .route("/quarantine/:loader/:version", put(skip_version).delete(clear_version))
+        .route_layer(middleware::from_fn_with_state(Arc::clone(&admin), authorize))
+        .with_state(admin)
+}
+
+async fn authorize(State(admin): State<Arc<Admin>>, request: Request, next: Next) -> Response {
+    let token = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match token {
+        Some(token) if constant_time_eq(token.as_bytes(), admin.token.as_bytes()) => next.run(request).await,
+        _ => {
+            warn!(path = %request.uri().path(), "Rejected unauthorized admin request");
+            failure(StatusCode::UNAUTHORIZED, "missing or invalid bearer token".to_string()).into_response()
+        }
+    }
+}
+
+/// Compare without exiting early, so the token cannot be guessed from timings
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[derive(Serialize)]
+struct LoaderStatus {
+    name: &'static str,
+    schedule: String,
+    paused: bool,
+    #[serde(flatten)]
+    health: LoaderHealth,
+}
+
+async fn loaders(State(admin): State<Arc<Admin>>) -> Reply {
+    let mut health = health::loaders();
+    let loaders: Vec<_> = admin
+        .schedules
+        .iter()
+        .map(|(name, schedule)| LoaderStatus {
+            name,
+            schedule: schedule.clone(),
+            paused: admin.triggers.is_paused(name),
+            health: health.remove(*name).unwrap_or_default(),
+        })
+        .collect();
+    ok(loaders)
+}
+
+async fn trigger(State(admin): State<Arc<Admin>>, Path(loader): Path<String>) -> Reply {
+    if !admin.triggers.trigger(&loader) {
+        return unknown_loader(&loader);
+    }
+    info!(loader = %loader, "⚡ Triggered through the admin API");
+    ok(json!({ "triggered": loader }))
+}
+
+async fn pause(State(admin): State<Arc<Admin>>, Path(loader): Path<String>) -> Reply {
+    set_paused(&admin, loader, true)
+}
+
+async fn resume(State(admin): State<Arc<Admin>>, Path(loader): Path<String>) -> Reply {
+    set_paused(&admin, loader, false)
+}
+
+fn set_paused(admin: &Admin, loader: String, paused: bool) -> Reply {
+    if !admin.triggers.set_paused(&loader, paused) {
+        return unknown_loader(&loader);
+    }
+    info!(loader = %loader, paused, "Loader pause changed through the admin API");
+    ok(json!({ "loader": loader, "paused": paused }))
+}
+
+async fn breaker(Path((loader, action)): Path<(String, String)>) -> Reply {
+    let Some(breaker) = crate::loader_breaker(&loader) else {
+        return failure(StatusCode::NOT_FOUND, format!("`{}` has no circuit breaker", loader));
+    };
+
+    match action.as_str() {
+        "open" => breaker.force_open().await,
+        "close" => breaker.force_close().await,
+        "reset" => breaker.reset().await,
+        _ => {
+            return failure(
+                StatusCode::BAD_REQUEST,
+                format!("unknown breaker action `{}`, expected open, close or reset", action),
+            )
+        }
+    }
+
+    let state = health::loaders().remove(&loader).and_then(|health| health.breaker);
+    ok(json!({ "loader": loader, "breaker": state }))
+}
+
+async fn last_report() -> Reply {
+    match report::latest() {
+        Some(report) => ok(report),
+        None => failure(StatusCode::NOT_FOUND, "no cycle finished yet".to_string()),
+    }
+}
+
+async fn all_quarantined() -> Reply {
+    ok(quarantine::entries().loaders)
+}
+
+/// Loader names are matched case-insensitively, `None` for unknown loaders
+fn quarantine_loader(admin: &Admin, loader: &str) -> Option<String> {
+    let loader = loader.to_lowercase();
+    admin.loaders.get(&loader).map(|_| loader)
+}
+
+/// The quarantine of `loader`
+fn quarantine_of(admin: &Admin, loader: &str) -> Reply {
+    let Some(loader) = quarantine_loader(admin, loader) else {
+        return failure(StatusCode::NOT_FOUND, format!("`{}` has no quarantine", loader));
+    };
+    ok(quarantine::entries().loaders.remove(&loader).unwrap_or_default())
+}
+
+async fn quarantined(State(admin): State<Arc<Admin>>, Path(loader): Path<String>) -> Reply {
+    quarantine_of(&admin, &loader)
+}
+
+async fn skip_version(State(admin): State<Arc<Admin>>, Path((loader, version)): Path<(String, String)>) -> Reply {
+    change_quarantine(&admin, loader, version, true).await
+}
+
+async fn clear_version(State(admin): State<Arc<Admin>>, Path((loader, version)): Path<(String, String)>) -> Reply {
+    change_quarantine(&admin, loader, version, false).await
+}
+
+async fn change_quarantine(admin: &Admin, loader: String, version: String, skipped: bool) -> Reply {
+    let Some(loader) = quarantine_loader(admin, &loader) else {
+        return failure(StatusCode::NOT_FOUND, format!("`{}` has no quarantine", loader));
+    };
+
+    if skipped {
+        quarantine::skip(&loader, &version);
+    } else {
+        quarantine::clear(&loader, &version);
+    }
+    info!(loader = %loader, version = %version, skipped, "Quarantine changed through the admin API");
+    if let Err(err) = quarantine::save(&crate::CLIENT).await {
+        warn!(loader = %loader, version = %version, error = %err, "Failed to save the quarantine");
+        let status = match err {
+            ErrorKind::Conflict { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        return failure(status, format!("failed to save the quarantine: {}", err));
+    }
+
+    quarantine_of(admin, &loader)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(!constant_time_eq(b"", b"secret"));
+    }
+}
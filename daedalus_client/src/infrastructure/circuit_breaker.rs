@@ -1,3 +1,4 @@
+use crate::infrastructure::health::BreakerStatus;
 use std::future::Future;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
@@ -13,6 +14,24 @@ enum BreakerState {
     Open { opened_at: Instant },
     /// Circuit is half-open, allowing a test request through
     HalfOpen,
+    /// Opened by an operator, stays open until closed or reset
+    ForcedOpen,
+    /// Closed by an operator, failures are not counted until reset
+    ForcedClosed,
+}
+
+impl BreakerState {
+    fn status(&self) -> BreakerStatus {
+        match self {
+            BreakerState::Closed { .. } | BreakerState::ForcedClosed => BreakerStatus::Closed,
+            BreakerState::HalfOpen => BreakerStatus::HalfOpen,
+            BreakerState::Open { .. } | BreakerState::ForcedOpen => BreakerStatus::Open,
+        }
+    }
+
+    fn is_forced(&self) -> bool {
+        matches!(self, BreakerState::ForcedOpen | BreakerState::ForcedClosed)
+    }
 }
 
 /// Circuit breaker errors
@@ -22,6 +41,12 @@ pub enum CircuitBreakerError {
     Open,
     /// Request failed
     Failed(crate::infrastructure::error::Error),
+    /// Request failed and opened the circuit after `failures` consecutive
+    /// failures
+    Opened {
+        error: crate::infrastructure::error::Error,
+        failures: u32,
+    },
 }
 
 impl std::fmt::Display for CircuitBreakerError {
@@ -29,6 +54,9 @@ impl std::fmt::Display for CircuitBreakerError {
         match self {
             CircuitBreakerError::Open => write!(f, "Circuit breaker is open"),
             CircuitBreakerError::Failed(e) => write!(f, "Request failed: {}", e),
+            CircuitBreakerError::Opened { error, failures } => {
+                write!(f, "Request failed, circuit opened after {} failures: {}", failures, error)
+            }
         }
     }
 }
@@ -59,12 +87,47 @@ impl CircuitBreaker {
     /// * `failure_threshold` - Number of consecutive failures before opening
     /// * `reset_timeout` - Duration to wait before trying again
     pub fn new(name: impl Into<String>, failure_threshold: u32, reset_timeout: Duration) -> Self {
-        Self {
+        let breaker = Self {
             name: name.into(),
             failure_threshold,
             reset_timeout,
             state: Arc::new(Mutex::new(BreakerState::Closed { failures: 0 })),
-        }
+        };
+        breaker.export(&BreakerState::Closed { failures: 0 });
+        breaker
+    }
+
+    /// Publish `state` as this breaker's metric and health
+    fn export(&self, state: &BreakerState) {
+        let status = state.status();
+        crate::infrastructure::metrics::CIRCUIT_BREAKER_STATE
+            .with_label_values(&[&self.name])
+            .set(status as i64);
+        crate::infrastructure::health::record_breaker(&self.name, status, state.is_forced());
+    }
+
+    /// Reject every request until [`CircuitBreaker::force_close`] or
+    /// [`CircuitBreaker::reset`]
+    pub async fn force_open(&self) {
+        self.set(BreakerState::ForcedOpen, "Circuit breaker forced open").await;
+    }
+
+    /// Let every request through, whatever fails, until
+    /// [`CircuitBreaker::reset`]
+    pub async fn force_close(&self) {
+        self.set(BreakerState::ForcedClosed, "Circuit breaker forced closed").await;
+    }
+
+    /// Return to a closed breaker without failures, lifting a forced state
+    pub async fn reset(&self) {
+        self.set(BreakerState::Closed { failures: 0 }, "Circuit breaker reset").await;
+    }
+
+    async fn set(&self, new: BreakerState, message: &str) {
+        let mut state = self.state.lock().await;
+        *state = new;
+        self.export(&state);
+        warn!(breaker = %self.name, "{}", message);
     }
 
     /// Executes a future with circuit breaker protection
@@ -86,6 +149,8 @@ impl CircuitBreaker {
     /// - `Ok(T)` if the operation succeeded
     /// - `Err(CircuitBreakerError::Open)` if the circuit is open
     /// - `Err(CircuitBreakerError::Failed(e))` if the operation failed
+    /// - `Err(CircuitBreakerError::Opened { .. })` if the operation failed and
+    ///   opened the circuit
     pub async fn call<F, T, E>(&self, future: F) -> Result<T, CircuitBreakerError>
     where
         F: Future<Output = Result<T, E>>,
@@ -94,6 +159,10 @@ impl CircuitBreaker {
         // Check current state
         let mut state = self.state.lock().await;
 
+        if let BreakerState::ForcedOpen = *state {
+            return Err(CircuitBreakerError::Open);
+        }
+
         if let BreakerState::Open { opened_at } = *state {
             // Check if reset timeout has elapsed
             if opened_at.elapsed() >= self.reset_timeout {
@@ -102,6 +171,7 @@ impl CircuitBreaker {
                     "Circuit breaker transitioning from open to half-open"
                 );
                 *state = BreakerState::HalfOpen;
+                self.export(&state);
             } else {
                 // Circuit is still open, reject request
                 return Err(CircuitBreakerError::Open);
@@ -129,13 +199,20 @@ impl CircuitBreaker {
                     }
                     _ => {}
                 }
+                self.export(&state);
                 Ok(result)
             }
             Err(error) => {
                 let error = error.into();
 
+                // A run interrupted by a shutdown says nothing about upstream
+                if error.is_cancelled() {
+                    return Err(CircuitBreakerError::Failed(error));
+                }
+
                 // Failure - increment counter or reopen circuit
                 let mut state = self.state.lock().await;
+                let mut opened = None;
                 match *state {
                     BreakerState::HalfOpen => {
                         warn!(
@@ -159,6 +236,7 @@ impl CircuitBreaker {
                             *state = BreakerState::Open {
                                 opened_at: Instant::now(),
                             };
+                            opened = Some(new_failures);
                         } else {
                             *state = BreakerState::Closed {
                                 failures: new_failures,
@@ -167,8 +245,14 @@ impl CircuitBreaker {
                     }
                     _ => {}
                 }
+                self.export(&state);
+                drop(state);
 
-                Err(CircuitBreakerError::Failed(error))
+                // A half-open breaker that fails again is not reported again
+                match opened {
+                    Some(failures) => Err(CircuitBreakerError::Opened { error, failures }),
+                    None => Err(CircuitBreakerError::Failed(error)),
+                }
             }
         }
     }
@@ -198,10 +282,13 @@ mod tests {
     async fn test_circuit_breaker_opens_after_threshold() {
         let breaker = CircuitBreaker::new("test", 3, Duration::from_millis(100));
 
-        // Fail 3 times
-        for _ in 0..3 {
-            let _ = breaker.call(async { Err::<(), _>(crate::infrastructure::error::invalid_input("error")) }).await;
+        // Fail 3 times, the third failure opens the circuit
+        for _ in 0..2 {
+            let result = breaker.call(async { Err::<(), _>(crate::infrastructure::error::invalid_input("error")) }).await;
+            assert!(matches!(result, Err(CircuitBreakerError::Failed(_))));
         }
+        let result = breaker.call(async { Err::<(), _>(crate::infrastructure::error::invalid_input("error")) }).await;
+        assert!(matches!(result, Err(CircuitBreakerError::Opened { failures: 3, .. })));
 
         // Circuit should be open
         assert!(breaker.is_open().await);
@@ -211,6 +298,20 @@ mod tests {
         assert!(matches!(result, Err(CircuitBreakerError::Open)));
     }
 
+    #[tokio::test]
+    async fn test_circuit_breaker_ignores_cancellation() {
+        let breaker = CircuitBreaker::new("test", 2, Duration::from_millis(100));
+
+        for _ in 0..3 {
+            let result = breaker
+                .call(async { Err::<(), _>(crate::infrastructure::error::ErrorKind::Cancelled) })
+                .await;
+            assert!(matches!(result, Err(CircuitBreakerError::Failed(_))));
+        }
+
+        assert!(!breaker.is_open().await);
+    }
+
     #[tokio::test]
     async fn test_circuit_breaker_half_open_after_timeout() {
         let breaker = CircuitBreaker::new("test", 2, Duration::from_millis(50));
@@ -232,4 +333,23 @@ mod tests {
         // Circuit should be closed again
         assert!(!breaker.is_open().await);
     }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_forced_states() {
+        let breaker = CircuitBreaker::new("test", 1, Duration::from_millis(10));
+
+        breaker.force_open().await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        // A forced breaker does not go half-open after the timeout
+        let result = breaker.call(async { Ok::<_, crate::infrastructure::error::Error>(42) }).await;
+        assert!(matches!(result, Err(CircuitBreakerError::Open)));
+
+        breaker.force_close().await;
+        let _ = breaker.call(async { Err::<(), _>(crate::infrastructure::error::invalid_input("error")) }).await;
+        assert!(!breaker.is_open().await);
+
+        breaker.reset().await;
+        let _ = breaker.call(async { Err::<(), _>(crate::infrastructure::error::invalid_input("error")) }).await;
+        assert!(breaker.is_open().await);
+    }
 }
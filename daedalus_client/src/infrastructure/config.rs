@@ -0,0 +1,995 @@
+//! Typed configuration
+//!
+//! Everything the client can be configured with lives in [`Config`]. It is
+//! loaded once at startup from an optional TOML file, then every environment
+//! variable that is set (including `.env`) overrides the matching file value,
+//! and the result is validated before anything else runs. All problems are
+//! reported together with the name of the offending setting.
+//!
+//! The file is taken from `--config`, then `DAEDALUS_CONFIG`, then
+//! `./daedalus.toml` if it exists. Without a file the environment alone
+//! configures the client, as before.
+//!
+//! ```toml
+//! base_url = "https://cdn.example.com"
+//! brand_name = "MyLauncher"
+//! support_email = "support@example.com"
+//!
+//! [s3]
+//! bucket_name = "minecraft-metadata"
+//! region = "r2"
+//! url = "abc123"
+//!
+//! [daemon]
+//! interval_secs = 1800
+//!
+//! [loaders.quilt]
+//! enabled = false
+//! ```
+
+use crate::infrastructure::error::{Error, ErrorKind};
+use crate::infrastructure::scheduler::Schedule;
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// Default config file looked up in the working directory
+pub const DEFAULT_CONFIG_FILE: &str = "daedalus.toml";
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Public CDN URL the published objects are served from (`BASE_URL`)
+    pub base_url: String,
+    /// Brand name written into the metadata (`BRAND_NAME`)
+    pub brand_name: String,
+    /// Support email written into the metadata and the user agent (`SUPPORT_EMAIL`)
+    pub support_email: String,
+    /// Sentry DSN, required when built with the `sentry` feature (`SENTRY_DSN`)
+    pub sentry_dsn: Option<String>,
+    /// Local directory of static files uploaded as-is (`CDN_UPLOAD_DIR`)
+    pub cdn_upload_dir: PathBuf,
+    /// Log output format (`LOG_FORMAT`)
+    pub log_format: LogFormat,
+    pub s3: S3Config,
+    pub cloudflare: CloudflareConfig,
+    pub betterstack: BetterstackConfig,
+    pub otlp: OtlpConfig,
+    pub notifications: NotificationsConfig,
+    pub daemon: DaemonConfig,
+    pub http: HttpConfig,
+    pub upload: UploadConfig,
+    pub circuit_breaker: CircuitBreakerConfig,
+    pub lock: LockConfig,
+    pub retention: RetentionConfig,
+    pub quarantine: QuarantineConfig,
+    pub loaders: LoadersConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            base_url: String::new(),
+            brand_name: String::new(),
+            support_email: String::new(),
+            sentry_dsn: None,
+            cdn_upload_dir: PathBuf::from("./upload_cdn"),
+            log_format: LogFormat::default(),
+            s3: S3Config::default(),
+            cloudflare: CloudflareConfig::default(),
+            betterstack: BetterstackConfig::default(),
+            otlp: OtlpConfig::default(),
+            notifications: NotificationsConfig::default(),
+            daemon: DaemonConfig::default(),
+            http: HttpConfig::default(),
+            upload: UploadConfig::default(),
+            circuit_breaker: CircuitBreakerConfig::default(),
+            lock: LockConfig::default(),
+            retention: RetentionConfig::default(),
+            quarantine: QuarantineConfig::default(),
+            loaders: LoadersConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable output
+    #[default]
+    #[serde(alias = "pretty")]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" | "pretty" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err("expected `text` or `json`".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct S3Config {
+    /// `S3_BUCKET_NAME`
+    pub bucket_name: String,
+    /// `S3_REGION`, `r2` for Cloudflare R2
+    pub region: String,
+    /// `S3_URL`, the endpoint (or the account id for R2)
+    pub url: String,
+    /// `S3_ACCESS_TOKEN`
+    pub access_token: String,
+    /// `S3_SECRET`
+    pub secret: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CloudflareConfig {
+    /// Purge the CDN cache after publishing (`CLOUDFLARE_INTEGRATION`)
+    pub enabled: bool,
+    /// `CLOUDFLARE_TOKEN`
+    pub token: Option<String>,
+    /// `CLOUDFLARE_ZONE_ID`
+    pub zone_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BetterstackConfig {
+    /// Ship logs to Betterstack when set (`BETTERSTACK_TOKEN`)
+    pub token: Option<String>,
+    /// `BETTERSTACK_URL`
+    pub url: String,
+}
+
+impl Default for BetterstackConfig {
+    fn default() -> Self {
+        Self {
+            token: None,
+            url: "https://in.logs.betterstack.com".to_string(),
+        }
+    }
+}
+
+/// See `infrastructure::telemetry` for what is exported
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OtlpConfig {
+    /// Export traces to this OTLP/HTTP collector, e.g. `http://localhost:4318`
+    /// (`OTEL_EXPORTER_OTLP_ENDPOINT`)
+    pub endpoint: Option<String>,
+    /// `OTEL_SERVICE_NAME`
+    pub service_name: String,
+}
+
+impl Default for OtlpConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: None,
+            service_name: "daedalus".to_string(),
+        }
+    }
+}
+
+/// See `services::webhooks` for the events and payloads
+///
+/// ```toml
+/// [[notifications.webhooks]]
+/// url = "https://discord.com/api/webhooks/..."
+/// format = "discord"
+/// events = ["new_versions", "breaker_open"]
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationsConfig {
+    /// Consecutive failed runs of a loader before `loader_failing` is sent
+    /// (`NOTIFY_FAILURE_THRESHOLD`)
+    pub failure_threshold: u32,
+    /// Webhooks every event is sent to. `WEBHOOK_URL`, `WEBHOOK_FORMAT` and
+    /// `WEBHOOK_SECRET` add one more.
+    pub webhooks: Vec<WebhookConfig>,
+}
+
+impl Default for NotificationsConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 3,
+            webhooks: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub url: String,
+    pub format: WebhookFormat,
+    /// Sign the payloads with HMAC-SHA256 under this key
+    pub secret: Option<String>,
+    /// Events sent to this webhook, every event when empty
+    pub events: Vec<WebhookEvent>,
+}
+
+impl WebhookConfig {
+    pub fn wants(&self, event: WebhookEvent) -> bool {
+        self.events.is_empty() || self.events.contains(&event)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    /// The event as JSON
+    #[default]
+    Json,
+    /// A Discord webhook message with an embed
+    Discord,
+    /// A Slack incoming webhook message
+    Slack,
+}
+
+impl FromStr for WebhookFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "discord" => Ok(Self::Discord),
+            "slack" => Ok(Self::Slack),
+            _ => Err("expected `json`, `discord` or `slack`".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// A commit published new Minecraft versions or loader builds
+    NewVersions,
+    /// A loader failed `failure_threshold` runs in a row
+    LoaderFailing,
+    /// A circuit breaker opened
+    BreakerOpen,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    /// Seconds between runs of loaders without their own schedule (`UPDATE_INTERVAL_SECS`)
+    pub interval_secs: u64,
+    /// Maximum number of concurrent uploads and downloads (`MAX_CONCURRENT_UPLOADS`)
+    pub max_concurrent_uploads: usize,
+    /// Unix socket accepting loader names to run immediately (`TRIGGER_SOCKET`)
+    pub trigger_socket: Option<PathBuf>,
+    /// Seconds a running cycle gets to drain after SIGTERM before it is aborted (`SHUTDOWN_TIMEOUT_SECS`)
+    pub shutdown_timeout_secs: u64,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 60 * 60,
+            max_concurrent_uploads: 10,
+            trigger_socket: None,
+            shutdown_timeout_secs: 60,
+        }
+    }
+}
+
+/// See `infrastructure::http` for the routes
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    /// Address the daemon serves its HTTP routes on, disabled when unset (`HTTP_LISTEN`)
+    pub listen: Option<SocketAddr>,
+    /// Bearer token of the admin API, which is disabled when unset
+    /// (`HTTP_ADMIN_TOKEN`)
+    pub admin_token: Option<String>,
+    /// `/readyz` fails when nothing was committed for this many update
+    /// intervals (`HTTP_READY_MAX_INTERVALS`)
+    pub ready_max_intervals: u32,
+    /// `/readyz` fails when a circuit breaker stayed open longer than this
+    /// (`HTTP_READY_MAX_BREAKER_OPEN_SECS`)
+    pub ready_max_breaker_open_secs: u64,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            listen: None,
+            admin_token: None,
+            ready_max_intervals: 3,
+            ready_max_breaker_open_secs: 60 * 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UploadConfig {
+    /// Retry attempts per upload (`MAX_UPLOAD_RETRIES`)
+    pub max_retries: usize,
+    /// Upper bound of the exponential backoff (`MAX_RETRY_DELAY_SECS`)
+    pub max_retry_delay_secs: u64,
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 10,
+            max_retry_delay_secs: 30 * 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive failures before a loader is skipped (`CIRCUIT_BREAKER_FAILURE_THRESHOLD`)
+    pub failure_threshold: u32,
+    /// Seconds before a skipped loader is tried again (`CIRCUIT_BREAKER_RESET_TIMEOUT_SECS`)
+    pub reset_timeout_secs: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            reset_timeout_secs: 5 * 60,
+        }
+    }
+}
+
+/// See `services::lock` for how the lease is taken and renewed
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LockConfig {
+    /// Take the bucket run lock before publishing (`LOCK_ENABLED`)
+    pub enabled: bool,
+    /// Seconds a lease stays valid without being renewed (`LOCK_TTL_SECS`)
+    pub ttl_secs: u64,
+    /// Wait for a lock held by another instance instead of exiting (`LOCK_WAIT`)
+    pub wait: bool,
+    /// Name recorded as the lock owner, the hostname when unset (`LOCK_OWNER`)
+    pub owner: Option<String>,
+}
+
+impl Default for LockConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            ttl_secs: 5 * 60,
+            wait: true,
+            owner: None,
+        }
+    }
+}
+
+/// See `services::retention` for how the windows are applied
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    /// `RETENTION_ENABLED`
+    pub enabled: bool,
+    /// `RETENTION_KEEP_ALL_HOURS`
+    pub keep_all_hours: i64,
+    /// `RETENTION_HOURLY_DAYS`
+    pub hourly_days: i64,
+    /// `RETENTION_DAILY_DAYS`
+    pub daily_days: i64,
+    /// Snapshot ids that are never deleted (`RETENTION_PINNED`, comma separated)
+    pub pinned: Vec<String>,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            keep_all_hours: 48,
+            hourly_days: 14,
+            daily_days: 365,
+            pinned: Vec::new(),
+        }
+    }
+}
+
+/// See `services::quarantine` for how failing versions are retried
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuarantineConfig {
+    /// Skip versions that failed until their next retry (`QUARANTINE_ENABLED`)
+    pub enabled: bool,
+    /// Seconds before the first retry, doubled with every further failure
+    /// (`QUARANTINE_BASE_DELAY_SECS`)
+    pub base_delay_secs: u64,
+    /// Upper bound of the delay between retries (`QUARANTINE_MAX_DELAY_SECS`)
+    pub max_delay_secs: u64,
+}
+
+impl Default for QuarantineConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            base_delay_secs: 60 * 60,
+            max_delay_secs: 7 * 24 * 60 * 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoadersConfig {
+    pub minecraft: MinecraftConfig,
+    pub forge: LoaderConfig,
+    pub fabric: LoaderConfig,
+    pub quilt: LoaderConfig,
+    pub neoforge: LoaderConfig,
+}
+
+impl LoadersConfig {
+    /// Settings of the loader section `name`
+    pub fn get(&self, name: &str) -> Option<&LoaderConfig> {
+        match name {
+            "forge" => Some(&self.forge),
+            "fabric" => Some(&self.fabric),
+            "quilt" => Some(&self.quilt),
+            "neoforge" => Some(&self.neoforge),
+            _ => None,
+        }
+    }
+
+    /// Section names paired with their settings
+    fn iter_mut(&mut self) -> [(&'static str, &mut LoaderConfig); 4] {
+        [
+            ("forge", &mut self.forge),
+            ("fabric", &mut self.fabric),
+            ("quilt", &mut self.quilt),
+            ("neoforge", &mut self.neoforge),
+        ]
+    }
+}
+
+/// Minecraft is always processed, every other loader depends on its version list
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MinecraftConfig {
+    /// Seconds between runs (`MINECRAFT_INTERVAL_SECS`)
+    pub interval_secs: Option<u64>,
+    /// Cron expression with a seconds field, instead of an interval (`MINECRAFT_CRON`)
+    pub cron: Option<String>,
+}
+
+impl MinecraftConfig {
+    pub fn schedule(&self, default_interval: u64) -> Result<Schedule, String> {
+        Schedule::new(self.interval_secs, self.cron.as_deref(), default_interval)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoaderConfig {
+    /// Process this loader (`<LOADER>_ENABLED`)
+    pub enabled: bool,
+    /// Seconds between runs, `daemon.interval_secs` when unset (`<LOADER>_INTERVAL_SECS`)
+    pub interval_secs: Option<u64>,
+    /// Cron expression with a seconds field, instead of an interval (`<LOADER>_CRON`)
+    pub cron: Option<String>,
+}
+
+impl Default for LoaderConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_secs: None,
+            cron: None,
+        }
+    }
+}
+
+impl LoaderConfig {
+    pub fn schedule(&self, default_interval: u64) -> Result<Schedule, String> {
+        Schedule::new(self.interval_secs, self.cron.as_deref(), default_interval)
+    }
+}
+
+impl Config {
+    /// Load, override from the environment and validate
+    ///
+    /// `path` is the `--config` argument. An explicitly requested file that
+    /// does not exist is an error, a missing `daedalus.toml` is not.
+    pub fn load(path: Option<&Path>) -> Result<Self, Error> {
+        let explicit = path
+            .map(Path::to_path_buf)
+            .or_else(|| dotenvy::var("DAEDALUS_CONFIG").ok().map(PathBuf::from));
+
+        let mut config = match explicit {
+            Some(path) => Self::from_file(&path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => Self::default(),
+        };
+
+        let mut errors = config.apply_env(|name| dotenvy::var(name).ok());
+        errors.extend(config.validate());
+
+        if !errors.is_empty() {
+            return Err(ErrorKind::Config(errors.join("\n")));
+        }
+
+        Ok(config)
+    }
+
+    /// `User-Agent` of the requests to webhooks and the Cloudflare API
+    pub fn user_agent(&self) -> String {
+        format!("gdlauncher/daedalus/{} ({})", env!("CARGO_PKG_VERSION"), self.support_email)
+    }
+
+    fn from_file(path: &Path) -> Result<Self, Error> {
+        let contents = std::fs::read_to_string(path).map_err(|err| {
+            ErrorKind::Config(format!("failed to read {}: {}", path.display(), err))
+        })?;
+
+        Self::parse(&contents)
+            .map_err(|err| ErrorKind::Config(format!("{}: {}", path.display(), err)))
+    }
+
+    fn parse(contents: &str) -> Result<Self, String> {
+        toml::from_str(contents).map_err(|err| err.message().to_string())
+    }
+
+    /// Override settings with the environment variables that are set
+    ///
+    /// Returns one message per variable that could not be parsed.
+    fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Vec<String> {
+        let mut errors = Vec::new();
+        let mut env = EnvOverrides {
+            var: &var,
+            errors: &mut errors,
+        };
+
+        env.string("BASE_URL", &mut self.base_url);
+        env.string("BRAND_NAME", &mut self.brand_name);
+        env.string("SUPPORT_EMAIL", &mut self.support_email);
+        env.optional("SENTRY_DSN", &mut self.sentry_dsn);
+        env.parsed("CDN_UPLOAD_DIR", &mut self.cdn_upload_dir);
+        env.parsed("LOG_FORMAT", &mut self.log_format);
+
+        env.string("S3_BUCKET_NAME", &mut self.s3.bucket_name);
+        env.string("S3_REGION", &mut self.s3.region);
+        env.string("S3_URL", &mut self.s3.url);
+        env.string("S3_ACCESS_TOKEN", &mut self.s3.access_token);
+        env.string("S3_SECRET", &mut self.s3.secret);
+
+        env.flag("CLOUDFLARE_INTEGRATION", &mut self.cloudflare.enabled);
+        env.optional("CLOUDFLARE_TOKEN", &mut self.cloudflare.token);
+        env.optional("CLOUDFLARE_ZONE_ID", &mut self.cloudflare.zone_id);
+
+        env.optional("BETTERSTACK_TOKEN", &mut self.betterstack.token);
+        env.string("BETTERSTACK_URL", &mut self.betterstack.url);
+        env.optional("OTEL_EXPORTER_OTLP_ENDPOINT", &mut self.otlp.endpoint);
+        env.string("OTEL_SERVICE_NAME", &mut self.otlp.service_name);
+
+        env.parsed("NOTIFY_FAILURE_THRESHOLD", &mut self.notifications.failure_threshold);
+        let mut webhook_url = None;
+        env.optional("WEBHOOK_URL", &mut webhook_url);
+        if let Some(url) = webhook_url {
+            let mut webhook = WebhookConfig {
+                url,
+                ..WebhookConfig::default()
+            };
+            env.parsed("WEBHOOK_FORMAT", &mut webhook.format);
+            env.optional("WEBHOOK_SECRET", &mut webhook.secret);
+            self.notifications.webhooks.push(webhook);
+        }
+
+        env.parsed("UPDATE_INTERVAL_SECS", &mut self.daemon.interval_secs);
+        env.parsed("MAX_CONCURRENT_UPLOADS", &mut self.daemon.max_concurrent_uploads);
+        env.optional_parsed("TRIGGER_SOCKET", &mut self.daemon.trigger_socket);
+        env.parsed("SHUTDOWN_TIMEOUT_SECS", &mut self.daemon.shutdown_timeout_secs);
+        env.optional_parsed("HTTP_LISTEN", &mut self.http.listen);
+        env.optional("HTTP_ADMIN_TOKEN", &mut self.http.admin_token);
+        env.parsed("HTTP_READY_MAX_INTERVALS", &mut self.http.ready_max_intervals);
+        env.parsed("HTTP_READY_MAX_BREAKER_OPEN_SECS", &mut self.http.ready_max_breaker_open_secs);
+        env.parsed("MAX_UPLOAD_RETRIES", &mut self.upload.max_retries);
+        env.parsed("MAX_RETRY_DELAY_SECS", &mut self.upload.max_retry_delay_secs);
+        env.parsed(
+            "CIRCUIT_BREAKER_FAILURE_THRESHOLD",
+            &mut self.circuit_breaker.failure_threshold,
+        );
+        env.parsed(
+            "CIRCUIT_BREAKER_RESET_TIMEOUT_SECS",
+            &mut self.circuit_breaker.reset_timeout_secs,
+        );
+
+        env.flag("LOCK_ENABLED", &mut self.lock.enabled);
+        env.parsed("LOCK_TTL_SECS", &mut self.lock.ttl_secs);
+        env.flag("LOCK_WAIT", &mut self.lock.wait);
+        env.optional("LOCK_OWNER", &mut self.lock.owner);
+
+        env.flag("RETENTION_ENABLED", &mut self.retention.enabled);
+        env.parsed("RETENTION_KEEP_ALL_HOURS", &mut self.retention.keep_all_hours);
+        env.parsed("RETENTION_HOURLY_DAYS", &mut self.retention.hourly_days);
+        env.parsed("RETENTION_DAILY_DAYS", &mut self.retention.daily_days);
+        env.list("RETENTION_PINNED", &mut self.retention.pinned);
+
+        env.flag("QUARANTINE_ENABLED", &mut self.quarantine.enabled);
+        env.parsed("QUARANTINE_BASE_DELAY_SECS", &mut self.quarantine.base_delay_secs);
+        env.parsed("QUARANTINE_MAX_DELAY_SECS", &mut self.quarantine.max_delay_secs);
+
+        env.optional_parsed("MINECRAFT_INTERVAL_SECS", &mut self.loaders.minecraft.interval_secs);
+        env.optional("MINECRAFT_CRON", &mut self.loaders.minecraft.cron);
+        for (name, loader) in self.loaders.iter_mut() {
+            let prefix = name.to_uppercase();
+            env.flag(&format!("{}_ENABLED", prefix), &mut loader.enabled);
+            env.optional_parsed(&format!("{}_INTERVAL_SECS", prefix), &mut loader.interval_secs);
+            env.optional(&format!("{}_CRON", prefix), &mut loader.cron);
+        }
+
+        errors
+    }
+
+    /// Check required settings and value ranges
+    ///
+    /// Also normalizes `base_url` by dropping a trailing slash.
+    fn validate(&mut self) -> Vec<String> {
+        let mut errors = Vec::new();
+        let mut require = |name: &str, value: &str| {
+            if value.trim().is_empty() {
+                errors.push(format!("`{}` is required", name));
+            }
+        };
+
+        require("base_url", &self.base_url);
+        require("brand_name", &self.brand_name);
+        require("support_email", &self.support_email);
+        require("s3.bucket_name", &self.s3.bucket_name);
+        require("s3.region", &self.s3.region);
+        require("s3.url", &self.s3.url);
+        require("s3.access_token", &self.s3.access_token);
+        require("s3.secret", &self.s3.secret);
+        if cfg!(feature = "sentry") {
+            require("sentry_dsn", self.sentry_dsn.as_deref().unwrap_or_default());
+        }
+
+        self.base_url = self.base_url.trim_end_matches('/').to_string();
+        if !self.base_url.is_empty() && !is_http_url(&self.base_url) {
+            errors.push(format!(
+                "`base_url` must be an http(s) URL, got `{}`",
+                self.base_url
+            ));
+        }
+        if !self.support_email.is_empty() && !self.support_email.contains('@') {
+            errors.push(format!(
+                "`support_email` is not an email address: `{}`",
+                self.support_email
+            ));
+        }
+        if !is_http_url(&self.betterstack.url) {
+            errors.push(format!(
+                "`betterstack.url` must be an http(s) URL, got `{}`",
+                self.betterstack.url
+            ));
+        }
+
+        if let Some(endpoint) = self.otlp.endpoint.as_deref().filter(|url| !is_http_url(url)) {
+            errors.push(format!("`otlp.endpoint` must be an http(s) URL, got `{}`", endpoint));
+        }
+
+        for webhook in self.notifications.webhooks.iter().filter(|webhook| !is_http_url(&webhook.url)) {
+            errors.push(format!(
+                "`notifications.webhooks.url` must be an http(s) URL, got `{}`",
+                webhook.url
+            ));
+        }
+
+        if self.cloudflare.enabled {
+            if self.cloudflare.token.is_none() {
+                errors.push("`cloudflare.token` is required when `cloudflare.enabled` is set".to_string());
+            }
+            if self.cloudflare.zone_id.is_none() {
+                errors.push("`cloudflare.zone_id` is required when `cloudflare.enabled` is set".to_string());
+            }
+        }
+
+        let mut positive = |name: &str, value: u64| {
+            if value == 0 {
+                errors.push(format!("`{}` must be greater than 0", name));
+            }
+        };
+        positive("daemon.interval_secs", self.daemon.interval_secs);
+        positive("daemon.max_concurrent_uploads", self.daemon.max_concurrent_uploads as u64);
+        positive("http.ready_max_intervals", self.http.ready_max_intervals.into());
+        positive("notifications.failure_threshold", self.notifications.failure_threshold.into());
+        positive("upload.max_retry_delay_secs", self.upload.max_retry_delay_secs);
+        positive("circuit_breaker.failure_threshold", self.circuit_breaker.failure_threshold.into());
+        positive("circuit_breaker.reset_timeout_secs", self.circuit_breaker.reset_timeout_secs);
+        positive("quarantine.base_delay_secs", self.quarantine.base_delay_secs);
+        if self.quarantine.max_delay_secs < self.quarantine.base_delay_secs {
+            errors.push(format!(
+                "`quarantine.max_delay_secs` ({}) must not be below `quarantine.base_delay_secs` ({})",
+                self.quarantine.max_delay_secs, self.quarantine.base_delay_secs
+            ));
+        }
+        // Renewals happen every third of the TTL, shorter leases expire between them
+        if self.lock.ttl_secs < 30 {
+            errors.push(format!("`lock.ttl_secs` must be at least 30, got {}", self.lock.ttl_secs));
+        }
+
+        let minecraft = &self.loaders.minecraft;
+        validate_schedule("minecraft", minecraft.interval_secs, minecraft.cron.as_deref(), &mut errors);
+        for (name, loader) in self.loaders.iter_mut() {
+            validate_schedule(name, loader.interval_secs, loader.cron.as_deref(), &mut errors);
+        }
+
+        let retention = &self.retention;
+        if retention.keep_all_hours < 0 || retention.hourly_days < 0 || retention.daily_days < 0 {
+            errors.push("`retention` windows must not be negative".to_string());
+        } else if retention.keep_all_hours > retention.hourly_days * 24
+            || retention.hourly_days > retention.daily_days
+        {
+            errors.push(format!(
+                "`retention` windows must grow: keep_all_hours ({}h) <= hourly_days ({}d) <= daily_days ({}d)",
+                retention.keep_all_hours, retention.hourly_days, retention.daily_days
+            ));
+        }
+
+        errors
+    }
+}
+
+/// Applies environment variables to config fields, collecting parse errors
+struct EnvOverrides<'a, F: Fn(&str) -> Option<String>> {
+    var: &'a F,
+    errors: &'a mut Vec<String>,
+}
+
+impl<F: Fn(&str) -> Option<String>> EnvOverrides<'_, F> {
+    fn string(&mut self, name: &str, target: &mut String) {
+        if let Some(value) = (self.var)(name) {
+            *target = value;
+        }
+    }
+
+    fn optional(&mut self, name: &str, target: &mut Option<String>) {
+        if let Some(value) = (self.var)(name) {
+            *target = Some(value).filter(|v| !v.is_empty());
+        }
+    }
+
+    fn parsed<T: FromStr>(&mut self, name: &str, target: &mut T)
+    where
+        T::Err: std::fmt::Display,
+    {
+        if let Some(value) = (self.var)(name) {
+            match value.trim().parse() {
+                Ok(parsed) => *target = parsed,
+                Err(err) => self
+                    .errors
+                    .push(format!("`{}`: invalid value `{}` ({})", name, value, err)),
+            }
+        }
+    }
+
+    fn optional_parsed<T: FromStr>(&mut self, name: &str, target: &mut Option<T>)
+    where
+        T::Err: std::fmt::Display,
+    {
+        match (self.var)(name) {
+            Some(value) if value.trim().is_empty() => *target = None,
+            Some(value) => match value.trim().parse() {
+                Ok(parsed) => *target = Some(parsed),
+                Err(err) => self
+                    .errors
+                    .push(format!("`{}`: invalid value `{}` ({})", name, value, err)),
+            },
+            None => {}
+        }
+    }
+
+    fn flag(&mut self, name: &str, target: &mut bool) {
+        if let Some(value) = (self.var)(name) {
+            match value.trim().to_ascii_lowercase().as_str() {
+                "true" | "1" => *target = true,
+                "false" | "0" => *target = false,
+                _ => self.errors.push(format!(
+                    "`{}`: invalid value `{}` (expected `true` or `false`)",
+                    name, value
+                )),
+            }
+        }
+    }
+
+    fn list(&mut self, name: &str, target: &mut Vec<String>) {
+        if let Some(value) = (self.var)(name) {
+            *target = value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+    }
+}
+
+/// Check the `interval_secs` and `cron` settings of one `loaders` section
+fn validate_schedule(loader: &str, interval_secs: Option<u64>, cron: Option<&str>, errors: &mut Vec<String>) {
+    if interval_secs.is_some() && cron.is_some() {
+        errors.push(format!(
+            "`loaders.{0}.interval_secs` and `loaders.{0}.cron` are mutually exclusive",
+            loader
+        ));
+    }
+    if interval_secs == Some(0) {
+        errors.push(format!("`loaders.{}.interval_secs` must be greater than 0", loader));
+    }
+    if let Some(Err(err)) = cron.map(Schedule::parse_cron) {
+        errors.push(format!("`loaders.{}.cron`: {}", loader, err));
+    }
+}
+
+fn is_http_url(url: &str) -> bool {
+    url.starts_with("https://") || url.starts_with("http://")
+}
+
+/// Make `config` the process-wide configuration
+pub fn init(config: Config) -> &'static Config {
+    CONFIG.get_or_init(|| config)
+}
+
+/// The process-wide configuration
+///
+/// Only the process-wide helpers that cannot be handed the config explicitly
+/// read it from here: URL formatting, `upload_file_to_bucket`, the S3 client
+/// and the circuit breakers. Everything else is passed the `Config` (or the
+/// section it needs) by its command. Outside of `main`
+/// (unit tests) this falls back to the defaults overridden by the
+/// environment, without validation.
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(|| {
+        let mut config = Config::default();
+        config.apply_env(|name| dotenvy::var(name).ok());
+        config
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    fn complete() -> Config {
+        let mut config = Config::parse(
+            r#"
+            base_url = "https://cdn.example.com/"
+            brand_name = "MyLauncher"
+            support_email = "support@example.com"
+            sentry_dsn = "https://key@sentry.io/1"
+
+            [s3]
+            bucket_name = "meta"
+            region = "r2"
+            url = "abc123"
+            access_token = "token"
+            secret = "secret"
+
+            [loaders.quilt]
+            enabled = false
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_empty());
+        config
+    }
+
+    #[test]
+    fn test_parse_file_with_defaults() {
+        let config = complete();
+
+        assert_eq!(config.base_url, "https://cdn.example.com");
+        assert_eq!(config.daemon.interval_secs, 3600);
+        assert_eq!(config.log_format, LogFormat::Text);
+        assert!(!config.loaders.quilt.enabled);
+        assert!(config.loaders.fabric.enabled);
+
+        let config = Config::parse(
+            r#"
+            [[notifications.webhooks]]
+            url = "https://discord.com/api/webhooks/1/x"
+            format = "discord"
+            events = ["new_versions"]
+            "#,
+        )
+        .unwrap();
+        let webhook = &config.notifications.webhooks[0];
+        assert_eq!(webhook.format, WebhookFormat::Discord);
+        assert!(webhook.wants(WebhookEvent::NewVersions));
+        assert!(!webhook.wants(WebhookEvent::LoaderFailing));
+
+        let err = Config::parse("[daemon]\ninterval = 5").unwrap_err();
+        assert!(err.contains("unknown field `interval`"), "{}", err);
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let mut config = complete();
+        let errors = config.apply_env(env(&[
+            ("UPDATE_INTERVAL_SECS", "600"),
+            ("LOG_FORMAT", "json"),
+            ("QUILT_ENABLED", "true"),
+            ("FABRIC_INTERVAL_SECS", "300"),
+            ("MINECRAFT_CRON", "0 */15 * * * *"),
+            ("RETENTION_PINNED", "2024-01-15T10-30-00Z"),
+            ("CLOUDFLARE_TOKEN", ""),
+            ("HTTP_LISTEN", "127.0.0.1:9090"),
+            ("WEBHOOK_URL", "https://hooks.slack.com/services/T0/B0/x"),
+            ("WEBHOOK_FORMAT", "slack"),
+        ]));
+
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(config.daemon.interval_secs, 600);
+        assert_eq!(config.log_format, LogFormat::Json);
+        assert!(config.loaders.quilt.enabled);
+        assert_eq!(config.loaders.fabric.interval_secs, Some(300));
+        assert_eq!(config.loaders.minecraft.cron.as_deref(), Some("0 */15 * * * *"));
+        assert_eq!(config.retention.pinned, ["2024-01-15T10-30-00Z"]);
+        assert_eq!(config.cloudflare.token, None);
+        assert_eq!(config.http.listen, Some(SocketAddr::from(([127, 0, 0, 1], 9090))));
+        let webhook = &config.notifications.webhooks[0];
+        assert_eq!(webhook.format, WebhookFormat::Slack);
+        assert!(webhook.wants(WebhookEvent::BreakerOpen));
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        let mut config = complete();
+        let mut errors = config.apply_env(env(&[
+            ("MAX_CONCURRENT_UPLOADS", "ten"),
+            ("RETENTION_ENABLED", "maybe"),
+            ("CIRCUIT_BREAKER_FAILURE_THRESHOLD", "0"),
+            ("CLOUDFLARE_INTEGRATION", "true"),
+            ("BASE_URL", "cdn.example.com"),
+            ("S3_SECRET", ""),
+            ("FABRIC_INTERVAL_SECS", "0"),
+            ("FABRIC_CRON", "every minute"),
+        ]));
+        errors.extend(config.validate());
+
+        let expected = [
+            "`MAX_CONCURRENT_UPLOADS`",
+            "`RETENTION_ENABLED`",
+            "`s3.secret` is required",
+            "`base_url` must be an http(s) URL",
+            "`cloudflare.token` is required",
+            "`cloudflare.zone_id` is required",
+            "`circuit_breaker.failure_threshold` must be greater than 0",
+            "`loaders.fabric.interval_secs` and `loaders.fabric.cron` are mutually exclusive",
+            "`loaders.fabric.interval_secs` must be greater than 0",
+            "`loaders.fabric.cron`: invalid cron expression `every minute`",
+        ];
+        assert_eq!(errors.len(), expected.len(), "{:#?}", errors);
+        for (error, expected) in errors.iter().zip(expected) {
+            assert!(error.contains(expected), "{} does not contain {}", error, expected);
+        }
+    }
+}
@@ -67,6 +67,22 @@ pub enum ErrorKind {
     #[error("Missing environment variable: {0}")]
     EnvVarMissing(String),
 
+    /// Invalid or incomplete configuration, one problem per line
+    #[error("Invalid configuration:\n{0}")]
+    Config(String),
+
+    /// A conditional write lost against a concurrent change of the object
+    #[error("Object '{path}' was changed concurrently")]
+    Conflict { path: String },
+
+    /// Another instance holds the bucket run lock
+    #[error("Run lock is held by {owner} until {expires_at}")]
+    LockHeld { owner: String, expires_at: String },
+
+    /// Work skipped or aborted because a shutdown was requested
+    #[error("Cancelled by shutdown")]
+    Cancelled,
+
     /// Task join error
     #[error("Task join error: {0}")]
     TaskJoin(#[from] tokio::task::JoinError),
@@ -130,6 +146,7 @@ impl ErrorKind {
             ErrorKind::SerdeJSON(_)
                 | ErrorKind::SerdeXML(_)
                 | ErrorKind::InvalidInput(_)
+                | ErrorKind::Config(_)
                 | ErrorKind::MissingField { .. }
                 | ErrorKind::VersionParse { .. }
                 | ErrorKind::ArtifactParse { .. }
@@ -170,6 +187,29 @@ impl ErrorKind {
         }
     }
 
+    /// Determines if the work was interrupted by a shutdown rather than failing
+    pub fn is_cancelled(&self) -> bool {
+        match self {
+            ErrorKind::Cancelled => true,
+            ErrorKind::Generic { source, .. } => source
+                .downcast_ref::<ErrorKind>()
+                .is_some_and(ErrorKind::is_cancelled),
+            _ => false,
+        }
+    }
+
+    /// Determines if this error came from our own bucket rather than from
+    /// upstream or the data being processed
+    pub fn is_storage_error(&self) -> bool {
+        match self {
+            ErrorKind::S3 { .. } | ErrorKind::Conflict { .. } => true,
+            ErrorKind::Generic { source, .. } => source
+                .downcast_ref::<ErrorKind>()
+                .is_some_and(ErrorKind::is_storage_error),
+            _ => false,
+        }
+    }
+
     /// Determines if this error is a network-related issue
     pub fn is_network_error(&self) -> bool {
         matches!(
@@ -223,6 +263,27 @@ mod tests {
         };
         assert!(!checksum_err.is_permanent());
         assert!(checksum_err.should_retry());
+        assert!(!checksum_err.is_storage_error());
+
+        // Failures of our own bucket, also behind context
+        let conflict = ErrorKind::Conflict {
+            path: "v3/manifest.json".to_string(),
+        };
+        assert!(conflict.is_storage_error());
+        let wrapped = ErrorKind::Generic {
+            context: "Failed to upload library".to_string(),
+            source: Box::new(conflict),
+        };
+        assert!(wrapped.is_storage_error());
+        assert!(!wrapped.is_cancelled());
+
+        // Interrupted by a shutdown
+        let cancelled = ErrorKind::Generic {
+            context: "Failed to process version".to_string(),
+            source: Box::new(ErrorKind::Cancelled),
+        };
+        assert!(cancelled.is_cancelled());
+        assert!(!cancelled.is_storage_error());
     }
 
     #[test]
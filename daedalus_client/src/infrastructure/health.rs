@@ -0,0 +1,274 @@
+//! Liveness and readiness of the daemon
+//!
+//! Loader runs, root commits and circuit breaker transitions are recorded
+//! here as they happen, `/readyz` (see `infrastructure::http`) reports them
+//! and decides whether the daemon is doing its job:
+//!
+//! - the run lock is held, a standby instance waiting for it is live but
+//!   not ready
+//! - a root manifest was committed within `http.ready_max_intervals` times
+//!   the longest loader schedule (counted from taking the run lock until the
+//!   first commit)
+//! - no circuit breaker stayed open longer than
+//!   `http.ready_max_breaker_open_secs`
+
+use crate::infrastructure::config::HttpConfig;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+static STATE: LazyLock<Mutex<State>> = LazyLock::new(Mutex::default);
+
+#[derive(Debug, Clone, Default)]
+struct State {
+    waiting_for_lock: bool,
+    /// When the run lock was taken, startup if it never had to be waited for
+    lock_acquired_at: Option<DateTime<Utc>>,
+    last_commit: Option<DateTime<Utc>>,
+    loaders: BTreeMap<String, LoaderHealth>,
+}
+
+/// What is known about one loader, keyed by its breaker name
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LoaderHealth {
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_error: Option<LastError>,
+    /// Failed runs since the last successful one
+    pub consecutive_failures: u32,
+    pub breaker: Option<BreakerHealth>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LastError {
+    pub at: DateTime<Utc>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BreakerHealth {
+    pub state: BreakerStatus,
+    /// Held in `state` by an operator through the admin API
+    pub forced: bool,
+    /// When the breaker entered `state`
+    pub since: DateTime<Utc>,
+}
+
+/// Circuit breaker state as reported, the discriminant is its metric value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerStatus {
+    Closed = 0,
+    HalfOpen = 1,
+    Open = 2,
+}
+
+fn update(f: impl FnOnce(&mut State)) {
+    f(&mut STATE.lock().expect("health mutex poisoned"));
+}
+
+pub fn record_success(loader: &str) {
+    update(|state| {
+        let health = state.loaders.entry(loader.to_string()).or_default();
+        health.last_success = Some(Utc::now());
+        health.consecutive_failures = 0;
+    });
+}
+
+/// Record a failed run, returns the failed runs since the last successful one
+pub fn record_failure(loader: &str, error: &impl std::fmt::Display) -> u32 {
+    let mut failures = 0;
+    update(|state| {
+        let health = state.loaders.entry(loader.to_string()).or_default();
+        health.last_error = Some(LastError {
+            at: Utc::now(),
+            message: error.to_string(),
+        });
+        health.consecutive_failures += 1;
+        failures = health.consecutive_failures;
+    });
+    failures
+}
+
+/// What is known about every loader so far
+pub fn loaders() -> BTreeMap<String, LoaderHealth> {
+    STATE.lock().expect("health mutex poisoned").loaders.clone()
+}
+
+/// Record that the daemon is waiting for the run lock
+pub fn record_lock_wait() {
+    update(|state| state.waiting_for_lock = true);
+}
+
+/// Record that the daemon took the run lock
+pub fn record_lock_acquired() {
+    update(|state| {
+        state.waiting_for_lock = false;
+        state.lock_acquired_at = Some(Utc::now());
+    });
+}
+
+/// Record a successful root manifest commit
+pub fn record_commit() {
+    update(|state| state.last_commit = Some(Utc::now()));
+}
+
+/// Record the state of the breaker `name`, keeping the time it was entered
+pub fn record_breaker(name: &str, status: BreakerStatus, forced: bool) {
+    update(|state| {
+        let breaker = &mut state.loaders.entry(name.to_string()).or_default().breaker;
+        match breaker {
+            Some(breaker) if breaker.state == status => breaker.forced = forced,
+            _ => {
+                *breaker = Some(BreakerHealth {
+                    state: status,
+                    forced,
+                    since: Utc::now(),
+                })
+            }
+        }
+    });
+}
+
+/// Body of `/readyz`
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub ready: bool,
+    /// Why the daemon is not ready, empty when it is
+    pub problems: Vec<String>,
+    pub last_commit: Option<DateTime<Utc>>,
+    pub loaders: BTreeMap<String, LoaderHealth>,
+}
+
+/// Readiness thresholds of a running daemon
+#[derive(Debug, Clone)]
+pub struct Readiness {
+    started_at: DateTime<Utc>,
+    max_commit_age: Duration,
+    max_breaker_open: Duration,
+}
+
+impl Readiness {
+    /// Thresholds for a daemon started now whose slowest loader waits up to
+    /// `interval` between runs
+    pub fn new(config: &HttpConfig, interval: Duration) -> Self {
+        Self {
+            started_at: Utc::now(),
+            max_commit_age: interval.saturating_mul(config.ready_max_intervals),
+            max_breaker_open: Duration::from_secs(config.ready_max_breaker_open_secs),
+        }
+    }
+
+    pub fn report(&self) -> Report {
+        let state = STATE.lock().expect("health mutex poisoned").clone();
+        self.evaluate(state, Utc::now())
+    }
+
+    fn evaluate(&self, state: State, now: DateTime<Utc>) -> Report {
+        let mut problems = Vec::new();
+
+        if state.waiting_for_lock {
+            problems.push("waiting for run lock".to_string());
+        } else {
+            let started_at = state.lock_acquired_at.unwrap_or(self.started_at);
+            let since_commit = now - state.last_commit.unwrap_or(started_at);
+            if since_commit.to_std().unwrap_or_default() > self.max_commit_age {
+                problems.push(match state.last_commit {
+                    Some(at) => format!("last commit at {} is older than {}s", at, self.max_commit_age.as_secs()),
+                    None => format!("nothing committed within {}s of taking the run lock", self.max_commit_age.as_secs()),
+                });
+            }
+        }
+
+        for (name, loader) in &state.loaders {
+            // A breaker opened by an operator is meant to stay open
+            if let Some(breaker) = loader
+                .breaker
+                .as_ref()
+                .filter(|breaker| breaker.state == BreakerStatus::Open && !breaker.forced)
+            {
+                if (now - breaker.since).to_std().unwrap_or_default() > self.max_breaker_open {
+                    problems.push(format!("{} circuit breaker open since {}", name, breaker.since));
+                }
+            }
+        }
+
+        Report {
+            ready: problems.is_empty(),
+            problems,
+            last_commit: state.last_commit,
+            loaders: state.loaders,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_readiness() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 15, 10, 0, 0).unwrap();
+        let readiness = Readiness {
+            started_at: start,
+            max_commit_age: Duration::from_secs(3 * 3600),
+            max_breaker_open: Duration::from_secs(3600),
+        };
+        let hours = chrono::Duration::hours;
+
+        // The first cycle gets the same allowance as any other
+        assert!(readiness.evaluate(State::default(), start + hours(2)).ready);
+        let report = readiness.evaluate(State::default(), start + hours(4));
+        assert!(!report.ready);
+        assert_eq!(report.problems.len(), 1);
+
+        let mut state = State {
+            last_commit: Some(start + hours(3)),
+            ..State::default()
+        };
+        assert!(readiness.evaluate(state.clone(), start + hours(5)).ready);
+
+        state.loaders.insert(
+            "forge".to_string(),
+            LoaderHealth {
+                breaker: Some(BreakerHealth {
+                    state: BreakerStatus::Open,
+                    forced: false,
+                    since: start + hours(3),
+                }),
+                ..LoaderHealth::default()
+            },
+        );
+        let report = readiness.evaluate(state, start + hours(5));
+        assert!(!report.ready);
+        assert!(report.problems[0].starts_with("forge circuit breaker open"), "{:?}", report.problems);
+    }
+
+    #[test]
+    fn test_readiness_run_lock() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 15, 10, 0, 0).unwrap();
+        let readiness = Readiness {
+            started_at: start,
+            max_commit_age: Duration::from_secs(3 * 3600),
+            max_breaker_open: Duration::from_secs(3600),
+        };
+        let hours = chrono::Duration::hours;
+
+        let waiting = State {
+            waiting_for_lock: true,
+            ..State::default()
+        };
+        let report = readiness.evaluate(waiting, start + hours(1));
+        assert_eq!(report.problems, vec!["waiting for run lock"]);
+
+        // The first commit is due relative to taking the lock, not startup
+        let acquired = State {
+            lock_acquired_at: Some(start + hours(10)),
+            ..State::default()
+        };
+        assert!(readiness.evaluate(acquired.clone(), start + hours(12)).ready);
+        assert!(!readiness.evaluate(acquired, start + hours(14)).ready);
+    }
+}
@@ -0,0 +1,80 @@
+//! Optional HTTP listener of the daemon (`http.listen`)
+//!
+//! | Route      | Content                                                      |
+//! |------------|--------------------------------------------------------------|
+//! | `/healthz` | Liveness, fails when the runtime stops scheduling tasks      |
+//! | `/readyz`  | Readiness and per-loader state, see `infrastructure::health` |
+//! | `/metrics` | Prometheus metrics, see `infrastructure::metrics`            |
+
+use crate::infrastructure::admin::{self, Admin};
+use crate::infrastructure::health::Readiness;
+use axum::extract::State;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::{Json, Router};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+fn router(readiness: Readiness, admin: Option<Admin>) -> Router {
+    let router = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .route("/metrics", get(metrics))
+        .with_state(Arc::new(readiness));
+
+    match admin {
+        Some(admin) => router.nest("/admin", admin::router(admin)),
+        None => router,
+    }
+}
+
+async fn healthz() -> impl IntoResponse {
+    // A wedged runtime does not get to a freshly spawned task
+    match tokio::time::timeout(Duration::from_secs(1), tokio::spawn(async {})).await {
+        Ok(Ok(())) => (StatusCode::OK, Json(serde_json::json!({ "status": "ok" }))),
+        _ => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({ "status": "unresponsive" })),
+        ),
+    }
+}
+
+async fn readyz(State(readiness): State<Arc<Readiness>>) -> impl IntoResponse {
+    let report = readiness.report();
+    let status = if report.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report))
+}
+
+async fn metrics() -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+        crate::infrastructure::metrics::render(),
+    )
+}
+
+/// Serve the HTTP routes on `addr` until the process exits
+///
+/// A listener that cannot be bound is logged and the daemon carries on
+/// without it.
+pub async fn serve(addr: SocketAddr, readiness: Readiness, admin: Option<Admin>) {
+    let listener = match tokio::net::TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            warn!(addr = %addr, error = %err, "Failed to bind HTTP listener, health and metrics disabled");
+            return;
+        }
+    };
+    info!(addr = %addr, admin = admin.is_some(), "Serving health and metrics over HTTP");
+
+    if let Err(err) = axum::serve(listener, router(readiness, admin)).await {
+        warn!(error = %err, "HTTP listener stopped");
+    }
+}
@@ -0,0 +1,257 @@
+//! Prometheus metrics
+//!
+//! Metrics live in a process-wide registry under the `daedalus_` namespace and
+//! are served in the text exposition format on `/metrics` when `http.listen`
+//! is set (see `infrastructure::http`).
+
+use prometheus::core::Collector;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::sync::LazyLock;
+use std::time::Instant;
+use tokio::sync::{AcquireError, Semaphore, SemaphorePermit};
+
+static REGISTRY: LazyLock<Registry> = LazyLock::new(|| {
+    Registry::new_custom(Some("daedalus".to_string()), None).expect("valid metrics namespace")
+});
+
+fn register<T: Collector + Clone + 'static>(metric: T) -> T {
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("metric registered twice");
+    metric
+}
+
+/// Duration of one loader run within a cycle
+pub static LOADER_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register(
+        HistogramVec::new(
+            HistogramOpts::new("loader_duration_seconds", "Duration of a loader run")
+                .buckets(vec![
+                    1.0, 5.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1200.0, 1800.0, 3600.0,
+                    7200.0, 14400.0,
+                ]),
+            &["loader"],
+        )
+        .expect("valid metric"),
+    )
+});
+
+/// Loader runs by outcome: `success`, `failure` or `skipped` (breaker open)
+pub static LOADER_RUNS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("loader_runs_total", "Loader runs by outcome"),
+            &["loader", "outcome"],
+        )
+        .expect("valid metric"),
+    )
+});
+
+/// Versions processed by loader and outcome, the counts of the `📊` log lines
+pub static VERSIONS_PROCESSED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("versions_processed_total", "Versions processed by outcome"),
+            &["loader", "outcome"],
+        )
+        .expect("valid metric"),
+    )
+});
+
+pub static DOWNLOADED_BYTES: LazyLock<IntCounter> = LazyLock::new(|| {
+    register(
+        IntCounter::new("downloaded_bytes_total", "Bytes downloaded from upstreams")
+            .expect("valid metric"),
+    )
+});
+
+pub static UPLOADED_BYTES: LazyLock<IntCounter> = LazyLock::new(|| {
+    register(
+        IntCounter::new("uploaded_bytes_total", "Bytes uploaded to the bucket")
+            .expect("valid metric"),
+    )
+});
+
+/// CAS uploads skipped because the same content was already uploaded
+pub static CAS_DEDUPE_HITS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register(
+        IntCounter::new(
+            "cas_dedupe_hits_total",
+            "CAS uploads skipped because the object was already uploaded",
+        )
+        .expect("valid metric"),
+    )
+});
+
+/// Retries of the backoff wrappers by operation
+pub static RETRIES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("retries_total", "Retried operations"),
+            &["operation"],
+        )
+        .expect("valid metric"),
+    )
+});
+
+/// Log records the Betterstack layer dropped because its queue was full
+pub static BETTERSTACK_DROPPED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register(
+        IntCounter::new(
+            "betterstack_dropped_logs_total",
+            "Log records dropped because the Betterstack queue was full",
+        )
+        .expect("valid metric"),
+    )
+});
+
+/// Tasks waiting for a permit of the upload/download semaphore
+pub static SEMAPHORE_WAITING: LazyLock<IntGauge> = LazyLock::new(|| {
+    register(
+        IntGauge::new(
+            "semaphore_waiting",
+            "Tasks waiting for an upload/download permit",
+        )
+        .expect("valid metric"),
+    )
+});
+
+/// Circuit breaker state: 0 closed, 1 half-open, 2 open
+pub static CIRCUIT_BREAKER_STATE: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register(
+        IntGaugeVec::new(
+            Opts::new(
+                "circuit_breaker_state",
+                "Circuit breaker state (0 closed, 1 half-open, 2 open)",
+            ),
+            &["breaker"],
+        )
+        .expect("valid metric"),
+    )
+});
+
+pub static LAST_COMMIT: LazyLock<IntGauge> = LazyLock::new(|| {
+    register(
+        IntGauge::new(
+            "last_commit_timestamp_seconds",
+            "Unix time of the last successful root manifest commit",
+        )
+        .expect("valid metric"),
+    )
+});
+
+/// Outcome of a loader run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Success,
+    Failure,
+    /// The circuit breaker was open
+    Skipped,
+    /// Interrupted by a shutdown
+    Cancelled,
+}
+
+impl Outcome {
+    fn as_str(self) -> &'static str {
+        match self {
+            Outcome::Success => "success",
+            Outcome::Failure => "failure",
+            Outcome::Skipped => "skipped",
+            Outcome::Cancelled => "cancelled",
+        }
+    }
+}
+
+/// Record a loader run that started at `started`
+pub fn record_loader_run(loader: &str, started: Instant, outcome: Outcome) {
+    LOADER_RUNS
+        .with_label_values(&[loader, outcome.as_str()])
+        .inc();
+    // Runs that did not finish would skew the durations
+    if matches!(outcome, Outcome::Success | Outcome::Failure) {
+        LOADER_DURATION
+            .with_label_values(&[loader])
+            .observe(started.elapsed().as_secs_f64());
+    }
+}
+
+/// Record the per-version counts of a processing pass
+pub fn record_versions(loader: &str, successful: u64, failed: u64) {
+    let loader = loader.to_lowercase();
+    VERSIONS_PROCESSED
+        .with_label_values(&[&loader, "success"])
+        .inc_by(successful);
+    VERSIONS_PROCESSED
+        .with_label_values(&[&loader, "failure"])
+        .inc_by(failed);
+}
+
+/// Count a retry of `operation`, for the `notify` hook of backon
+pub fn record_retry(operation: &str) {
+    RETRIES.with_label_values(&[operation]).inc();
+}
+
+/// Acquire a permit of the upload/download semaphore, tracking the queue depth
+pub async fn acquire_permit(semaphore: &Semaphore) -> Result<SemaphorePermit<'_>, AcquireError> {
+    struct Waiting;
+
+    impl Drop for Waiting {
+        fn drop(&mut self) {
+            SEMAPHORE_WAITING.dec();
+        }
+    }
+
+    SEMAPHORE_WAITING.inc();
+    let _waiting = Waiting;
+    semaphore.acquire().await
+}
+
+/// Every metric in the text exposition format
+pub fn render() -> String {
+    // Metrics register on first use, force them so all of them are exported
+    LazyLock::force(&LOADER_DURATION);
+    LazyLock::force(&LOADER_RUNS);
+    LazyLock::force(&VERSIONS_PROCESSED);
+    LazyLock::force(&DOWNLOADED_BYTES);
+    LazyLock::force(&UPLOADED_BYTES);
+    LazyLock::force(&CAS_DEDUPE_HITS);
+    LazyLock::force(&RETRIES);
+    LazyLock::force(&BETTERSTACK_DROPPED);
+    LazyLock::force(&SEMAPHORE_WAITING);
+    LazyLock::force(&CIRCUIT_BREAKER_STATE);
+    LazyLock::force(&LAST_COMMIT);
+
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&REGISTRY.gather(), &mut buffer)
+        .expect("text encoding of metrics cannot fail");
+    String::from_utf8(buffer).expect("metrics are valid UTF-8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        record_loader_run("fabric", Instant::now(), Outcome::Success);
+        record_loader_run("forge", Instant::now(), Outcome::Skipped);
+        record_loader_run("quilt", Instant::now(), Outcome::Cancelled);
+        record_versions("Fabric", 3, 1);
+
+        let output = render();
+        assert!(output.contains(r#"daedalus_loader_runs_total{loader="fabric",outcome="success"} 1"#));
+        assert!(output.contains(r#"daedalus_loader_runs_total{loader="forge",outcome="skipped"} 1"#));
+        assert!(output.contains(r#"daedalus_loader_runs_total{loader="quilt",outcome="cancelled"} 1"#));
+        assert!(!output.contains("daedalus_loader_duration_seconds_count{loader=\"quilt\"}"));
+        assert!(output.contains(r#"daedalus_versions_processed_total{loader="fabric",outcome="failure"} 1"#));
+        assert!(output.contains("daedalus_loader_duration_seconds_count{loader=\"fabric\"} 1"));
+        // Skipped runs have no duration
+        assert!(!output.contains("daedalus_loader_duration_seconds_count{loader=\"forge\"}"));
+        assert!(output.contains("# TYPE daedalus_uploaded_bytes_total counter"));
+    }
+}
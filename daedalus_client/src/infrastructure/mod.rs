@@ -1,2 +1,10 @@
+pub mod admin;
 pub mod circuit_breaker;
+pub mod config;
+pub mod health;
+pub mod http;
+pub mod metrics;
+pub mod scheduler;
+pub mod shutdown;
+pub mod telemetry;
 pub mod error;
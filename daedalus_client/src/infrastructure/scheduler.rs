@@ -0,0 +1,354 @@
+//! Per-loader schedules and on-demand triggers for the daemon
+//!
+//! Every loader runs on its own [`Schedule`], either a fixed interval or a
+//! cron expression. [`Triggers`] wakes a loader up immediately, or pauses its
+//! scheduled runs; it is fed by `SIGUSR1` (every loader), the admin API and
+//! by the optional trigger socket, which accepts one loader name (or `all`)
+//! per connection:
+//!
+//! ```text
+//! echo fabric | nc -U /run/daedalus.sock
+//! ```
+
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+/// How far ahead cron occurrences are inspected by [`Schedule::longest_gap`]
+const CRON_HORIZON_DAYS: i64 = 366;
+/// Upper bound on the cron occurrences inspected by [`Schedule::longest_gap`]
+const CRON_MAX_OCCURRENCES: usize = 10_000;
+
+/// When a loader runs
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    /// A fixed delay between the start of two runs
+    Interval(Duration),
+    /// A cron expression with a seconds field (`sec min hour day month weekday`)
+    Cron(Box<cron::Schedule>),
+}
+
+impl Schedule {
+    /// Build a schedule from the `interval_secs` and `cron` settings
+    ///
+    /// `cron` wins when both are set (validation rejects that), and
+    /// `default_interval` is used when neither is.
+    pub fn new(
+        interval_secs: Option<u64>,
+        cron: Option<&str>,
+        default_interval: u64,
+    ) -> Result<Self, String> {
+        match (cron, interval_secs) {
+            (Some(expression), _) => Self::parse_cron(expression),
+            (None, Some(secs)) => Ok(Self::Interval(Duration::from_secs(secs))),
+            (None, None) => Ok(Self::Interval(Duration::from_secs(default_interval))),
+        }
+    }
+
+    pub fn parse_cron(expression: &str) -> Result<Self, String> {
+        cron::Schedule::from_str(expression)
+            .map(|schedule| Self::Cron(Box::new(schedule)))
+            .map_err(|err| format!("invalid cron expression `{}`: {}", expression, err))
+    }
+
+    /// How long to wait after a run that started at `last_run`
+    pub fn delay(&self, last_run: DateTime<Utc>, now: DateTime<Utc>) -> Duration {
+        let next = match self {
+            Schedule::Interval(interval) => {
+                last_run + chrono::Duration::from_std(*interval).unwrap_or(chrono::Duration::MAX)
+            }
+            Schedule::Cron(schedule) => match schedule.after(&now).next() {
+                Some(next) => next,
+                // An expression without future occurrences never fires again
+                None => return Duration::MAX,
+            },
+        };
+
+        (next - now).to_std().unwrap_or(Duration::ZERO)
+    }
+
+    /// The longest wait between two runs from `now` on
+    ///
+    /// Cron expressions are inspected up to a year ahead. `None` when the
+    /// expression never fires twice again.
+    pub fn longest_gap(&self, now: DateTime<Utc>) -> Option<Duration> {
+        match self {
+            Schedule::Interval(interval) => Some(*interval),
+            Schedule::Cron(schedule) => {
+                let horizon = now + chrono::Duration::days(CRON_HORIZON_DAYS);
+                let occurrences: Vec<_> = schedule
+                    .after(&now)
+                    .take_while(|at| *at <= horizon)
+                    .take(CRON_MAX_OCCURRENCES)
+                    .collect();
+                occurrences
+                    .windows(2)
+                    .filter_map(|pair| (pair[1] - pair[0]).to_std().ok())
+                    .max()
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Schedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Schedule::Interval(interval) => write!(f, "every {}s", interval.as_secs()),
+            Schedule::Cron(schedule) => write!(f, "cron `{}`", schedule),
+        }
+    }
+}
+
+/// Wakes scheduled loaders before their next run is due
+#[derive(Debug, Default)]
+pub struct Triggers {
+    loaders: HashMap<String, (Notify, AtomicBool)>,
+}
+
+impl Triggers {
+    pub fn new<'a>(loaders: impl IntoIterator<Item = &'a str>) -> Self {
+        Self {
+            loaders: loaders
+                .into_iter()
+                .map(|loader| (loader.to_string(), (Notify::new(), AtomicBool::new(false))))
+                .collect(),
+        }
+    }
+
+    /// Skip the scheduled runs of `loader` (`paused`) or resume them
+    ///
+    /// Triggered runs still happen while paused. Returns `false` for loaders
+    /// that are not scheduled.
+    pub fn set_paused(&self, loader: &str, paused: bool) -> bool {
+        match self.loaders.get(loader) {
+            Some((_, flag)) => {
+                flag.store(paused, Ordering::SeqCst);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_paused(&self, loader: &str) -> bool {
+        self.loaders
+            .get(loader)
+            .is_some_and(|(_, paused)| paused.load(Ordering::SeqCst))
+    }
+
+    /// Request an immediate run of `loader`, or of every loader for `all`
+    ///
+    /// A trigger received while the loader is running makes it run again
+    /// right after. Returns `false` for loaders that are not scheduled.
+    pub fn trigger(&self, loader: &str) -> bool {
+        if loader == "all" {
+            self.loaders.values().for_each(|(notify, _)| notify.notify_one());
+            return true;
+        }
+
+        match self.loaders.get(loader) {
+            Some((notify, _)) => {
+                notify.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Completes when `loader` is triggered
+    pub async fn triggered(&self, loader: &str) {
+        match self.loaders.get(loader) {
+            Some((notify, _)) => notify.notified().await,
+            None => std::future::pending().await,
+        }
+    }
+}
+
+/// Trigger every loader on `SIGUSR1`
+#[cfg(unix)]
+pub async fn listen_signal(triggers: &Triggers) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut signals = match signal(SignalKind::user_defined1()) {
+        Ok(signals) => signals,
+        Err(err) => {
+            warn!(error = %err, "Failed to install SIGUSR1 handler, signal triggers disabled");
+            return;
+        }
+    };
+
+    while signals.recv().await.is_some() {
+        info!("Received SIGUSR1, triggering every loader");
+        triggers.trigger("all");
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn listen_signal(_triggers: &Triggers) {
+    std::future::pending().await
+}
+
+/// Time a trigger connection gets to send its loader name
+const SOCKET_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest request line read from a trigger connection, loader names are short
+const SOCKET_MAX_LINE: u64 = 256;
+
+/// Accept trigger requests on a Unix socket at `path`
+///
+/// Each connection sends one loader name and gets `ok` or an error back.
+/// Connections are handled one at a time, so a client that does not send a
+/// line within [`SOCKET_READ_TIMEOUT`] is dropped instead of holding up the
+/// next one.
+#[cfg(unix)]
+pub async fn listen_socket(path: &std::path::Path, triggers: &Triggers) {
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+    // A socket left behind by a previous process would make bind fail
+    let _ = std::fs::remove_file(path);
+    let listener = match tokio::net::UnixListener::bind(path) {
+        Ok(listener) => listener,
+        Err(err) => {
+            warn!(path = %path.display(), error = %err, "Failed to bind trigger socket, socket triggers disabled");
+            return;
+        }
+    };
+    info!(path = %path.display(), "Listening for triggers");
+
+    loop {
+        let mut stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                warn!(error = %err, "Failed to accept trigger connection");
+                continue;
+            }
+        };
+
+        let mut line = String::new();
+        let (reader, mut writer) = stream.split();
+        let mut reader = BufReader::new(reader.take(SOCKET_MAX_LINE));
+        match tokio::time::timeout(SOCKET_READ_TIMEOUT, reader.read_line(&mut line)).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(_)) => continue,
+            Err(_) => {
+                warn!("Trigger connection sent nothing in time, dropping it");
+                continue;
+            }
+        }
+
+        let loader = line.trim();
+        let reply = if triggers.trigger(loader) {
+            info!(loader, "⚡ Triggered through the trigger socket");
+            "ok\n".to_string()
+        } else {
+            format!("error: `{}` is not a scheduled loader\n", loader)
+        };
+        let _ = writer.write_all(reply.as_bytes()).await;
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn listen_socket(_path: &std::path::Path, _triggers: &Triggers) {
+    warn!("Trigger sockets are only supported on Unix");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_schedule_delay() {
+        let last_run = Utc.with_ymd_and_hms(2024, 1, 15, 10, 0, 0).unwrap();
+        let now = Utc.with_ymd_and_hms(2024, 1, 15, 10, 20, 0).unwrap();
+
+        let interval = Schedule::new(Some(1800), None, 3600).unwrap();
+        assert_eq!(interval.delay(last_run, now), Duration::from_secs(600));
+        // An overdue run starts right away
+        assert_eq!(interval.delay(last_run, now + chrono::Duration::hours(1)), Duration::ZERO);
+
+        let default = Schedule::new(None, None, 3600).unwrap();
+        assert_eq!(default.delay(last_run, now), Duration::from_secs(2400));
+
+        let cron = Schedule::new(None, Some("0 */15 * * * *"), 3600).unwrap();
+        assert_eq!(cron.delay(last_run, now), Duration::from_secs(600));
+
+        assert!(Schedule::parse_cron("every minute").is_err());
+    }
+
+    #[test]
+    fn test_schedule_longest_gap() {
+        // A Monday
+        let now = Utc.with_ymd_and_hms(2024, 1, 15, 10, 0, 0).unwrap();
+
+        let interval = Schedule::new(Some(1800), None, 3600).unwrap();
+        assert_eq!(interval.longest_gap(now), Some(Duration::from_secs(1800)));
+
+        let hourly = Schedule::new(None, Some("0 0 * * * *"), 3600).unwrap();
+        assert_eq!(hourly.longest_gap(now), Some(Duration::from_secs(3600)));
+
+        // Weekday mornings only wait over the weekend
+        let weekdays = Schedule::new(None, Some("0 0 6 * * Mon-Fri"), 3600).unwrap();
+        assert_eq!(weekdays.longest_gap(now), Some(Duration::from_secs(3 * 86400)));
+
+        let past = Schedule::new(None, Some("0 0 0 1 1 * 2020"), 3600).unwrap();
+        assert_eq!(past.longest_gap(now), None);
+    }
+
+    #[tokio::test]
+    async fn test_triggers() {
+        let triggers = Triggers::new(["fabric", "forge"]);
+
+        assert!(!triggers.trigger("quilt"));
+        assert!(triggers.trigger("fabric"));
+        // The stored permit completes the next wait immediately
+        tokio::time::timeout(Duration::from_secs(1), triggers.triggered("fabric"))
+            .await
+            .unwrap();
+
+        assert!(triggers.trigger("all"));
+        tokio::time::timeout(Duration::from_secs(1), triggers.triggered("forge"))
+            .await
+            .unwrap();
+
+        assert!(triggers.set_paused("forge", true));
+        assert!(triggers.is_paused("forge"));
+        assert!(!triggers.is_paused("fabric"));
+        assert!(!triggers.set_paused("quilt", true));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test(start_paused = true)]
+    async fn test_listen_socket_idle_client() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::UnixStream;
+
+        let path = std::env::temp_dir().join(format!("daedalus-trigger-{}.sock", std::process::id()));
+        let triggers = Triggers::new(["fabric"]);
+
+        let client = async {
+            while !path.exists() {
+                tokio::task::yield_now().await;
+            }
+            // Connects first and never sends anything
+            let _idle = UnixStream::connect(&path).await.unwrap();
+
+            let mut stream = UnixStream::connect(&path).await.unwrap();
+            stream.write_all(b"fabric\n").await.unwrap();
+            let mut reply = String::new();
+            stream.read_to_string(&mut reply).await.unwrap();
+            reply
+        };
+
+        let reply = tokio::select! {
+            reply = client => reply,
+            _ = listen_socket(&path, &triggers) => unreachable!(),
+        };
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(reply, "ok\n");
+    }
+}
@@ -0,0 +1,106 @@
+//! Cooperative shutdown
+//!
+//! The first SIGTERM or Ctrl+C only requests a shutdown: processors stop
+//! starting new versions (see [`check`]), downloads and upload retries bail
+//! out, and a cycle that was interrupted skips its root commit, so the
+//! published state never points at a half-processed loader. In-flight
+//! uploads are allowed to finish until `daemon.shutdown_timeout_secs` runs out
+//! (see [`deadline`]), a second signal aborts right away.
+
+use crate::infrastructure::error::{Error, ErrorKind};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+#[cfg(unix)]
+use tokio::signal::unix::{signal as unix_signal, SignalKind};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+static NOTIFY: Notify = Notify::const_new();
+
+/// Ask everything to wind down
+pub fn request() {
+    if !REQUESTED.swap(true, Ordering::SeqCst) {
+        NOTIFY.notify_waiters();
+    }
+}
+
+pub fn is_requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Fails with [`ErrorKind::Cancelled`] once a shutdown was requested
+///
+/// Called before starting a unit of work that can be skipped.
+pub fn check() -> Result<(), Error> {
+    if is_requested() {
+        Err(ErrorKind::Cancelled)
+    } else {
+        Ok(())
+    }
+}
+
+/// Completes once a shutdown was requested
+pub async fn requested() {
+    let notified = NOTIFY.notified();
+    tokio::pin!(notified);
+    // Register before checking the flag so a concurrent request is not missed
+    notified.as_mut().enable();
+
+    if !is_requested() {
+        notified.await;
+    }
+}
+
+/// Completes when a shutdown signal is received (SIGTERM or Ctrl+C)
+pub async fn signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        unix_signal(SignalKind::terminate())
+            .expect("failed to install signal handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {
+            info!("Received Ctrl+C signal");
+        }
+        _ = terminate => {
+            info!("Received SIGTERM signal");
+        }
+    }
+}
+
+/// Request a shutdown on the first signal
+pub async fn listen() {
+    signal().await;
+    info!("🛑 Shutdown requested, draining the running cycle");
+    request();
+}
+
+/// Completes `timeout` after a shutdown was requested, or on a second signal
+///
+/// Whatever is still running at that point gets aborted.
+pub async fn deadline(timeout: Duration) {
+    requested().await;
+
+    tokio::select! {
+        _ = tokio::time::sleep(timeout) => {
+            warn!(timeout_secs = timeout.as_secs(), "Shutdown deadline exceeded, aborting in-flight work");
+        }
+        _ = signal() => {
+            warn!("Second shutdown signal, aborting in-flight work");
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! OpenTelemetry trace export
+//!
+//! With `otlp.endpoint` set, every `tracing` span is exported over OTLP/HTTP,
+//! so a cycle shows up as one trace: `processing_cycle`, the per-loader
+//! `*_processing` spans, one span per version (`minecraft_version`,
+//! `forge_version`, ...) and the `#[instrument]` spans of every download and
+//! upload below them.
+//!
+//! Spans are batched in the background and flushed by
+//! [`TelemetryHandle::shutdown`] before the process exits.
+
+use crate::infrastructure::config::OtlpConfig;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::{Tracer, TracerProvider};
+use opentelemetry_sdk::Resource;
+use tracing_opentelemetry::OpenTelemetryLayer;
+
+/// Keeps the exporter alive until [`TelemetryHandle::shutdown`]
+pub struct TelemetryHandle {
+    provider: TracerProvider,
+}
+
+impl TelemetryHandle {
+    /// Export the spans still buffered and stop the exporter
+    pub async fn shutdown(self) {
+        let provider = self.provider;
+        // The batch processor blocks until its queue is drained
+        let result = tokio::task::spawn_blocking(move || provider.shutdown()).await;
+        if let Ok(Err(err)) = result {
+            eprintln!("Failed to flush traces on shutdown: {}", err);
+        }
+    }
+}
+
+/// The tracing layer exporting spans to `otlp.endpoint`, `None` when unset
+///
+/// Must be called within the Tokio runtime. An exporter that cannot be built
+/// is reported on stderr, as logging is not set up yet, and skipped.
+pub fn layer<S>(config: &OtlpConfig) -> Option<(OpenTelemetryLayer<S, Tracer>, TelemetryHandle)>
+where
+    S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+    let endpoint = config.endpoint.as_deref()?;
+    let exporter = match opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(traces_url(endpoint))
+        .build()
+    {
+        Ok(exporter) => exporter,
+        Err(err) => {
+            eprintln!("Failed to set up OTLP trace export to {}: {}", endpoint, err);
+            return None;
+        }
+    };
+
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, opentelemetry_sdk::runtime::Tokio)
+        .with_resource(Resource::new([
+            KeyValue::new("service.name", config.service_name.clone()),
+            KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+        ]))
+        .build();
+    let tracer = provider.tracer("daedalus_client");
+
+    Some((
+        tracing_opentelemetry::layer().with_tracer(tracer),
+        TelemetryHandle { provider },
+    ))
+}
+
+/// Traces are posted to `/v1/traces` below the collector's base URL
+fn traces_url(endpoint: &str) -> String {
+    let endpoint = endpoint.trim_end_matches('/');
+    if endpoint.ends_with("/v1/traces") {
+        endpoint.to_string()
+    } else {
+        format!("{}/v1/traces", endpoint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_traces_url() {
+        assert_eq!(traces_url("http://localhost:4318"), "http://localhost:4318/v1/traces");
+        assert_eq!(traces_url("http://localhost:4318/"), "http://localhost:4318/v1/traces");
+        assert_eq!(traces_url("https://otel.example.com/v1/traces"), "https://otel.example.com/v1/traces");
+    }
+}
@@ -1,17 +1,19 @@
 pub mod fabric;
 pub mod quilt;
 
-use crate::{download_file, format_url};
+use crate::common::merge_loader_versions;
+use crate::infrastructure::config::Config;
+use crate::download_file;
 use crate::services::upload::BatchUploader;
 use dashmap::DashSet;
 use daedalus::minecraft::{Library, VersionManifest};
 use daedalus::modded::{LoaderVersion, PartialVersionInfo, Version};
-use daedalus::{get_hash, Branding, BRANDING};
+use daedalus::{Branding, BRANDING};
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::{Mutex, RwLock, Semaphore};
-use tracing::{info, warn};
+use tracing::{info, warn, Instrument};
 
 fn extract_hash_from_cas_url(url: &str) -> Option<String> {
     let parts: Vec<&str> = url.rsplitn(3, '/').collect();
@@ -106,14 +108,17 @@ impl<S: LoaderStrategy> LoaderProcessor<S> {
     ///
     /// This is the generic implementation of what was previously duplicated
     /// in fabric.rs and quilt.rs.
+    #[allow(clippy::too_many_arguments)]
     pub async fn retrieve_data<V>(
         &self,
         minecraft_versions: &VersionManifest,
         uploader: &BatchUploader,
         manifest_builder: &crate::services::cas::ManifestBuilder,
+        config: &Config,
         s3_client: &s3::Bucket,
         semaphore: Arc<Semaphore>,
-    ) -> Result<(), crate::infrastructure::error::Error>
+        previous: &crate::services::previous_state::PreviousState,
+        ) -> Result<(), crate::infrastructure::error::Error>
     where
         V: LoaderVersionsList + for<'de> Deserialize<'de>,
     {
@@ -122,20 +127,9 @@ impl<S: LoaderStrategy> LoaderProcessor<S> {
         // Fetch list of available versions from the loader API
         let list: V = self.fetch_versions_list(None, semaphore.clone()).await?;
 
-        // Try to load existing manifest to do incremental updates
-        let old_manifest = daedalus::modded::fetch_manifest(&format_url(&format!(
-            "{}/v{}/manifest.json",
-            self.strategy.manifest_path_prefix(),
-            crate::services::cas::CAS_VERSION,
-        )))
-        .await
-        .ok();
-
-        let mut versions = if let Some(old_manifest) = old_manifest {
-            old_manifest.game_versions
-        } else {
-            Vec::new()
-        };
+        // Start from the live manifest to do incremental updates
+        let mut versions =
+            previous.game_versions(self.strategy.manifest_path_prefix());
 
         // Prepare list of loaders to process
         // Format: (stable, version, old_version_opt)
@@ -144,6 +138,11 @@ impl<S: LoaderStrategy> LoaderProcessor<S> {
         {
             let mut loaders = loaders_mutex.write().await;
             for loader in list.loader() {
+                if crate::services::quarantine::skips(self.strategy.name(), loader.version()) {
+                    info!("⏭️  {} - Skipping quarantined version: {}", self.strategy.name(), loader.version());
+                    continue;
+                }
+
                 // Find old version if it exists in the dummy version
                 let old_loader_version = versions
                     .iter()
@@ -173,6 +172,7 @@ impl<S: LoaderStrategy> LoaderProcessor<S> {
         let mut fetch_failed = 0;
 
         for (stable, loader, old_loader_version) in loaders_mutex.read().await.clone() {
+            crate::infrastructure::shutdown::check()?;
             match self
                 .fetch_loader_version(DUMMY_GAME_VERSION, &loader, semaphore.clone())
                 .await
@@ -189,6 +189,8 @@ impl<S: LoaderStrategy> LoaderProcessor<S> {
                         e
                     );
                     fetch_failed += 1;
+                    crate::services::report::record_failure(self.strategy.name(), &loader, &e);
+                    crate::services::quarantine::record_failure(&config.quarantine, self.strategy.name(), &loader, &e);
                 }
             }
         }
@@ -208,6 +210,8 @@ impl<S: LoaderStrategy> LoaderProcessor<S> {
         let mut process_failed = 0;
 
         for (stable, loader, version, old_loader_version) in loader_versions {
+            // The per-version errors below are tolerated, a shutdown is not
+            crate::infrastructure::shutdown::check()?;
             let loader_clone = loader.clone();
             let process_result = self
                 .process_loader_version(
@@ -219,15 +223,18 @@ impl<S: LoaderStrategy> LoaderProcessor<S> {
                     &loader_version_mutex,
                     uploader,
                     manifest_builder,
+                    config,
                     s3_client,
                     &visited_artifacts,
                     semaphore.clone(),
                 )
+                .instrument(tracing::info_span!("loader_version", loader = self.strategy.name(), version = %loader_clone))
                 .await;
 
             match process_result {
                 Ok(_) => {
                     process_successful += 1;
+                    crate::services::quarantine::record_success(self.strategy.name(), &loader_clone);
                 }
                 Err(e) => {
                     warn!(
@@ -237,6 +244,8 @@ impl<S: LoaderStrategy> LoaderProcessor<S> {
                         e
                     );
                     process_failed += 1;
+                    crate::services::report::record_failure(self.strategy.name(), &loader_clone, &e);
+                    crate::services::quarantine::record_failure(&config.quarantine, self.strategy.name(), &loader_clone, &e);
                 }
             }
         }
@@ -247,24 +256,24 @@ impl<S: LoaderStrategy> LoaderProcessor<S> {
             process_successful,
             process_failed
         );
+        crate::infrastructure::metrics::record_versions(self.strategy.name(), process_successful, process_failed);
 
-        // Add processed loaders to versions list
-        let mut loader_version_mutex = loader_version_mutex.into_inner();
+        // Add processed loaders to versions list, replacing the previous
+        // entries of loaders that were processed again
+        let loader_version_mutex = loader_version_mutex.into_inner();
         if !loader_version_mutex.is_empty() {
-            if let Some(version) = versions.iter_mut().find(|x| {
-                x.id == BRANDING.get_or_init(Branding::default).dummy_replace_string
-            }) {
-                version.loaders.append(&mut loader_version_mutex);
-            } else {
-                versions.push(Version {
+            versions = merge_loader_versions(
+                versions,
+                vec![Version {
                     id: BRANDING
                         .get_or_init(Branding::default)
                         .dummy_replace_string
                         .clone(),
                     stable: true,
                     loaders: loader_version_mutex,
-                });
-            }
+                }],
+                self.strategy.name(),
+            );
         }
 
         // Add game versions that don't have loaders yet
@@ -384,6 +393,7 @@ impl<S: LoaderStrategy> LoaderProcessor<S> {
         loader_version_mutex: &Mutex<Vec<LoaderVersion>>,
         uploader: &BatchUploader,
         manifest_builder: &crate::services::cas::ManifestBuilder,
+        config: &Config,
         s3_client: &s3::Bucket,
         visited_artifacts: &Arc<DashSet<String>>,
         semaphore: Arc<Semaphore>,
@@ -462,9 +472,9 @@ impl<S: LoaderStrategy> LoaderProcessor<S> {
                                 .await?;
 
                                 // Upload to CAS and get hash
-                                let hash = uploader.upload_cas(
+                                let hash = uploader.upload_maven_artifact(
                                     artifact.to_vec(),
-                                    Some("application/java-archive".to_string()),
+                                    &artifact_path,
                                     s3_client,
                                     semaphore.clone(),
                                 ).await?;
@@ -497,15 +507,15 @@ impl<S: LoaderStrategy> LoaderProcessor<S> {
                         .await?;
 
                         // Upload to CAS and get hash
-                        let hash = uploader.upload_cas(
+                        let hash = uploader.upload_maven_artifact(
                             artifact.to_vec(),
-                            Some("application/java-archive".to_string()),
+                            &artifact_path,
                             s3_client,
                             semaphore.clone(),
                         ).await?;
 
                         // Store full CAS URL
-                        let base_url = dotenvy::var("BASE_URL").unwrap();
+                        let base_url = &config.base_url;
                         lib.url = Some(format!(
                             "{}/v{}/objects/{}/{}",
                             base_url,
@@ -534,15 +544,15 @@ impl<S: LoaderStrategy> LoaderProcessor<S> {
                 .await?;
 
                 // Upload to CAS and get hash
-                let hash = uploader.upload_cas(
+                let hash = uploader.upload_maven_artifact(
                     artifact.to_vec(),
-                    Some("application/java-archive".to_string()),
+                    &artifact_path,
                     s3_client,
                     semaphore.clone(),
                 ).await?;
 
                 // Store full CAS URL
-                let base_url = dotenvy::var("BASE_URL").unwrap();
+                let base_url = &config.base_url;
                 lib.url = Some(format!(
                     "{}/v{}/objects/{}/{}",
                     base_url,
@@ -578,8 +588,8 @@ impl<S: LoaderStrategy> LoaderProcessor<S> {
             data: None,
         };
 
-        let version_bytes = serde_json::to_vec(&version_info)?;
-        let new_hash = get_hash(bytes::Bytes::from(version_bytes.clone())).await?;
+        let version_bytes = crate::common::to_canonical_vec(&version_info)?;
+        let new_hash = BatchUploader::compute_hash(&version_bytes);
 
         let should_upload = if let Some(old_version) = &old_loader_version {
             if let Some(old_hash) = extract_hash_from_cas_url(&old_version.url) {
@@ -614,7 +624,7 @@ impl<S: LoaderStrategy> LoaderProcessor<S> {
         // use a custom manifest format (daedalus::modded::Manifest) that doesn't include
         // release_time. The manifest will be built at the end of retrieve_data() using
         // manifest_builder.set_loader_versions().
-        let base_url = dotenvy::var("BASE_URL").unwrap();
+        let base_url = &config.base_url;
         let cas_url = format!(
             "{}/v{}/objects/{}/{}",
             base_url,
@@ -623,6 +633,8 @@ impl<S: LoaderStrategy> LoaderProcessor<S> {
             &version_hash[2..]
         );
 
+        crate::services::report::record_processed(self.strategy.name(), should_upload);
+
         let mut loader_version_map = loader_version_mutex.lock().await;
         loader_version_map.push(LoaderVersion {
             id: loader,
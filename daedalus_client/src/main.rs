@@ -1,6 +1,7 @@
 use backon::{ExponentialBuilder, Retryable};
+use clap::{Parser, Subcommand};
 use daedalus::Branding;
-use tracing::{error, info, warn, instrument, Instrument};
+use tracing::{error, info, instrument};
 use s3::creds::Credentials;
 use s3::{Bucket, Region};
 use std::ffi::OsStr;
@@ -11,23 +12,9 @@ use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::EnvFilter;
 
-#[cfg(unix)]
-use tokio::signal::unix::{signal, SignalKind};
-
-/// Configuration constants
-/// Update interval for fetching new metadata (1 hour)
-const UPDATE_INTERVAL_SECS: u64 = 60 * 60;
-/// Maximum number of concurrent upload operations
-const MAX_CONCURRENT_UPLOADS: usize = 10;
-/// Circuit breaker: number of consecutive failures before opening
-const CIRCUIT_BREAKER_FAILURE_THRESHOLD: u32 = 5;
-/// Circuit breaker: duration to wait before retrying (5 minutes)
-const CIRCUIT_BREAKER_RESET_TIMEOUT_SECS: u64 = 300;
-/// Maximum number of retry attempts for uploads
-const MAX_UPLOAD_RETRIES: usize = 10;
-/// Maximum delay between retries (30 minutes)
-const MAX_RETRY_DELAY_SECS: u64 = 1800;
+use crate::infrastructure::config::{Config, LogFormat};
 
+mod commands;
 mod common;
 mod fabric;
 mod infrastructure;
@@ -38,39 +25,61 @@ mod neoforge;
 mod quilt;
 mod services;
 
-/// Create a future that completes when a shutdown signal is received (SIGTERM or Ctrl+C)
-async fn shutdown_signal() {
-    let ctrl_c = async {
-        tokio::signal::ctrl_c()
-            .await
-            .expect("failed to install Ctrl+C handler");
-    };
+/// GDLauncher metadata generator
+///
+/// Runs the update daemon when no subcommand is given.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Cli {
+    /// TOML config file (default: `DAEDALUS_CONFIG` or `./daedalus.toml`)
+    #[arg(long, global = true, value_name = "FILE")]
+    config: Option<std::path::PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
 
-    #[cfg(unix)]
-    let terminate = async {
-        signal(SignalKind::terminate())
-            .expect("failed to install signal handler")
-            .recv()
-            .await;
-    };
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Run the update daemon (default)
+    Run(commands::run::RunArgs),
+    /// Run a single processing cycle for every loader
+    Once(commands::run::OnceArgs),
+    /// Run a single processing cycle for the given loaders
+    Only(commands::run::OnlyArgs),
+    /// Check that everything the live root manifest references exists
+    Audit(commands::audit::AuditArgs),
+    /// Delete expired history snapshots and loader manifests
+    Gc(commands::gc::GcArgs),
+    /// Inspect root manifest history and roll back to a snapshot
+    Rollback(commands::rollback::RollbackArgs),
+    /// Show the version changes between two root manifests
+    Diff(commands::diff::DiffArgs),
+    /// Upload the static files from `cdn_upload_dir` (default `./upload_cdn`)
+    UploadStatic,
+    /// Publish the libraries of the live manifests missing from `maven/`
+    MavenBackfill,
+    /// Migrate the published tree of an older CAS version to the current one
+    Migrate(commands::migrate::MigrateArgs),
+    /// Inspect or edit the quarantine of failing versions
+    Quarantine(commands::quarantine::QuarantineArgs),
+    /// Ask the running daemon to run a loader now
+    Trigger(commands::trigger::TriggerArgs),
+}
 
-    #[cfg(not(unix))]
-    let terminate = std::future::pending::<()>();
+fn main() -> Result<(), crate::infrastructure::error::Error> {
+    let cli = Cli::parse();
 
-    tokio::select! {
-        _ = ctrl_c => {
-            info!("Received Ctrl+C signal");
+    let config = match Config::load(cli.config.as_deref()) {
+        Ok(config) => infrastructure::config::init(config),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
         }
-        _ = terminate => {
-            info!("Received SIGTERM signal");
-        }
-    }
-}
+    };
 
-fn main() -> Result<(), crate::infrastructure::error::Error> {
     #[cfg(feature = "sentry")]
     let _guard = sentry::init((
-        dotenvy::var("SENTRY_DSN").unwrap(),
+        config.sentry_dsn.clone(),
         sentry::ClientOptions {
             release: sentry::release_name!(),
             ..Default::default()
@@ -82,9 +91,7 @@ fn main() -> Result<(), crate::infrastructure::error::Error> {
         .build()
         .unwrap()
         .block_on(async {
-            let use_json = dotenvy::var("LOG_FORMAT")
-                .map(|v| v == "json")
-                .unwrap_or(false);
+            let use_json = config.log_format == LogFormat::Json;
 
             let filter = if std::env::var("RUST_LOG").is_ok() {
                 println!("Loaded logger directives from RUST_LOG env");
@@ -93,14 +100,12 @@ fn main() -> Result<(), crate::infrastructure::error::Error> {
                 EnvFilter::new("daedalus_client=info")
             };
 
-            let betterstack_token = dotenvy::var("BETTERSTACK_TOKEN").ok();
-            let _betterstack_handle = if let Some(ref token) = betterstack_token {
-                let betterstack_url = dotenvy::var("BETTERSTACK_URL")
-                    .unwrap_or_else(|_| "https://in.logs.betterstack.com".to_string());
+            let (otlp_layer, telemetry_handle) = infrastructure::telemetry::layer(&config.otlp).unzip();
 
+            let betterstack_handle = if let Some(ref token) = config.betterstack.token {
                 let (betterstack_layer, handle) = services::betterstack::BetterstackLayer::new(
                     token.clone(),
-                    betterstack_url,
+                    config.betterstack.url.clone(),
                     None,
                     None,
                 );
@@ -115,6 +120,7 @@ fn main() -> Result<(), crate::infrastructure::error::Error> {
                         .with_line_number(true);
 
                     tracing_subscriber::registry()
+                        .with(otlp_layer)
                         .with(json_layer)
                         .with(betterstack_layer)
                         .with(filter)
@@ -134,6 +140,7 @@ fn main() -> Result<(), crate::infrastructure::error::Error> {
                         .with_thread_names(true);
 
                     tracing_subscriber::registry()
+                        .with(otlp_layer)
                         .with(pretty_layer)
                         .with(betterstack_layer)
                         .with(filter)
@@ -159,6 +166,7 @@ fn main() -> Result<(), crate::infrastructure::error::Error> {
                         .with_line_number(true);
 
                     tracing_subscriber::registry()
+                        .with(otlp_layer)
                         .with(json_layer)
                         .with(filter)
                         .init();
@@ -176,6 +184,7 @@ fn main() -> Result<(), crate::infrastructure::error::Error> {
                         .with_thread_names(true);
 
                     tracing_subscriber::registry()
+                        .with(otlp_layer)
                         .with(pretty_layer)
                         .with(filter)
                         .init();
@@ -190,389 +199,97 @@ fn main() -> Result<(), crate::infrastructure::error::Error> {
                 None
             };
 
-            if check_env_vars() {
-                return Err(crate::infrastructure::error::invalid_input("Some environment variables are missing!"));
+            if let Some(endpoint) = config.otlp.endpoint.as_ref().filter(|_| telemetry_handle.is_some()) {
+                info!(endpoint = %endpoint, service_name = %config.otlp.service_name, "Exporting traces over OTLP");
             }
 
             Branding::set_branding(Branding::new(
-                dotenvy::var("BRAND_NAME").unwrap(),
-                dotenvy::var("SUPPORT_EMAIL").unwrap(),
+                config.brand_name.clone(),
+                config.support_email.clone(),
             ))
             .unwrap();
 
-            let mut timer = tokio::time::interval(Duration::from_secs(UPDATE_INTERVAL_SECS));
-            let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_UPLOADS));
-
-            {
-                let uploaded_files = Arc::new(Mutex::new(Vec::new()));
-
-                match upload_static_files(&uploaded_files, semaphore.clone())
-                    .await
-                {
-                    Ok(()) => {}
-                    Err(err) => {
-                        error!("{:?}", err);
-                    }
-                }
-            }
+            let semaphore = Arc::new(Semaphore::new(config.daemon.max_concurrent_uploads));
+
+            let command = cli.command.unwrap_or(Command::Run(commands::run::RunArgs::default()));
+            // Commands that publish get time to drain, the others stop on the first signal
+            let shutdown_timeout = match command {
+                Command::Run(_)
+                | Command::Once(_)
+                | Command::Only(_)
+                | Command::Rollback(_)
+                | Command::Migrate(_) => Duration::from_secs(config.daemon.shutdown_timeout_secs),
+                _ => Duration::ZERO,
+            };
+            tokio::spawn(infrastructure::shutdown::listen());
 
-            let mut is_first_run = true;
-
-            loop {
-                // Wait for either timer tick or shutdown signal
-                info!("Waiting for next update timer or shutdown signal");
-                tokio::select! {
-                    _ = timer.tick() => {
-                        // Timer ticked - continue with processing cycle
-                    }
-                    _ = shutdown_signal() => {
-                        info!("Shutdown signal received - exiting gracefully");
-                        break;
-                    }
+            let result = tokio::select! {
+                result = dispatch(command, config, semaphore) => result,
+                _ = infrastructure::shutdown::deadline(shutdown_timeout) => {
+                    error!("Shutdown deadline reached, exiting with work in flight");
+                    Err(infrastructure::error::ErrorKind::Cancelled)
                 }
+            };
 
-                let loop_span = tracing::info_span!("processing_cycle", is_first_run);
-                async {
-                    let uploader = services::upload::BatchUploader::new();
-                    let manifest_builder = services::cas::ManifestBuilder::new();
-
-                    let versions = {
-                        let span = tracing::info_span!("minecraft_processing");
-                        async {
-                            match MINECRAFT_BREAKER.call(async {
-                                minecraft::retrieve_data(
-                                    &uploader,
-                                    &manifest_builder,
-                                    &CLIENT,
-                                    semaphore.clone(),
-                                    is_first_run,
-                                )
-                                .await
-                            })
-                            .await
-                            {
-                                Ok(res) => {
-                                    info!(version_count = res.versions.len(), "Minecraft data retrieved");
-                                    Some(res)
-                                }
-                                Err(crate::infrastructure::circuit_breaker::CircuitBreakerError::Open) => {
-                                    warn!("Minecraft circuit breaker is open, skipping");
-                                    None
-                                }
-                                Err(crate::infrastructure::circuit_breaker::CircuitBreakerError::Failed(err)) => {
-                                    error!(error = %err, "Minecraft processing failed");
-                                    None
-                                }
-                            }
-                        }
-                        .instrument(span)
-                        .await
-                    };
-
-                    if let Some(manifest) = versions {
-                        if cfg!(feature = "fabric") {
-                            let span = tracing::info_span!("fabric_processing");
-                            async {
-                                match FABRIC_BREAKER.call(async {
-                                    fabric::retrieve_data(
-                                        &manifest,
-                                        &uploader,
-                                        &manifest_builder,
-                                        &CLIENT,
-                                        semaphore.clone(),
-                                    )
-                                    .await
-                                })
-                                .await
-                                {
-                                    Ok(_) => info!("Fabric processing completed"),
-                                    Err(crate::infrastructure::circuit_breaker::CircuitBreakerError::Open) => {
-                                        warn!("Fabric circuit breaker is open, skipping");
-                                    }
-                                    Err(crate::infrastructure::circuit_breaker::CircuitBreakerError::Failed(err)) => {
-                                        error!(error = %err, "Fabric processing failed");
-                                    }
-                                }
-                            }
-                            .instrument(span)
-                            .await;
-                        }
-
-                        if cfg!(feature = "forge") {
-                            let span = tracing::info_span!("forge_processing");
-                            async {
-                                match FORGE_BREAKER.call(async {
-                                    forge::retrieve_data(
-                                        &manifest,
-                                        &uploader,
-                                        &manifest_builder,
-                                        &CLIENT,
-                                        semaphore.clone(),
-                                    )
-                                    .await
-                                })
-                                .await
-                                {
-                                    Ok(_) => info!("Forge processing completed"),
-                                    Err(crate::infrastructure::circuit_breaker::CircuitBreakerError::Open) => {
-                                        warn!("Forge circuit breaker is open, skipping");
-                                    }
-                                    Err(crate::infrastructure::circuit_breaker::CircuitBreakerError::Failed(err)) => {
-                                        error!(error = %err, "Forge processing failed");
-                                    }
-                                }
-                            }
-                            .instrument(span)
-                            .await;
-                        }
-
-                        if cfg!(feature = "quilt") {
-                            let span = tracing::info_span!("quilt_processing");
-                            async {
-                                match QUILT_BREAKER.call(async {
-                                    quilt::retrieve_data(
-                                        &manifest,
-                                        &uploader,
-                                        &manifest_builder,
-                                        &CLIENT,
-                                        semaphore.clone(),
-                                    )
-                                    .await
-                                })
-                                .await
-                                {
-                                    Ok(_) => info!("Quilt processing completed"),
-                                    Err(crate::infrastructure::circuit_breaker::CircuitBreakerError::Open) => {
-                                        warn!("Quilt circuit breaker is open, skipping");
-                                    }
-                                    Err(crate::infrastructure::circuit_breaker::CircuitBreakerError::Failed(err)) => {
-                                        error!(error = %err, "Quilt processing failed");
-                                    }
-                                }
-                            }
-                            .instrument(span)
-                            .await;
-                        }
-
-                        if cfg!(feature = "neoforge") {
-                            let span = tracing::info_span!("neoforge_processing");
-                            async {
-                                match NEOFORGE_BREAKER.call(async {
-                                    neoforge::retrieve_data(
-                                        &manifest,
-                                        &uploader,
-                                        &manifest_builder,
-                                        &CLIENT,
-                                        semaphore.clone(),
-                                    )
-                                    .await
-                                })
-                                .await
-                                {
-                                    Ok(_) => info!("NeoForge processing completed"),
-                                    Err(crate::infrastructure::circuit_breaker::CircuitBreakerError::Open) => {
-                                        warn!("NeoForge circuit breaker is open, skipping");
-                                    }
-                                    Err(crate::infrastructure::circuit_breaker::CircuitBreakerError::Failed(err)) => {
-                                        error!(error = %err, "NeoForge processing failed");
-                                    }
-                                }
-                            }
-                            .instrument(span)
-                            .await;
-                        }
-
-                        // All CAS objects have been uploaded immediately during processing
-                        // Now we upload the loader manifests and root manifest atomically
-                        let timestamp = chrono::Utc::now().format("%Y-%m-%dT%H-%M-%SZ").to_string();
-                        let mut loader_references = std::collections::HashMap::new();
-                        let mut uploaded_manifest_urls = Vec::new();
-
-                        let all_loaders = manifest_builder.get_loaders();
-                        info!(loader_count = all_loaders.len(), "Building loader manifests");
-
-                        for loader in &all_loaders {
-                            if let Some(loader_manifest) = manifest_builder.build_loader_manifest(loader) {
-                                let manifest_path = format!("v{}/manifests/{}/{}.json", crate::services::cas::CAS_VERSION, loader, loader_manifest.timestamp);
-
-                                info!(
-                                    loader = %loader,
-                                    version_count = loader_manifest.versions.as_array().map(|a| a.len()).unwrap_or(0),
-                                    path = %manifest_path,
-                                    "Uploading loader manifest"
-                                );
-
-                                match serde_json::to_vec_pretty(&loader_manifest) {
-                                    Ok(manifest_bytes) => {
-                                        match upload_file_to_bucket(
-                                            manifest_path.clone(),
-                                            manifest_bytes,
-                                            Some("application/json".to_string()),
-                                            &tokio::sync::Mutex::new(Vec::new()),
-                                            semaphore.clone(),
-                                        ).await {
-                                            Ok(_) => {
-                                                info!(loader = %loader, "Loader manifest uploaded successfully");
-                                                loader_references.insert(
-                                                    loader.clone(),
-                                                    services::cas::LoaderReference::new(loader, loader_manifest.timestamp.clone())
-                                                );
-                                                uploaded_manifest_urls.push(format!("{}/{}", dotenvy::var("BASE_URL").unwrap(), manifest_path));
-                                            }
-                                            Err(e) => {
-                                                error!(loader = %loader, error = %e, "Failed to upload loader manifest");
-                                            }
-                                        }
-                                    }
-                                    Err(e) => {
-                                        error!(loader = %loader, error = %e, "Failed to serialize loader manifest");
-                                    }
-                                }
-                            }
-                        }
-
-                        if !loader_references.is_empty() {
-                            let root_manifest = services::cas::RootManifest::new(loader_references);
-                            let root_path = format!("v{}/manifest.json", crate::services::cas::CAS_VERSION);
-
-                            info!("Uploading root manifest (atomic commit point)");
-
-                            match serde_json::to_vec_pretty(&root_manifest) {
-                                Ok(root_bytes) => {
-                                    match upload_file_to_bucket(
-                                        root_path.clone(),
-                                        root_bytes.clone(),
-                                        Some("application/json".to_string()),
-                                        &tokio::sync::Mutex::new(Vec::new()),
-                                        semaphore.clone(),
-                                    ).await {
-                                        Ok(_) => {
-                                            info!("Root manifest uploaded successfully - all changes are now live");
-                                            uploaded_manifest_urls.push(format!("{}/{}", dotenvy::var("BASE_URL").unwrap(), root_path));
-                                        }
-                                        Err(e) => {
-                                            error!(error = %e, "Failed to upload root manifest - changes NOT committed");
-                                        }
-                                    }
-
-                                    let backup_path = format!("v{}/history/manifest-{}.json", crate::services::cas::CAS_VERSION, timestamp);
-                                    info!(backup_path = %backup_path, "Creating backup of root manifest");
-
-                                    match upload_file_to_bucket(
-                                        backup_path,
-                                        root_bytes,
-                                        Some("application/json".to_string()),
-                                        &tokio::sync::Mutex::new(Vec::new()),
-                                        semaphore.clone(),
-                                    ).await {
-                                        Ok(_) => info!("Backup created successfully"),
-                                        Err(e) => warn!(error = %e, "Failed to create backup (non-fatal)"),
-                                    }
-                                }
-                                Err(e) => {
-                                    error!(error = %e, "Failed to serialize root manifest");
-                                }
-                            }
-
-                            info!("Processing cycle completed successfully");
-
-                            if !uploaded_manifest_urls.is_empty() {
-                                let cloudflare_enabled = dotenvy::var("CLOUDFLARE_INTEGRATION")
-                                    .map(|v| v == "true")
-                                    .unwrap_or(false);
-
-                                if cloudflare_enabled {
-                                    match (
-                                        dotenvy::var("CLOUDFLARE_TOKEN"),
-                                        dotenvy::var("CLOUDFLARE_ZONE_ID"),
-                                    ) {
-                                        (Ok(token), Ok(zone_id)) => {
-                                            match services::cloudflare::purge_cloudflare_cache(&token, &zone_id, &uploaded_manifest_urls).await {
-                                                Ok(_) => {
-                                                    info!("Cloudflare cache purge successful");
-                                                }
-                                                Err(e) => {
-                                                    warn!(error = %e, "Cloudflare cache purge failed, but continuing");
-                                                }
-                                            }
-                                        }
-                                        _ => {
-                                            warn!(
-                                                "CLOUDFLARE_INTEGRATION is enabled but CLOUDFLARE_TOKEN or \
-                                                 CLOUDFLARE_ZONE_ID is missing"
-                                            );
-                                        }
-                                    }
-                                } else {
-                                    info!("Cloudflare cache purging disabled (set CLOUDFLARE_INTEGRATION=true to enable)");
-                                }
-                            }
-                        } else {
-                            warn!("No loader manifests were built - skipping root manifest upload");
-                        }
-                    }
-
-                    is_first_run = false;
-                }
-                .instrument(loop_span)
-                .await;
+            if let Some(handle) = telemetry_handle {
+                handle.shutdown().await;
+            }
+            if let Some(handle) = betterstack_handle {
+                handle.flush().await;
             }
 
-            info!("Application shutdown complete");
-            Ok(())
+            result
         })
 }
 
-fn check_env_vars() -> bool {
-    let mut failed = false;
-
-    fn check_var<T: std::str::FromStr>(var: &str) -> bool {
-        if dotenvy::var(var)
-            .ok()
-            .and_then(|s| s.parse::<T>().ok())
-            .is_none()
-        {
-            warn!(
-                "Variable `{}` missing in dotenvy or not of type `{}`",
-                var,
-                std::any::type_name::<T>()
-            );
-            true
-        } else {
-            false
+/// Run the selected subcommand
+async fn dispatch(
+    command: Command,
+    config: &'static Config,
+    semaphore: Arc<Semaphore>,
+) -> Result<(), infrastructure::error::Error> {
+    match command {
+        Command::Run(args) => commands::run::run(args, config, semaphore).await,
+        Command::Once(args) => commands::run::once(args, config, semaphore).await,
+        Command::Only(args) => commands::run::only(args, config, semaphore).await,
+        Command::Audit(args) => commands::audit::run(args, &CLIENT).await,
+        Command::Gc(args) => commands::gc::run(args, config, &CLIENT).await,
+        Command::Rollback(args) => {
+            commands::rollback::run(args, config, &CLIENT, semaphore).await
+        }
+        Command::Diff(args) => commands::diff::run(args, &CLIENT).await,
+        Command::UploadStatic => {
+            let uploaded_files = Mutex::new(Vec::new());
+            upload_static_files(&config.cdn_upload_dir, &uploaded_files, semaphore).await?;
+            println!("Uploaded {} static files", uploaded_files.lock().await.len());
+            Ok(())
+        }
+        Command::MavenBackfill => commands::maven::backfill(config, &CLIENT, semaphore).await,
+        Command::Migrate(args) => {
+            commands::migrate::run(args, config, &CLIENT, semaphore).await
         }
+        Command::Quarantine(args) => commands::quarantine::run(args, config, &CLIENT).await,
+        Command::Trigger(args) => commands::trigger::run(args, config).await,
     }
-
-    failed |= check_var::<String>("BASE_URL");
-
-    failed |= check_var::<String>("S3_ACCESS_TOKEN");
-    failed |= check_var::<String>("S3_SECRET");
-    failed |= check_var::<String>("S3_URL");
-    failed |= check_var::<String>("S3_REGION");
-    failed |= check_var::<String>("S3_BUCKET_NAME");
-
-    failed |= check_var::<String>("BRAND_NAME");
-    failed |= check_var::<String>("SUPPORT_EMAIL");
-
-    failed
 }
 
 static CLIENT: LazyLock<Bucket> = LazyLock::new(|| {
+    let s3 = &infrastructure::config::get().s3;
     let bucket = Bucket::new(
-        &dotenvy::var("S3_BUCKET_NAME").unwrap(),
-        if &*dotenvy::var("S3_REGION").unwrap() == "r2" {
+        &s3.bucket_name,
+        if s3.region == "r2" {
             Region::R2 {
-                account_id: dotenvy::var("S3_URL").unwrap(),
+                account_id: s3.url.clone(),
             }
         } else {
             Region::Custom {
-                region: dotenvy::var("S3_REGION").unwrap(),
-                endpoint: dotenvy::var("S3_URL").unwrap(),
+                region: s3.region.clone(),
+                endpoint: s3.url.clone(),
             }
         },
         Credentials::new(
-            Some(&*dotenvy::var("S3_ACCESS_TOKEN").unwrap()),
-            Some(&*dotenvy::var("S3_SECRET").unwrap()),
+            Some(&s3.access_token),
+            Some(&s3.secret),
             None,
             None,
             None,
@@ -584,26 +301,48 @@ static CLIENT: LazyLock<Bucket> = LazyLock::new(|| {
     bucket.with_path_style()
 });
 
+/// Circuit breaker for one loader, using the configured thresholds
+fn circuit_breaker(name: &str) -> crate::infrastructure::circuit_breaker::CircuitBreaker {
+    let config = &infrastructure::config::get().circuit_breaker;
+    crate::infrastructure::circuit_breaker::CircuitBreaker::new(
+        name,
+        config.failure_threshold,
+        Duration::from_secs(config.reset_timeout_secs),
+    )
+}
+
 static MINECRAFT_BREAKER: LazyLock<crate::infrastructure::circuit_breaker::CircuitBreaker> = LazyLock::new(|| {
-    crate::infrastructure::circuit_breaker::CircuitBreaker::new("minecraft", CIRCUIT_BREAKER_FAILURE_THRESHOLD, Duration::from_secs(CIRCUIT_BREAKER_RESET_TIMEOUT_SECS))
+    circuit_breaker("minecraft")
 });
 
 static FORGE_BREAKER: LazyLock<crate::infrastructure::circuit_breaker::CircuitBreaker> = LazyLock::new(|| {
-    crate::infrastructure::circuit_breaker::CircuitBreaker::new("forge", CIRCUIT_BREAKER_FAILURE_THRESHOLD, Duration::from_secs(CIRCUIT_BREAKER_RESET_TIMEOUT_SECS))
+    circuit_breaker("forge")
 });
 
 static FABRIC_BREAKER: LazyLock<crate::infrastructure::circuit_breaker::CircuitBreaker> = LazyLock::new(|| {
-    crate::infrastructure::circuit_breaker::CircuitBreaker::new("fabric", CIRCUIT_BREAKER_FAILURE_THRESHOLD, Duration::from_secs(CIRCUIT_BREAKER_RESET_TIMEOUT_SECS))
+    circuit_breaker("fabric")
 });
 
 static QUILT_BREAKER: LazyLock<crate::infrastructure::circuit_breaker::CircuitBreaker> = LazyLock::new(|| {
-    crate::infrastructure::circuit_breaker::CircuitBreaker::new("quilt", CIRCUIT_BREAKER_FAILURE_THRESHOLD, Duration::from_secs(CIRCUIT_BREAKER_RESET_TIMEOUT_SECS))
+    circuit_breaker("quilt")
 });
 
 static NEOFORGE_BREAKER: LazyLock<crate::infrastructure::circuit_breaker::CircuitBreaker> = LazyLock::new(|| {
-    crate::infrastructure::circuit_breaker::CircuitBreaker::new("neoforge", CIRCUIT_BREAKER_FAILURE_THRESHOLD, Duration::from_secs(CIRCUIT_BREAKER_RESET_TIMEOUT_SECS))
+    circuit_breaker("neoforge")
 });
 
+/// The circuit breaker of the loader `name`
+fn loader_breaker(name: &str) -> Option<&'static crate::infrastructure::circuit_breaker::CircuitBreaker> {
+    match name {
+        "minecraft" => Some(&MINECRAFT_BREAKER),
+        "forge" => Some(&FORGE_BREAKER),
+        "fabric" => Some(&FABRIC_BREAKER),
+        "quilt" => Some(&QUILT_BREAKER),
+        "neoforge" => Some(&NEOFORGE_BREAKER),
+        _ => None,
+    }
+}
+
 #[instrument(skip(bytes, uploaded_files, semaphore), fields(size = bytes.len()))]
 pub async fn upload_file_to_bucket(
     path: String,
@@ -612,7 +351,15 @@ pub async fn upload_file_to_bucket(
     uploaded_files: &tokio::sync::Mutex<Vec<String>>,
     semaphore: Arc<Semaphore>,
 ) -> Result<(), crate::infrastructure::error::Error> {
-    let _permit = semaphore.acquire().await?;
+    if let Some(dir) = services::dry_run::output_dir() {
+        services::dry_run::write(dir, &path, &bytes).await?;
+        uploaded_files.lock().await.push(path);
+        return Ok(());
+    }
+
+    let _permit = crate::infrastructure::metrics::acquire_permit(&semaphore).await?;
+    let retries = &infrastructure::config::get().upload;
+    let client = services::metadata::ObjectMetadata::new(&path, &bytes).apply(&CLIENT);
 
     info!(path = %path, "Started uploading");
 
@@ -620,11 +367,11 @@ pub async fn upload_file_to_bucket(
         let key = path.clone();
 
         let result = if let Some(ref content_type) = content_type {
-            CLIENT
+            client
                 .put_object_with_content_type(key.clone(), &bytes, content_type)
                 .await
         } else {
-            CLIENT.put_object(key.clone(), &bytes).await
+            client.put_object(key.clone(), &bytes).await
         }
         .map_err(|err| {
             error!(path = %path, error = %err, "Failed to upload");
@@ -638,6 +385,7 @@ pub async fn upload_file_to_bucket(
                     uploaded_files.push(key);
                 }
                 info!(path = %path, "Upload completed");
+                crate::services::report::record_uploaded(bytes.len());
 
                 Ok(())
             }
@@ -649,14 +397,16 @@ pub async fn upload_file_to_bucket(
     })
     .retry(
         ExponentialBuilder::default()
-            .with_max_times(MAX_UPLOAD_RETRIES)
-            .with_max_delay(Duration::from_secs(MAX_RETRY_DELAY_SECS)),
+            .with_max_times(retries.max_retries)
+            .with_max_delay(Duration::from_secs(retries.max_retry_delay_secs)),
     )
+    .when(|_| !infrastructure::shutdown::is_requested())
+    .notify(|_, _| crate::infrastructure::metrics::record_retry("upload"))
     .await
 }
 
 pub fn format_url(path: &str) -> String {
-    let base_url = &*dotenvy::var("BASE_URL").unwrap();
+    let base_url = &infrastructure::config::get().base_url;
     let full_url = format!("{}/{}", base_url, path);
     info!(path = %path, url = %full_url, "Formatted URL");
     full_url
@@ -666,20 +416,19 @@ pub use services::download::{download_file, download_file_mirrors};
 
 #[instrument(skip(uploaded_files, semaphore))]
 pub async fn upload_static_files(
+    cdn_upload_dir: &std::path::Path,
     uploaded_files: &tokio::sync::Mutex<Vec<String>>,
     semaphore: Arc<Semaphore>,
 ) -> Result<(), crate::infrastructure::error::Error> {
     use path_slash::PathExt as _;
-    let cdn_upload_dir =
-        dotenvy::var("CDN_UPLOAD_DIR").unwrap_or("./upload_cdn".to_string());
 
-    info!(dir = %cdn_upload_dir, "Uploading static files");
+    info!(dir = %cdn_upload_dir.display(), "Uploading static files");
 
-    if !std::path::Path::new(&cdn_upload_dir).exists() {
-        panic!("CDN_UPLOAD_DIR does not exist");
+    if !cdn_upload_dir.exists() {
+        panic!("cdn_upload_dir does not exist");
     }
 
-    for entry in walkdir::WalkDir::new(&cdn_upload_dir) {
+    for entry in walkdir::WalkDir::new(cdn_upload_dir) {
         let entry = entry.map_err(|e| {
             crate::infrastructure::error::ErrorKind::Io(std::io::Error::new(
                 std::io::ErrorKind::Other,
@@ -688,7 +437,7 @@ pub async fn upload_static_files(
         })?;
         if entry.path().is_file() {
             let upload_path = entry.path()
-                .strip_prefix(&cdn_upload_dir)
+                .strip_prefix(cdn_upload_dir)
                 .expect("Unwrap to be safe because we are striping the prefix to the directory walked")
                  .to_slash()
                 .ok_or_else(|| {
@@ -57,31 +57,29 @@ pub fn patch_library(patches: &[LibraryPatch], mut library: Library) -> Vec<Libr
 
 /// Fetch library patches from embedded JSON file
 pub async fn get_library_patches(
+    base_url: &str,
 ) -> Result<Vec<LibraryPatch>, crate::infrastructure::error::Error> {
     let patches = include_bytes!("../../patched-library-patches.json");
     let unprocessed_patches: Vec<LibraryPatch> = serde_json::from_slice(patches)?;
-    Ok(unprocessed_patches.iter().map(pre_process_patch).collect())
+    Ok(unprocessed_patches.iter().map(|patch| pre_process_patch(patch, base_url)).collect())
 }
 
 /// Pre-process a patch by replacing ${BASE_URL} placeholders
-fn pre_process_patch(patch: &LibraryPatch) -> LibraryPatch {
-    fn patch_url(url: &mut String) {
-        *url = url.replace(
-            "${BASE_URL}",
-            &dotenvy::var("BASE_URL").expect("BASE_URL must be set"),
-        );
+fn pre_process_patch(patch: &LibraryPatch, base_url: &str) -> LibraryPatch {
+    fn patch_url(url: &mut String, base_url: &str) {
+        *url = url.replace("${BASE_URL}", base_url);
     }
 
-    fn patch_downloads(downloads: &mut LibraryDownloads) {
+    fn patch_downloads(downloads: &mut LibraryDownloads, base_url: &str) {
         if let Some(artifact) = downloads.artifact.as_mut() {
             if let Some(url) = artifact.url.as_mut() {
-                patch_url(url);
+                patch_url(url, base_url);
             }
         }
         if let Some(classifiers) = downloads.classifiers.as_mut() {
             for (_, artifact) in classifiers.iter_mut() {
                 if let Some(url) = artifact.url.as_mut() {
-                    patch_url(url);
+                    patch_url(url, base_url);
                 }
             }
         }
@@ -91,16 +89,16 @@ fn pre_process_patch(patch: &LibraryPatch) -> LibraryPatch {
     if let Some(libraries) = patch_copy.additional_libraries.as_mut() {
         for lib in libraries.iter_mut() {
             if let Some(downloads) = lib.downloads.as_mut() {
-                patch_downloads(downloads);
+                patch_downloads(downloads, base_url);
             }
         }
     }
     if let Some(override_) = patch_copy.override_.as_mut() {
         if let Some(url) = override_.url.as_mut() {
-            patch_url(url);
+            patch_url(url, base_url);
         }
         if let Some(downloads) = override_.downloads.as_mut() {
-            patch_downloads(downloads);
+            patch_downloads(downloads, base_url);
         }
     }
     patch_copy
@@ -29,6 +29,7 @@ pub use types::LibraryPatch;
 
 use crate::download_file;
 use crate::format_url;
+use crate::infrastructure::config::Config;
 use crate::services::upload::BatchUploader;
 use dashmap::DashSet;
 use daedalus::minecraft::{JavaVersion, MinecraftJavaProfile, VersionManifest};
@@ -37,7 +38,7 @@ use std::convert::TryFrom;
 use std::sync::Arc;
 use std::time::Instant;
 use tokio::sync::{Mutex, Semaphore};
-use tracing::{info, warn};
+use tracing::{info, warn, Instrument};
 
 /// Retrieve and process all Minecraft version data
 ///
@@ -55,31 +56,31 @@ use tracing::{info, warn};
 /// # Arguments
 /// - `uploader`: Batch uploader for CAS uploads
 /// - `manifest_builder`: CAS manifest builder for tracking versions
+/// - `config`: Configuration, for the public base URL
 /// - `s3_client`: S3 bucket client for uploads
 /// - `semaphore`: Concurrency control semaphore
-/// - `is_first_run`: Whether this is the first run (skips old manifest loading)
+/// - `previous`: Previously published state, used to skip unchanged versions
+/// - `is_first_run`: Whether this is the first run (ignores the previous state)
 ///
 /// # Returns
 /// The processed Minecraft version manifest with all versions and metadata
 pub async fn retrieve_data(
     uploader: &BatchUploader,
     manifest_builder: &crate::services::cas::ManifestBuilder,
+    config: &Config,
     s3_client: &s3::Bucket,
     semaphore: Arc<Semaphore>,
+    previous: &crate::services::previous_state::PreviousState,
     is_first_run: bool,
 ) -> Result<VersionManifest, crate::infrastructure::error::Error> {
     info!(is_first_run = is_first_run, "Retrieving Minecraft data");
 
-    // TODO: Old manifest doesn't take LWJGL meta into account
-    let old_manifest = if is_first_run {
-        None
+    // Library patches may have changed since the last deploy, so the first run
+    // reprocesses every version instead of trusting the previous state
+    let old_versions = if is_first_run {
+        Vec::new()
     } else {
-        daedalus::minecraft::fetch_version_manifest(Some(&format_url(&format!(
-            "minecraft/v{}/manifest.json",
-            daedalus::minecraft::CURRENT_FORMAT_VERSION
-        ))))
-        .await
-        .ok()
+        previous.minecraft_versions()
     };
 
     let mut manifest = daedalus::minecraft::fetch_version_manifest(None).await?;
@@ -88,7 +89,7 @@ pub async fn retrieve_data(
 
     // Own the patches and share as an Arc slice to avoid borrowed refs in futures
     let patches: Arc<[LibraryPatch]> =
-        Arc::from(library_patches::get_library_patches().await?.into_boxed_slice());
+        Arc::from(library_patches::get_library_patches(&config.base_url).await?.into_boxed_slice());
 
     let visited_assets = Arc::new(DashSet::new());
 
@@ -98,26 +99,38 @@ pub async fn retrieve_data(
 
     for version in manifest.versions.iter_mut().rev() {
         version_futures.push(async {
-            let old_version = if let Some(old_manifest) = &old_manifest {
-                old_manifest.versions.iter().find(|x| x.id == version.id)
-            } else {
-                None
-            };
+            let old_version = old_versions.iter().find(|x| x.id == version.id);
 
+            // The published sha1 is our CAS hash, not Mojang's, so the upstream
+            // `time` (last modified) is what tells us the version JSON changed
             if let Some(old_version) = old_version {
-                if old_version.sha1 == version.sha1 {
+                if old_version.time == version.time
+                    && crate::common::extract_hash_from_cas_url(&old_version.url).is_some()
+                {
+                    let mut cloned_manifest = cloned_manifest.lock().await;
+                    if let Some(position) = cloned_manifest
+                        .versions
+                        .iter()
+                        .position(|x| x.id == version.id)
+                    {
+                        cloned_manifest.versions[position] = old_version.clone();
+                    }
+                    crate::services::report::record_processed("minecraft", false);
                     return Ok(());
                 }
             }
 
+            // Changed versions are not started once a shutdown was requested
+            crate::infrastructure::shutdown::check()?;
+
             let visited_assets = Arc::clone(&visited_assets);
             let cloned_manifest_mutex = Arc::clone(&cloned_manifest);
             let semaphore = Arc::clone(&semaphore);
             let patches = Arc::clone(&patches);
+            let span = tracing::info_span!("minecraft_version", version = %version.id);
+            let version_id = version.id.clone();
 
-            let assets_hash = old_version.and_then(|x| x.assets_index_sha1.clone());
-
-            async move {
+            let result = async move {
                 let mut version_info = daedalus::minecraft::fetch_version_info(version).await?;
 
                 // Process libraries: apply patches (including LWJGL fixes)
@@ -212,19 +225,7 @@ pub async fn retrieve_data(
                 );
                 let assets_index_url = version_info.asset_index.url.clone();
 
-                let mut download_assets = false;
-
                 if visited_assets.insert(version_info.asset_index.id.clone()) {
-                    if let Some(assets_hash) = assets_hash {
-                        if version_info.asset_index.sha1 != assets_hash {
-                            download_assets = true;
-                        }
-                    } else {
-                        download_assets = true;
-                    }
-                }
-
-                if download_assets {
                     let assets_index = download_file(
                         &assets_index_url,
                         Some(&version_info.asset_index.sha1),
@@ -242,7 +243,7 @@ pub async fn retrieve_data(
                         )
                         .await?;
 
-                    let base_url = dotenvy::var("BASE_URL").unwrap();
+                    let base_url = &config.base_url;
                     version_info.asset_index.url = format!(
                         "{}/v{}/objects/{}/{}",
                         base_url,
@@ -252,7 +253,7 @@ pub async fn retrieve_data(
                     );
                 }
 
-                let version_bytes = serde_json::to_vec(&version_info)?;
+                let version_bytes = crate::common::to_canonical_vec(&version_info)?;
                 let version_hash = uploader
                     .upload_cas(
                         version_bytes.clone(),
@@ -271,7 +272,7 @@ pub async fn retrieve_data(
                         .iter()
                         .position(|x| version.id == x.id)
                     {
-                        let base_url = dotenvy::var("BASE_URL").unwrap();
+                        let base_url = &config.base_url;
                         cloned_manifest.versions[position].url = format!(
                             "{}/v{}/objects/{}/{}",
                             base_url,
@@ -291,7 +292,7 @@ pub async fn retrieve_data(
                             });
                         cloned_manifest.versions[position].sha1 = version_hash.clone();
                     } else {
-                        let base_url = dotenvy::var("BASE_URL").unwrap();
+                        let base_url = &config.base_url;
                         cloned_manifest.versions.insert(
                             0,
                             daedalus::minecraft::Version {
@@ -326,9 +327,14 @@ pub async fn retrieve_data(
 
                 Ok::<(), crate::infrastructure::error::Error>(())
             }
-            .await?;
+            .instrument(span)
+            .await;
 
-            Ok::<(), crate::infrastructure::error::Error>(())
+            match &result {
+                Ok(()) => crate::services::report::record_processed("minecraft", true),
+                Err(err) => crate::services::report::record_failure("minecraft", &version_id, err),
+            }
+            result
         })
     }
 
@@ -369,6 +375,7 @@ pub async fn retrieve_data(
             "📊 Minecraft - Processing complete: {} successful, {} failed",
             successful, failed
         );
+        crate::infrastructure::metrics::record_versions("minecraft", successful, failed);
     }
 
     let elapsed = now.elapsed();
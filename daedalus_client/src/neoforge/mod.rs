@@ -6,6 +6,7 @@
 pub mod types;
 
 use crate::{download_file, format_url};
+use crate::infrastructure::config::Config;
 use crate::services::upload::BatchUploader;
 use crate::common::{change_detection::detect_version_change, manifest_merge::{merge_loader_versions, sort_by_minecraft_order, sort_loaders_by_metadata}};
 use dashmap::DashSet;
@@ -13,56 +14,33 @@ use daedalus::minecraft::{Library, VersionManifest};
 use daedalus::modded::{
     LoaderVersion, PartialVersionInfo, Processor, SidedDataEntry,
 };
-use daedalus::get_hash;
-use tracing::{info, warn};
+use tracing::{info, warn, Instrument};
 // Note: Using lenient_semver instead of semver::Version to handle
 // non-standard NeoForge versions like "26.1.0.0-alpha.1+snapshot-1"
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::io::Read;
-use std::sync::{Arc, LazyLock};
+use std::sync::Arc;
 use std::time::Instant;
 use tokio::sync::{Mutex, Semaphore};
 
 // Re-export types
 pub use types::NeoForgeInstallerProfile;
 
-/// Skip list for known broken NeoForge/Forge versions
-/// These versions have permanent issues (missing files, corrupted archives, etc.)
-static NEOFORGE_SKIP_LIST: LazyLock<HashSet<&'static str>> = LazyLock::new(|| {
-    vec![
-        // Unreachable / 404 versions (synced from Modrinth daedalus)
-        "1.20.1-47.1.7",
-        "47.1.82",
-    ]
-    .into_iter()
-    .collect()
-});
-
 pub async fn retrieve_data(
     minecraft_versions: &VersionManifest,
     uploader: &BatchUploader,
     manifest_builder: &crate::services::cas::ManifestBuilder,
+    config: &Config,
     s3_client: &s3::Bucket,
     semaphore: Arc<Semaphore>,
+    previous: &crate::services::previous_state::PreviousState,
 ) -> Result<(), crate::infrastructure::error::Error> {
     info!("Retrieving NeoForge data ...");
 
     let maven_metadata = fetch_maven_metadata(semaphore.clone()).await?;
-    let old_manifest = daedalus::modded::fetch_manifest(&format_url(&format!(
-        "neoforge/v{}/manifest.json",
-        crate::services::cas::CAS_VERSION,
-    )))
-    .await
-    .ok();
-
-    let old_versions =
-        Arc::new(Mutex::new(if let Some(old_manifest) = old_manifest {
-            old_manifest.game_versions
-        } else {
-            Vec::new()
-        }));
+    let old_versions = Arc::new(Mutex::new(previous.game_versions("neoforge")));
 
     let versions = Arc::new(Mutex::new(Vec::new()));
 
@@ -93,11 +71,12 @@ pub async fn retrieve_data(
                         let visited_assets = Arc::clone(&visited_assets);
                         let semaphore = Arc::clone(&semaphore);
                         let minecraft_version = minecraft_version.clone();
+                        let span = tracing::info_span!("neoforge_version", version = %loader_version_full);
+                        let version_id = loader_version_full.clone();
 
-                        async move {
-                            // Check skip list first
-                            if NEOFORGE_SKIP_LIST.contains(loader_version_full.as_str()) {
-                                info!("⏭️  NeoForge - Skipping excluded version: {}", loader_version_full);
+                        let result = async move {
+                            if crate::services::quarantine::skips("neoforge", &loader_version_full) {
+                                info!("⏭️  NeoForge - Skipping quarantined version: {}", loader_version_full);
                                 return Ok::<Option<LoaderVersion>, crate::infrastructure::error::Error>(None);
                             }
 
@@ -289,15 +268,15 @@ pub async fn retrieve_data(
 
                                     if let Some(bytes) = artifact_bytes {
                                         // Upload to CAS and get hash
-                                        let hash = uploader.upload_cas(
+                                        let hash = uploader.upload_maven_artifact(
                                             bytes.to_vec(),
-                                            Some("application/java-archive".to_string()),
+                                            artifact_path,
                                             s3_client,
                                             semaphore.clone(),
                                         ).await?;
 
                                         // Use common CAS URL building
-                                        let cas_url = crate::common::cas::build_cas_url(&hash)?;
+                                        let cas_url = crate::common::cas::build_cas_url(&config.base_url, &hash)?;
 
                                         // Update library URL with CAS URL
                                         if let Some(ref mut downloads) = lib.downloads {
@@ -330,8 +309,8 @@ pub async fn retrieve_data(
                                     logging: None
                                 };
 
-                                let version_bytes = serde_json::to_vec(&new_profile)?;
-                                let new_hash = get_hash(bytes::Bytes::from(version_bytes.clone())).await?;
+                                let version_bytes = crate::common::to_canonical_vec(&new_profile)?;
+                                let new_hash = BatchUploader::compute_hash(&version_bytes);
 
                                 let old_loader_version = {
                                     let versions = versions_mutex.lock().await;
@@ -362,8 +341,9 @@ pub async fn retrieve_data(
                                 };
 
                                 // Use common CAS URL building
-                                let cas_url = crate::common::cas::build_cas_url(&version_hash)?;
+                                let cas_url = crate::common::cas::build_cas_url(&config.base_url, &version_hash)?;
 
+                                crate::services::report::record_processed("neoforge", should_upload);
                                 return Ok(Some(LoaderVersion {
                                     id: loader_version_full,
                                     url: cas_url,
@@ -372,7 +352,17 @@ pub async fn retrieve_data(
                             }
 
                             Ok(None)
-                        }.await
+                        }.instrument(span).await;
+
+                        match &result {
+                            Ok(Some(_)) => crate::services::quarantine::record_success("neoforge", &version_id),
+                            Ok(None) => {}
+                            Err(err) => {
+                                crate::services::report::record_failure("neoforge", &version_id, err);
+                                crate::services::quarantine::record_failure(&config.quarantine, "neoforge", &version_id, err);
+                            }
+                        }
+                        result
                     });
 
                     {
@@ -383,6 +373,9 @@ pub async fn retrieve_data(
                         let mut failed = 0;
 
                         while versions.peek().is_some() {
+                            // Stop starting versions once a shutdown was requested
+                            crate::infrastructure::shutdown::check()?;
+
                             let now = Instant::now();
 
                             let chunk: Vec<_> = versions.by_ref().take(1).collect();
@@ -414,6 +407,7 @@ pub async fn retrieve_data(
                         if failed > 0 {
                             warn!("⚠️  NeoForge - Skipped {} versions due to errors, {} succeeded", failed, successful);
                         }
+                        crate::infrastructure::metrics::record_versions("neoforge", successful, failed);
                     }
                 }
 
@@ -437,6 +431,9 @@ pub async fn retrieve_data(
         let mut failed_mc_versions = 0;
 
         while versions.peek().is_some() {
+            // Stop starting versions once a shutdown was requested
+            crate::infrastructure::shutdown::check()?;
+
             let now = Instant::now();
 
             let chunk: Vec<_> = versions.by_ref().take(1).collect();
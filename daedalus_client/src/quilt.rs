@@ -1,4 +1,5 @@
 use crate::loaders::quilt::{QuiltStrategy, QuiltVersions};
+use crate::infrastructure::config::Config;
 use crate::loaders::LoaderProcessor;
 use crate::services::upload::BatchUploader;
 use daedalus::minecraft::VersionManifest;
@@ -14,11 +15,13 @@ pub async fn retrieve_data(
     minecraft_versions: &VersionManifest,
     uploader: &BatchUploader,
     manifest_builder: &crate::services::cas::ManifestBuilder,
+    config: &Config,
     s3_client: &s3::Bucket,
     semaphore: Arc<Semaphore>,
+    previous: &crate::services::previous_state::PreviousState,
 ) -> Result<(), crate::infrastructure::error::Error> {
     let processor = LoaderProcessor::new(QuiltStrategy);
     processor
-        .retrieve_data::<QuiltVersions>(minecraft_versions, uploader, manifest_builder, s3_client, semaphore)
+        .retrieve_data::<QuiltVersions>(minecraft_versions, uploader, manifest_builder, config, s3_client, semaphore, previous)
         .await
 }
@@ -1,11 +1,20 @@
 use serde_json::Value;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, oneshot};
+use tracing::span::{Attributes, Id, Record};
 use tracing::{info, warn};
 use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
 use tracing_subscriber::Layer;
 
+/// Records waiting to be shipped, events beyond it are dropped
+const CHANNEL_CAPACITY: usize = 10_000;
+
+/// How long the final flush may take on shutdown
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// Betterstack log shipping layer
 ///
 /// This layer captures tracing events and ships them to Betterstack's HTTP log ingestion API.
@@ -16,11 +25,17 @@ use tracing_subscriber::Layer;
 /// - Background flush task (configurable interval)
 /// - Graceful error handling (won't crash app on logging failures)
 /// - JSON format compatible with Betterstack API
+/// - Fields of the enclosing spans (`loader`, `version`, `path`, `cycle_id`,
+///   ...) merged into every record, with file, line, thread and the run ID
+///   of the process
+/// - A bounded queue: when shipping falls behind, new records are dropped
+///   and counted instead of piling up in memory
 pub struct BetterstackLayer {
-    /// Shared buffer for batching logs
-    buffer: Arc<Mutex<Vec<Value>>>,
-    /// Maximum batch size before forcing a flush
-    batch_size: usize,
+    sender: mpsc::Sender<Value>,
+    /// Records dropped because the queue was full, since the last report
+    dropped: Arc<AtomicU64>,
+    /// Identifies this process in every record
+    run_id: String,
 }
 
 impl BetterstackLayer {
@@ -33,88 +48,180 @@ impl BetterstackLayer {
     /// * `flush_interval` - Duration between automatic flushes (default: 5 seconds)
     ///
     /// # Returns
-    /// A tuple of (layer, flush_handle) where the handle can be used to await graceful shutdown
+    /// A tuple of (layer, handle) where the handle flushes the remaining logs on shutdown
     pub fn new(
         token: String,
         url: String,
         batch_size: Option<usize>,
         flush_interval: Option<Duration>,
-    ) -> (Self, tokio::task::JoinHandle<()>) {
+    ) -> (Self, BetterstackHandle) {
         let batch_size = batch_size.unwrap_or(100);
         let flush_interval = flush_interval.unwrap_or(Duration::from_secs(5));
-        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let (sender, receiver) = mpsc::channel(CHANNEL_CAPACITY);
+        let (stop, stopped) = oneshot::channel();
+        let layer = Self::with_sender(sender, run_id());
 
-        let flush_handle = {
-            let buffer_clone = Arc::clone(&buffer);
-            tokio::spawn(async move {
-                flush_loop(buffer_clone, token, url, flush_interval).await;
-            })
-        };
-
-        let layer = Self {
-            buffer,
+        let shipper = Shipper {
+            client: reqwest::Client::new(),
+            token,
+            url,
             batch_size,
+            dropped: Arc::clone(&layer.dropped),
         };
+        let task = tokio::spawn(shipper.run(receiver, flush_interval, stopped));
 
-        (layer, flush_handle)
+        (layer, BetterstackHandle { stop, task })
     }
 
-    /// Add a log event to the buffer
-    async fn enqueue(&self, event: Value) {
-        let mut buffer = self.buffer.lock().await;
-        buffer.push(event);
+    fn with_sender(sender: mpsc::Sender<Value>, run_id: String) -> Self {
+        Self {
+            sender,
+            dropped: Arc::new(AtomicU64::new(0)),
+            run_id,
+        }
+    }
+}
+
+/// Owner of the background shipping task
+///
+/// Logs still queued when the process exits are lost unless [`flush`](Self::flush) is awaited.
+pub struct BetterstackHandle {
+    stop: oneshot::Sender<()>,
+    task: tokio::task::JoinHandle<()>,
+}
 
-        // Flush if batch is full
-        if buffer.len() >= self.batch_size {
-            drop(buffer); // Release lock before flushing
-            // Note: Actual flush happens in background task
+impl BetterstackHandle {
+    /// Ship whatever is still queued and stop the background task
+    pub async fn flush(self) {
+        let _ = self.stop.send(());
+        if tokio::time::timeout(FLUSH_TIMEOUT, self.task).await.is_err() {
+            eprintln!(
+                "Flushing logs to Betterstack did not finish within {}s, remaining logs dropped",
+                FLUSH_TIMEOUT.as_secs()
+            );
         }
     }
 }
 
+/// Fields recorded on a span, kept in its extensions
+struct SpanFields(serde_json::Map<String, Value>);
+
 impl<S> Layer<S> for BetterstackLayer
 where
-    S: tracing::Subscriber,
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
 {
-    fn on_event(&self, event: &tracing::Event<'_>, _ctx: Context<'_, S>) {
-        // Convert tracing event to JSON
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
         let mut visitor = JsonVisitor::new();
+        attrs.record(&mut visitor);
+        span.extensions_mut().insert(SpanFields(visitor.fields));
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut extensions = span.extensions_mut();
+        if let Some(SpanFields(fields)) = extensions.get_mut::<SpanFields>() {
+            let mut visitor = JsonVisitor {
+                fields: std::mem::take(fields),
+            };
+            values.record(&mut visitor);
+            *fields = visitor.fields;
+        }
+    }
+
+    fn on_event(&self, event: &tracing::Event<'_>, ctx: Context<'_, S>) {
+        // Outer spans first, so inner span fields and then the event's own win
+        let mut fields = serde_json::Map::new();
+        let mut spans = Vec::new();
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                spans.push(Value::String(span.name().to_string()));
+                if let Some(SpanFields(span_fields)) = span.extensions().get::<SpanFields>() {
+                    fields.extend(span_fields.clone());
+                }
+            }
+        }
+
+        let mut visitor = JsonVisitor { fields };
         event.record(&mut visitor);
 
+        let metadata = event.metadata();
+        let thread = std::thread::current();
         let json_event = serde_json::json!({
             "timestamp": chrono::Utc::now().to_rfc3339(),
-            "level": format!("{:?}", event.metadata().level()).to_lowercase(),
-            "target": event.metadata().target(),
+            "level": format!("{:?}", metadata.level()).to_lowercase(),
+            "target": metadata.target(),
+            "file": metadata.file(),
+            "line": metadata.line(),
+            "thread": thread.name().map(str::to_string).unwrap_or_else(|| format!("{:?}", thread.id())),
+            "run_id": self.run_id,
+            "spans": spans,
             "fields": visitor.fields,
         });
 
-        // Enqueue asynchronously (spawn to avoid blocking)
-        let buffer = Arc::clone(&self.buffer);
-        let batch_size = self.batch_size;
-        tokio::spawn(async move {
-            let layer = BetterstackLayer { buffer, batch_size };
-            layer.enqueue(json_event).await;
-        });
+        if self.sender.try_send(json_event).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            crate::infrastructure::metrics::BETTERSTACK_DROPPED.inc();
+        }
     }
 }
 
-/// Background flush loop
-async fn flush_loop(buffer: Arc<Mutex<Vec<Value>>>, token: String, url: String, interval: Duration) {
-    let mut timer = tokio::time::interval(interval);
-    let client = reqwest::Client::new();
+/// Background side of the layer, batching queued records
+struct Shipper {
+    client: reqwest::Client,
+    token: String,
+    url: String,
+    batch_size: usize,
+    dropped: Arc<AtomicU64>,
+}
 
-    loop {
-        timer.tick().await;
+impl Shipper {
+    /// Ship full batches right away and partial ones every `interval`, until stopped
+    async fn run(self, mut receiver: mpsc::Receiver<Value>, interval: Duration, mut stopped: oneshot::Receiver<()>) {
+        let mut timer = tokio::time::interval(interval);
+        let mut batch = Vec::with_capacity(self.batch_size);
 
-        let logs = {
-            let mut buffer = buffer.lock().await;
-            if buffer.is_empty() {
-                continue;
+        loop {
+            tokio::select! {
+                record = receiver.recv() => {
+                    let Some(record) = record else { break };
+                    batch.push(record);
+                    if batch.len() >= self.batch_size {
+                        self.ship(std::mem::take(&mut batch)).await;
+                    }
+                }
+                _ = timer.tick() => {
+                    if !batch.is_empty() {
+                        self.ship(std::mem::take(&mut batch)).await;
+                    }
+                }
+                _ = &mut stopped => break,
             }
-            std::mem::take(&mut *buffer)
-        };
+        }
 
-        if let Err(e) = ship_logs(&client, &token, &url, &logs).await {
+        // Final flush: everything queued so far, errors go to stderr as
+        // logging is shutting down
+        while let Ok(record) = receiver.try_recv() {
+            batch.push(record);
+        }
+        for logs in batch.chunks(self.batch_size.max(1)) {
+            if let Err(e) = ship_logs(&self.client, &self.token, &self.url, logs).await {
+                eprintln!("Failed to flush {} logs to Betterstack on shutdown: {}", logs.len(), e);
+            }
+        }
+    }
+
+    async fn ship(&self, logs: Vec<Value>) {
+        let dropped = self.dropped.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            warn!(dropped, "Betterstack queue was full, logs dropped");
+        }
+
+        if let Err(e) = ship_logs(&self.client, &self.token, &self.url, &logs).await {
             warn!(
                 error = %e,
                 log_count = logs.len(),
@@ -126,6 +233,15 @@ async fn flush_loop(buffer: Arc<Mutex<Vec<Value>>>, token: String, url: String,
     }
 }
 
+/// Identifies the records of this process, `<pid>-<start time>`
+fn run_id() -> String {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default();
+    format!("{:x}-{:x}", std::process::id(), nanos)
+}
+
 /// Ship logs to Betterstack HTTP API
 async fn ship_logs(
     client: &reqwest::Client,
@@ -191,6 +307,13 @@ impl tracing::field::Visit for JsonVisitor {
             .insert(field.name().to_string(), Value::Number(value.into()));
     }
 
+    fn record_f64(&mut self, field: &tracing::field::Field, value: f64) {
+        if let Some(number) = serde_json::Number::from_f64(value) {
+            self.fields
+                .insert(field.name().to_string(), Value::Number(number));
+        }
+    }
+
     fn record_bool(&mut self, field: &tracing::field::Field, value: bool) {
         self.fields
             .insert(field.name().to_string(), Value::Bool(value));
@@ -200,6 +323,7 @@ impl tracing::field::Visit for JsonVisitor {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
 
     #[test]
     fn test_json_visitor_basic() {
@@ -213,4 +337,37 @@ mod tests {
         let _runtime = tokio::runtime::Runtime::new().unwrap();
         // Layer creation happens in async context in real usage
     }
+
+    #[test]
+    fn test_event_carries_span_context() {
+        let (sender, mut receiver) = mpsc::channel(1);
+        let layer = BetterstackLayer::with_sender(sender, "run-1".to_string());
+        let dropped = Arc::clone(&layer.dropped);
+        let subscriber = tracing_subscriber::registry().with(layer);
+
+        tracing::subscriber::with_default(subscriber, || {
+            let cycle = tracing::info_span!("processing_cycle", cycle_id = "abc", loader = tracing::field::Empty);
+            let _cycle = cycle.enter();
+            cycle.record("loader", "forge");
+            let version = tracing::info_span!("forge_version", version = "1.20.1-47.1.0");
+            let _version = version.enter();
+
+            tracing::info!(path = "v3/objects/ab/cd", "Uploading");
+            // The queue holds one record, the next one is dropped
+            tracing::info!("Dropped");
+        });
+
+        let record = receiver.try_recv().unwrap();
+        assert_eq!(record["run_id"], "run-1");
+        assert_eq!(record["spans"], serde_json::json!(["processing_cycle", "forge_version"]));
+        assert_eq!(record["fields"]["cycle_id"], "abc");
+        assert_eq!(record["fields"]["loader"], "forge");
+        assert_eq!(record["fields"]["version"], "1.20.1-47.1.0");
+        assert_eq!(record["fields"]["path"], "v3/objects/ab/cd");
+        assert_eq!(record["fields"]["message"], "Uploading");
+        assert!(record["file"].as_str().unwrap().ends_with("betterstack.rs"));
+        assert!(record["line"].is_number());
+
+        assert_eq!(dropped.load(Ordering::Relaxed), 1);
+    }
 }
@@ -0,0 +1,196 @@
+use crate::infrastructure::error::{invalid_input, s3_error, Error, ErrorKind};
+use crate::services::metadata::ObjectMetadata;
+use reqwest::header::{HeaderValue, IF_MATCH, IF_NONE_MATCH};
+use s3::error::S3Error;
+use s3::Bucket;
+use std::collections::HashMap;
+use tracing::{debug, instrument};
+
+/// Read an object straight from the bucket
+///
+/// Reads bypass the CDN so we always see the latest committed state rather
+/// than a cached copy.
+///
+/// # Returns
+///
+/// * `Ok(Some(bytes))` - The object exists
+/// * `Ok(None)` - The object does not exist (HTTP 404)
+/// * `Err(_)` - Any other S3 failure
+#[instrument(skip(s3_client))]
+pub async fn get_object(
+    s3_client: &Bucket,
+    path: &str,
+) -> Result<Option<Vec<u8>>, Error> {
+    Ok(get_object_with_etag(s3_client, path)
+        .await?
+        .map(|(bytes, _)| bytes))
+}
+
+/// An object and the ETag it was read at
+pub type TaggedObject = (Vec<u8>, Option<String>);
+
+/// Read an object along with its ETag, for a later conditional write
+#[instrument(skip(s3_client))]
+pub async fn get_object_with_etag(
+    s3_client: &Bucket,
+    path: &str,
+) -> Result<Option<TaggedObject>, Error> {
+    match s3_client.get_object(path).await {
+        Ok(response) if response.status_code() == 404 => {
+            debug!(path = %path, "Object not found");
+            Ok(None)
+        }
+        Ok(response) => {
+            let etag = response.headers().get("etag").cloned();
+            Ok(Some((response.to_vec(), etag)))
+        }
+        Err(S3Error::Http(404, _)) => {
+            debug!(path = %path, "Object not found");
+            Ok(None)
+        }
+        Err(err) => Err(s3_error(err, path)),
+    }
+}
+
+/// Precondition of a conditional write
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WriteCondition {
+    /// Write unconditionally
+    Always,
+    /// Only create the object (`If-None-Match: *`)
+    IfAbsent,
+    /// Only replace the object if it is still at this ETag (`If-Match`)
+    IfMatch(String),
+}
+
+/// Write an object if `condition` holds
+///
+/// Fails with [`ErrorKind::Conflict`] when the object was changed (or
+/// created) by someone else. The object gets the metadata of its class, see
+/// `services::metadata`. During a dry run the object is written locally and
+/// the condition is ignored.
+///
+/// # Returns
+///
+/// The ETag of the written object
+#[instrument(skip(s3_client, bytes), fields(size = bytes.len()))]
+pub async fn put_object_if(
+    s3_client: &Bucket,
+    path: &str,
+    bytes: &[u8],
+    content_type: &str,
+    condition: &WriteCondition,
+) -> Result<String, Error> {
+    if let Some(dir) = crate::services::dry_run::output_dir() {
+        crate::services::dry_run::write(dir, path, bytes).await?;
+        return Ok(String::new());
+    }
+
+    let client = ObjectMetadata::new(path, bytes).apply(s3_client);
+    let mut headers = client.extra_headers().clone();
+    match condition {
+        WriteCondition::Always => {}
+        WriteCondition::IfAbsent => {
+            headers.insert(IF_NONE_MATCH, HeaderValue::from_static("*"));
+        }
+        WriteCondition::IfMatch(etag) => {
+            let etag = HeaderValue::from_str(etag)
+                .map_err(|err| invalid_input(format!("Invalid ETag {}: {}", etag, err)))?;
+            headers.insert(IF_MATCH, etag);
+        }
+    }
+
+    match client
+        .with_extra_headers(headers)
+        .put_object_with_content_type(path, bytes, content_type)
+        .await
+    {
+        // rust-s3 returns the ETag of a PUT as the response body
+        Ok(response) => {
+            crate::services::report::record_uploaded(bytes.len());
+            Ok(response.to_string().unwrap_or_default())
+        }
+        // 409 is returned for a conditional write racing another one
+        Err(S3Error::Http(412 | 409, _)) => Err(ErrorKind::Conflict {
+            path: path.to_string(),
+        }),
+        Err(err) => Err(s3_error(err, path)),
+    }
+}
+
+/// List every object under `prefix`
+///
+/// Follows continuation tokens, so the result covers the whole prefix.
+#[instrument(skip(s3_client))]
+pub async fn list_objects(
+    s3_client: &Bucket,
+    prefix: &str,
+) -> Result<Vec<s3::serde_types::Object>, Error> {
+    let pages = s3_client
+        .list(prefix.to_string(), None)
+        .await
+        .map_err(|err| s3_error(err, prefix))?;
+
+    Ok(pages.into_iter().flat_map(|page| page.contents).collect())
+}
+
+/// Check whether an object exists without downloading it
+#[instrument(skip(s3_client))]
+pub async fn object_exists(s3_client: &Bucket, path: &str) -> Result<bool, Error> {
+    match s3_client.head_object(path).await {
+        Ok((_, 404)) => Ok(false),
+        Ok(_) => Ok(true),
+        Err(S3Error::Http(404, _)) => Ok(false),
+        Err(err) => Err(s3_error(err, path)),
+    }
+}
+
+/// User metadata (`x-amz-meta-*`, prefix stripped) of an object, or `None`
+/// if it does not exist
+#[instrument(skip(s3_client))]
+pub async fn object_metadata(
+    s3_client: &Bucket,
+    path: &str,
+) -> Result<Option<HashMap<String, String>>, Error> {
+    match s3_client.head_object(path).await {
+        Ok((_, 404)) | Err(S3Error::Http(404, _)) => Ok(None),
+        Ok((head, _)) => Ok(Some(head.metadata.unwrap_or_default())),
+        Err(err) => Err(s3_error(err, path)),
+    }
+}
+
+/// Delete an object, treating an already missing object as success
+#[instrument(skip(s3_client))]
+pub async fn delete_object(s3_client: &Bucket, path: &str) -> Result<(), Error> {
+    if crate::services::dry_run::is_enabled() {
+        debug!(path = %path, "Dry run: skipping delete");
+        return Ok(());
+    }
+
+    match s3_client.delete_object(path).await {
+        Ok(_) | Err(S3Error::Http(404, _)) => Ok(()),
+        Err(err) => Err(s3_error(err, path)),
+    }
+}
+
+/// Server-side copy of an object within the bucket
+///
+/// Object metadata (Content-Type, Cache-Control, ...) is copied along.
+#[instrument(skip(s3_client))]
+pub async fn copy_object(s3_client: &Bucket, from: &str, to: &str) -> Result<(), Error> {
+    if let Some(dir) = crate::services::dry_run::output_dir() {
+        let bytes = match crate::services::dry_run::read(dir, from).await {
+            Some(bytes) => bytes,
+            None => get_object(s3_client, from)
+                .await?
+                .ok_or_else(|| crate::infrastructure::error::invalid_input(format!("Object {} does not exist", from)))?,
+        };
+        return crate::services::dry_run::write(dir, to, &bytes).await;
+    }
+
+    s3_client
+        .copy_object_internal(from, to)
+        .await
+        .map(|_| ())
+        .map_err(|err| s3_error(err, from))
+}
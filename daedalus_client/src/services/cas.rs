@@ -12,8 +12,16 @@ use tracing::{info, instrument};
 /// ## Version History
 /// - v4: Previous version
 /// - v5: Optimized Fabric/Quilt processing - only intermediary libraries are downloaded per game version
+///
+/// After bumping, run `daedalus_client migrate --from <old>` once to build the
+/// new tree from the old one instead of re-downloading everything upstream.
 pub const CAS_VERSION: u32 = 5;
 
+/// Bucket path of the live root manifest (`v{CAS_VERSION}/manifest.json`)
+pub fn root_manifest_path() -> String {
+    format!("v{}/manifest.json", CAS_VERSION)
+}
+
 /// Content-Addressable Storage (CAS) system
 ///
 /// This module implements a content-addressable storage architecture where:
@@ -61,6 +69,19 @@ impl LoaderReference {
     }
 }
 
+/// Location of the per-game-version indexes
+///
+/// The paths are templates: `{game_version}` and `{loader}` are replaced by
+/// the Minecraft version and loader name, e.g.
+/// `v{CAS_VERSION}/index/neoforge/1.20.4.json`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct IndexReference {
+    /// Cross-loader index of a game version
+    pub game: String,
+    /// Index of one loader for a game version
+    pub loader: String,
+}
+
 /// Root manifest that points to the current version of each loader manifest
 ///
 /// This is the single source of truth for the current state of the metadata.
@@ -75,6 +96,9 @@ pub struct RootManifest {
     /// Map of loader name to its manifest reference
     /// Example: "minecraft" -> { timestamp: "2024-01-15T10-30-00Z", url: "v{CAS_VERSION}/manifests/minecraft/2024-01-15T10-30-00Z.json" }
     pub loaders: HashMap<String, LoaderReference>,
+    /// Per-game-version indexes derived from the loader manifests, if published
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub indexes: Option<IndexReference>,
 }
 
 impl RootManifest {
@@ -84,6 +108,7 @@ impl RootManifest {
             schema_version: 1,
             created_at: Utc::now(),
             loaders,
+            indexes: None,
         }
     }
 
@@ -0,0 +1,533 @@
+//! Per-cycle changelog of loader manifests
+//!
+//! After a root manifest is committed, the new loader manifests are compared
+//! with the ones that were live before the cycle. The structured result is
+//! published as `v{CAS_VERSION}/changes/<timestamp>.json`, and the most recent
+//! changelogs are aggregated into an Atom feed at
+//! `v{CAS_VERSION}/changes/feed.atom` that launchers can poll for
+//! "new build available" notices. The feed is kept incrementally: the
+//! changelogs it shows are stored next to it in `feed-entries.json` and each
+//! cycle prepends its own.
+//!
+//! Versions are compared by id and content hash. All loader manifest schemas
+//! are supported:
+//!
+//! - game versions with nested `loaders` (forge, neoforge, fabric, quilt)
+//! - flat entries with a CAS `url` (minecraft) or a `hash` (simple entries)
+
+use crate::common::extract_hash_from_cas_url;
+use crate::infrastructure::error::Error;
+use crate::services::bucket::{get_object, list_objects};
+use crate::services::cas::{LoaderManifest, CAS_VERSION};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tracing::{info, instrument, warn};
+
+/// Number of changelogs aggregated into the Atom feed
+const FEED_ENTRIES: usize = 50;
+
+/// Bucket prefix of the changelogs
+pub fn changes_prefix() -> String {
+    format!("v{}/changes/", CAS_VERSION)
+}
+
+/// Bucket path of the changelog of a cycle
+pub fn changes_path(timestamp: &str) -> String {
+    format!("{}{}.json", changes_prefix(), timestamp)
+}
+
+/// Bucket path of the Atom feed
+pub fn feed_path() -> String {
+    format!("{}feed.atom", changes_prefix())
+}
+
+/// Bucket path of the changelogs the feed is rendered from, newest first
+pub fn feed_entries_path() -> String {
+    format!("{}feed-entries.json", changes_prefix())
+}
+
+/// A single version that was added, removed or changed
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct VersionChange {
+    /// Game version the loader version belongs to (modded loaders only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub game_version: Option<String>,
+    /// Version id (Minecraft version or loader version)
+    pub id: String,
+    /// Content hash before the cycle
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_hash: Option<String>,
+    /// Content hash after the cycle
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_hash: Option<String>,
+}
+
+/// Changes of a single loader manifest
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LoaderChanges {
+    /// Game versions that gained their first entry (modded loaders only)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub added_game_versions: Vec<String>,
+    /// Game versions that lost all entries (modded loaders only)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed_game_versions: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub added: Vec<VersionChange>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<VersionChange>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub changed: Vec<VersionChange>,
+}
+
+impl LoaderChanges {
+    /// Compare the `versions` of two loader manifests
+    pub fn between(old: &Value, new: &Value) -> Self {
+        let (old_games, old_entries) = flatten(old);
+        let (new_games, new_entries) = flatten(new);
+
+        let mut changes = Self {
+            added_game_versions: new_games
+                .iter()
+                .filter(|game| !old_games.contains(game))
+                .cloned()
+                .collect(),
+            removed_game_versions: old_games
+                .iter()
+                .filter(|game| !new_games.contains(game))
+                .cloned()
+                .collect(),
+            ..Self::default()
+        };
+
+        for ((game_version, id), new_hash) in &new_entries {
+            let change = |old_hash: Option<&Option<String>>| VersionChange {
+                game_version: game_version.clone(),
+                id: id.clone(),
+                old_hash: old_hash.cloned().flatten(),
+                new_hash: new_hash.clone(),
+            };
+
+            match old_entries.get(&(game_version.clone(), id.clone())) {
+                None => changes.added.push(change(None)),
+                Some(old_hash) if old_hash != new_hash => changes.changed.push(change(Some(old_hash))),
+                Some(_) => {}
+            }
+        }
+
+        for ((game_version, id), old_hash) in &old_entries {
+            if !new_entries.contains_key(&(game_version.clone(), id.clone())) {
+                changes.removed.push(VersionChange {
+                    game_version: game_version.clone(),
+                    id: id.clone(),
+                    old_hash: old_hash.clone(),
+                    new_hash: None,
+                });
+            }
+        }
+
+        changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added_game_versions.is_empty()
+            && self.removed_game_versions.is_empty()
+            && self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+    }
+
+    /// One line summary, e.g. `3 added, 1 changed`
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        for (count, label) in [
+            (self.added.len(), "added"),
+            (self.changed.len(), "changed"),
+            (self.removed.len(), "removed"),
+        ] {
+            if count > 0 {
+                parts.push(format!("{} {}", count, label));
+            }
+        }
+
+        if parts.is_empty() {
+            "no version changes".to_string()
+        } else {
+            parts.join(", ")
+        }
+    }
+}
+
+type EntryKey = (Option<String>, String);
+
+/// Flatten a loader manifest `versions` array into game versions and entries
+///
+/// Entries are keyed by `(game_version, id)`; the game version is only set
+/// for nested modded loader versions.
+fn flatten(versions: &Value) -> (Vec<String>, BTreeMap<EntryKey, Option<String>>) {
+    let mut games = Vec::new();
+    let mut entries = BTreeMap::new();
+
+    for version in versions.as_array().into_iter().flatten() {
+        let Some(id) = version.get("id").and_then(Value::as_str) else {
+            continue;
+        };
+
+        match version.get("loaders").and_then(Value::as_array) {
+            Some(loaders) => {
+                if !loaders.is_empty() {
+                    games.push(id.to_string());
+                }
+                for loader in loaders {
+                    if let Some(loader_id) = loader.get("id").and_then(Value::as_str) {
+                        entries.insert(
+                            (Some(id.to_string()), loader_id.to_string()),
+                            entry_hash(loader),
+                        );
+                    }
+                }
+            }
+            None => {
+                entries.insert((None, id.to_string()), entry_hash(version));
+            }
+        }
+    }
+
+    (games, entries)
+}
+
+/// Content hash of a version entry (`hash` field or CAS `url`)
+fn entry_hash(entry: &Value) -> Option<String> {
+    entry
+        .get("hash")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .or_else(|| {
+            entry
+                .get("url")
+                .and_then(Value::as_str)
+                .and_then(extract_hash_from_cas_url)
+        })
+}
+
+/// Changelog of one processing cycle
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Changelog {
+    pub schema_version: u32,
+    /// Timestamp of the root manifest commit (`%Y-%m-%dT%H-%M-%S%.3fZ`)
+    pub timestamp: String,
+    /// Loader name → changes, only loaders that changed
+    pub loaders: BTreeMap<String, LoaderChanges>,
+}
+
+impl Changelog {
+    /// Compare the previously live loader manifests with the new ones
+    ///
+    /// Loaders without a previous manifest are compared against an empty one,
+    /// so everything shows up as added. Loaders that were not rebuilt this
+    /// cycle are not part of `new` and therefore never reported as removed.
+    pub fn between(
+        timestamp: &str,
+        previous: &crate::services::previous_state::PreviousState,
+        new: &HashMap<String, LoaderManifest>,
+    ) -> Self {
+        let empty = Value::Array(Vec::new());
+
+        let loaders = new
+            .iter()
+            .map(|(loader, manifest)| {
+                let old = previous
+                    .loader_manifest(loader)
+                    .map(|manifest| &manifest.versions)
+                    .unwrap_or(&empty);
+                (loader.clone(), LoaderChanges::between(old, &manifest.versions))
+            })
+            .filter(|(_, changes)| !changes.is_empty())
+            .collect();
+
+        Self {
+            schema_version: 1,
+            timestamp: timestamp.to_string(),
+            loaders,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.loaders.is_empty()
+    }
+
+    /// Human readable title for feeds, e.g. `forge: 2 added; neoforge: 1 added`
+    pub fn title(&self) -> String {
+        self.loaders
+            .iter()
+            .map(|(loader, changes)| format!("{}: {}", loader, changes.summary()))
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+
+    /// Plain text body listing every change
+    pub fn body(&self) -> String {
+        let mut body = String::new();
+
+        for (loader, changes) in &self.loaders {
+            body.push_str(&format!("{}\n", loader));
+
+            for game in &changes.added_game_versions {
+                body.push_str(&format!("  + game version {}\n", game));
+            }
+            for game in &changes.removed_game_versions {
+                body.push_str(&format!("  - game version {}\n", game));
+            }
+            for (marker, list) in [("+", &changes.added), ("~", &changes.changed), ("-", &changes.removed)] {
+                for change in list {
+                    match &change.game_version {
+                        Some(game) => body.push_str(&format!("  {} {} ({})\n", marker, change.id, game)),
+                        None => body.push_str(&format!("  {} {}\n", marker, change.id)),
+                    }
+                }
+            }
+        }
+
+        body
+    }
+}
+
+/// Parse a changelog timestamp into RFC 3339 for the feed
+fn rfc3339(timestamp: &str) -> String {
+    chrono::NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%dT%H-%M-%S%.fZ")
+        .map(|naive| naive.and_utc().to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+        .unwrap_or_else(|_| timestamp.to_string())
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Render an Atom feed from changelogs, newest first
+pub fn render_feed(changelogs: &[Changelog]) -> String {
+    let feed_url = crate::format_url(&feed_path());
+    let updated = changelogs
+        .first()
+        .map(|changelog| rfc3339(&changelog.timestamp))
+        .unwrap_or_else(|| chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
+
+    let mut feed = String::new();
+    feed.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    feed.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    feed.push_str("  <title>Metadata changes</title>\n");
+    feed.push_str(&format!("  <id>{}</id>\n", escape_xml(&feed_url)));
+    feed.push_str(&format!("  <link rel=\"self\" href=\"{}\"/>\n", escape_xml(&feed_url)));
+    feed.push_str(&format!("  <updated>{}</updated>\n", updated));
+
+    for changelog in changelogs {
+        let url = crate::format_url(&changes_path(&changelog.timestamp));
+        feed.push_str("  <entry>\n");
+        feed.push_str(&format!("    <id>{}</id>\n", escape_xml(&url)));
+        feed.push_str(&format!("    <title>{}</title>\n", escape_xml(&changelog.title())));
+        feed.push_str(&format!("    <updated>{}</updated>\n", rfc3339(&changelog.timestamp)));
+        feed.push_str(&format!(
+            "    <link rel=\"alternate\" type=\"application/json\" href=\"{}\"/>\n",
+            escape_xml(&url)
+        ));
+        for loader in changelog.loaders.keys() {
+            feed.push_str(&format!("    <category term=\"{}\"/>\n", escape_xml(loader)));
+        }
+        feed.push_str(&format!(
+            "    <content type=\"text\">{}</content>\n",
+            escape_xml(&changelog.body())
+        ));
+        feed.push_str("  </entry>\n");
+    }
+
+    feed.push_str("</feed>\n");
+    feed
+}
+
+/// Read the newest changelogs, newest first
+async fn recent_changelogs(s3_client: &s3::Bucket) -> Result<Vec<Changelog>, Error> {
+    let entries_path = feed_entries_path();
+    let mut keys: Vec<String> = list_objects(s3_client, &changes_prefix())
+        .await?
+        .into_iter()
+        .map(|object| object.key)
+        .filter(|key| key.ends_with(".json") && *key != entries_path)
+        .collect();
+    keys.sort();
+    keys.reverse();
+
+    let mut changelogs = Vec::new();
+    for key in keys.into_iter().take(FEED_ENTRIES) {
+        match get_object(s3_client, &key).await {
+            Ok(Some(bytes)) => match serde_json::from_slice::<Changelog>(&bytes) {
+                Ok(changelog) => changelogs.push(changelog),
+                Err(err) => warn!(key = %key, error = %err, "Skipping unreadable changelog"),
+            },
+            Ok(None) => {}
+            Err(err) => warn!(key = %key, error = %err, "Failed to read changelog"),
+        }
+    }
+
+    Ok(changelogs)
+}
+
+/// Publish the changelog of a cycle and refresh the Atom feed
+///
+/// Does nothing when no loader changed.
+///
+/// # Returns
+///
+/// The public URLs that need a CDN purge (the feed)
+#[instrument(skip(s3_client, changelog, semaphore), fields(timestamp = %changelog.timestamp))]
+pub async fn publish(
+    s3_client: &s3::Bucket,
+    changelog: &Changelog,
+    semaphore: Arc<Semaphore>,
+) -> Result<Vec<String>, Error> {
+    if changelog.is_empty() {
+        info!("No loader changes this cycle, skipping changelog");
+        return Ok(Vec::new());
+    }
+
+    for (loader, changes) in &changelog.loaders {
+        info!(loader = %loader, summary = %changes.summary(), "📝 Loader changes");
+    }
+
+    crate::upload_file_to_bucket(
+        changes_path(&changelog.timestamp),
+        crate::common::to_canonical_vec(changelog)?,
+        Some("application/json".to_string()),
+        &tokio::sync::Mutex::new(Vec::new()),
+        semaphore.clone(),
+    )
+    .await?;
+
+    // Prepend to the entries the feed was last rendered from, the changelogs
+    // themselves are only read when those are missing
+    let mut changelogs = match get_object(s3_client, &feed_entries_path()).await? {
+        Some(bytes) => match serde_json::from_slice::<Vec<Changelog>>(&bytes) {
+            Ok(changelogs) => changelogs,
+            Err(err) => {
+                warn!(error = %err, "Feed entries are unreadable, rebuilding them");
+                recent_changelogs(s3_client).await?
+            }
+        },
+        None => recent_changelogs(s3_client).await?,
+    };
+    changelogs.retain(|entry| entry.timestamp != changelog.timestamp);
+    changelogs.insert(0, changelog.clone());
+    changelogs.truncate(FEED_ENTRIES);
+
+    crate::upload_file_to_bucket(
+        feed_entries_path(),
+        crate::common::to_canonical_vec(&changelogs)?,
+        Some("application/json".to_string()),
+        &tokio::sync::Mutex::new(Vec::new()),
+        semaphore.clone(),
+    )
+    .await?;
+
+    crate::upload_file_to_bucket(
+        feed_path(),
+        render_feed(&changelogs).into_bytes(),
+        Some("application/atom+xml".to_string()),
+        &tokio::sync::Mutex::new(Vec::new()),
+        semaphore,
+    )
+    .await?;
+
+    info!(entries = changelogs.len(), "Changelog and feed published");
+
+    Ok(vec![crate::format_url(&feed_path())])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cas_url(hash: &str) -> String {
+        format!("https://cdn.example.com/v{}/objects/{}/{}", CAS_VERSION, &hash[..2], &hash[2..])
+    }
+
+    #[test]
+    fn test_modded_changes() {
+        let old = serde_json::json!([
+            { "id": "1.20.1", "stable": true, "loaders": [
+                { "id": "47.1.0", "url": cas_url("aa11"), "stable": true },
+                { "id": "47.1.1", "url": cas_url("bb22"), "stable": false },
+            ]},
+            { "id": "1.19.4", "stable": true, "loaders": [
+                { "id": "45.0.0", "url": cas_url("cc33"), "stable": true },
+            ]},
+        ]);
+        let new = serde_json::json!([
+            { "id": "1.20.1", "stable": true, "loaders": [
+                { "id": "47.1.0", "url": cas_url("aa11"), "stable": true },
+                { "id": "47.1.1", "url": cas_url("dd44"), "stable": false },
+                { "id": "47.1.2", "url": cas_url("ee55"), "stable": false },
+            ]},
+            { "id": "1.21", "stable": true, "loaders": [
+                { "id": "51.0.0", "url": cas_url("ff66"), "stable": false },
+            ]},
+        ]);
+
+        let changes = LoaderChanges::between(&old, &new);
+
+        assert_eq!(changes.added_game_versions, vec!["1.21".to_string()]);
+        assert_eq!(changes.removed_game_versions, vec!["1.19.4".to_string()]);
+        assert_eq!(
+            changes.changed,
+            vec![VersionChange {
+                game_version: Some("1.20.1".to_string()),
+                id: "47.1.1".to_string(),
+                old_hash: Some("bb22".to_string()),
+                new_hash: Some("dd44".to_string()),
+            }]
+        );
+        let added: Vec<_> = changes.added.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(added, vec!["47.1.2", "51.0.0"]);
+        assert_eq!(changes.removed[0].id, "45.0.0");
+        assert_eq!(changes.summary(), "2 added, 1 changed, 1 removed");
+    }
+
+    #[test]
+    fn test_flat_changes() {
+        let old = serde_json::json!([{ "id": "1.20.4", "hash": "aa", "size": 1 }]);
+        let new = serde_json::json!([
+            { "id": "1.20.4", "hash": "aa", "size": 1 },
+            { "id": "24w14a", "url": cas_url("bb99") },
+        ]);
+
+        let changes = LoaderChanges::between(&old, &new);
+
+        assert_eq!(changes.added.len(), 1);
+        assert_eq!(changes.added[0].game_version, None);
+        assert_eq!(changes.added[0].new_hash.as_deref(), Some("bb99"));
+        assert!(LoaderChanges::between(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn test_escape_xml_and_body() {
+        assert_eq!(escape_xml("a<b>&\"c\""), "a&lt;b&gt;&amp;&quot;c&quot;");
+
+        let changelog = Changelog {
+            schema_version: 1,
+            timestamp: "2024-01-15T10-30-00Z".to_string(),
+            loaders: BTreeMap::from([(
+                "forge".to_string(),
+                LoaderChanges::between(
+                    &serde_json::json!([]),
+                    &serde_json::json!([{ "id": "1.20.1", "loaders": [{ "id": "47.1.0", "url": cas_url("aa11") }] }]),
+                ),
+            )]),
+        };
+
+        assert_eq!(changelog.title(), "forge: 1 added");
+        assert!(changelog.body().contains("+ 47.1.0 (1.20.1)"));
+        assert_eq!(rfc3339(&changelog.timestamp), "2024-01-15T10:30:00Z");
+    }
+}
@@ -1,3 +1,4 @@
+use crate::infrastructure::config::Config;
 use crate::infrastructure::error::{Error, fetch_error, invalid_input};
 use std::sync::LazyLock;
 use std::time::Duration;
@@ -8,19 +9,12 @@ use tracing::{error, info, instrument, warn, Instrument};
 /// This client is configured with:
 /// - TCP keepalive for long-lived connections
 /// - Generous timeouts for API operations
-/// - Proper user agent identification
 /// - Connection pooling for efficiency
 static HTTP_CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
     reqwest::Client::builder()
         .tcp_keepalive(Some(Duration::from_secs(10)))
         .timeout(Duration::from_secs(120))
         .connect_timeout(Duration::from_secs(30))
-        .user_agent(format!(
-            "gdlauncher/daedalus/{} ({})",
-            env!("CARGO_PKG_VERSION"),
-            dotenvy::var("SUPPORT_EMAIL")
-                .unwrap_or_else(|_| "support@gdlauncher.com".to_string())
-        ))
         .pool_max_idle_per_host(10)
         .build()
         .expect("Failed to build HTTP client")
@@ -37,6 +31,7 @@ static HTTP_CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
 ///
 /// * `token` - Cloudflare API token with cache purge permissions
 /// * `zone_id` - The Cloudflare zone ID for the domain
+/// * `user_agent` - `User-Agent` identifying this deployment
 /// * `urls` - List of full URLs to purge from cache
 ///
 /// # Returns
@@ -51,12 +46,13 @@ static HTTP_CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
 ///     "https://example.com/file1.json".to_string(),
 ///     "https://example.com/file2.json".to_string(),
 /// ];
-/// purge_cloudflare_cache("api_token", "zone_id", &urls).await?;
+/// purge_cloudflare_cache("api_token", "zone_id", &config.user_agent(), &urls).await?;
 /// ```
-#[instrument(skip(token, zone_id, urls), fields(url_count = urls.len()))]
+#[instrument(skip(token, zone_id, user_agent, urls), fields(url_count = urls.len()))]
 pub async fn purge_cloudflare_cache(
     token: &str,
     zone_id: &str,
+    user_agent: &str,
     urls: &[String],
 ) -> Result<(), Error> {
     if urls.is_empty() {
@@ -79,6 +75,7 @@ pub async fn purge_cloudflare_cache(
                     zone_id
                 ))
                 .header("Authorization", format!("Bearer {}", token))
+                .header("User-Agent", user_agent)
                 .header("Content-Type", "application/json")
                 .json(&serde_json::json!({ "files": chunk }))
                 .send()
@@ -128,3 +125,36 @@ pub async fn purge_cloudflare_cache(
 
     Ok(())
 }
+
+/// Purge the given URLs if Cloudflare integration is enabled
+///
+/// Purge failures are logged and never returned, since the uploaded content
+/// is already committed at this point.
+pub async fn purge_if_enabled(config: &Config, urls: &[String]) {
+    if urls.is_empty() {
+        return;
+    }
+
+    if crate::services::dry_run::is_enabled() {
+        info!(url_count = urls.len(), "Dry run: skipping Cloudflare cache purge");
+        return;
+    }
+
+    // Validation guarantees both are set when the integration is enabled
+    let cloudflare = &config.cloudflare;
+    let (true, Some(token), Some(zone_id)) =
+        (cloudflare.enabled, &cloudflare.token, &cloudflare.zone_id)
+    else {
+        info!("Cloudflare cache purging disabled (set cloudflare.enabled to enable)");
+        return;
+    };
+
+    match purge_cloudflare_cache(token, zone_id, &config.user_agent(), urls).await {
+        Ok(_) => {
+            info!("Cloudflare cache purge successful");
+        }
+        Err(e) => {
+            warn!(error = %e, "Cloudflare cache purge failed, but continuing");
+        }
+    }
+}
@@ -1,4 +1,6 @@
 use crate::infrastructure::error::Error;
+use crate::infrastructure::metrics;
+use crate::infrastructure::shutdown;
 use std::sync::Arc;
 use tokio::sync::Semaphore;
 use tracing::{info, instrument};
@@ -7,6 +9,7 @@ use tracing::{info, instrument};
 ///
 /// This function wraps the daedalus library's download_file function,
 /// adding semaphore-based concurrency control and structured logging.
+/// Fails with `ErrorKind::Cancelled` once a shutdown was requested.
 ///
 /// # Arguments
 ///
@@ -23,9 +26,11 @@ pub async fn download_file(
     sha1: Option<&str>,
     semaphore: Arc<Semaphore>,
 ) -> Result<bytes::Bytes, Error> {
-    let _permit = semaphore.acquire().await?;
+    let _permit = metrics::acquire_permit(&semaphore).await?;
+    shutdown::check()?;
     info!(url = %url, has_sha1 = sha1.is_some(), "Started downloading");
     let val = daedalus::download_file(url, sha1).await?;
+    crate::services::report::record_downloaded(val.len());
     info!(url = %url, "Download completed");
     Ok(val)
 }
@@ -53,9 +58,11 @@ pub async fn download_file_mirrors(
     sha1: Option<&str>,
     semaphore: Arc<Semaphore>,
 ) -> Result<bytes::Bytes, Error> {
-    let _permit = semaphore.acquire().await?;
+    let _permit = metrics::acquire_permit(&semaphore).await?;
+    shutdown::check()?;
     info!(base = %base, has_sha1 = sha1.is_some(), "Started downloading from mirrors");
     let val = daedalus::download_file_mirrors(base, mirrors, sha1).await?;
+    crate::services::report::record_downloaded(val.len());
     info!(base = %base, "Download from mirrors completed");
     Ok(val)
 }
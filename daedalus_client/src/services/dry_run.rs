@@ -0,0 +1,94 @@
+//! Dry-run mode: write the output tree to a local directory
+//!
+//! With `--dry-run --out <dir>` the client runs a single cycle against the
+//! real upstreams and reads the currently published state from the bucket,
+//! but every write is redirected to `<dir>/<bucket path>`. Deletes become
+//! no-ops and the Cloudflare purge is skipped, so nothing published changes.
+//!
+//! All bucket writes go through `upload_file_to_bucket`, the CAS uploader and
+//! `services::bucket`, which check [`output_dir`] before touching S3.
+
+use crate::infrastructure::error::Error;
+use crate::services::cas::RootManifest;
+use crate::services::changelog::Changelog;
+use crate::services::history::RootDiff;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use tracing::{debug, info};
+
+static OUTPUT_DIR: OnceLock<PathBuf> = OnceLock::new();
+static FILES_WRITTEN: AtomicUsize = AtomicUsize::new(0);
+static BYTES_WRITTEN: AtomicU64 = AtomicU64::new(0);
+
+/// Redirect all writes of this process to `dir`
+pub fn enable(dir: PathBuf) -> Result<(), Error> {
+    std::fs::create_dir_all(&dir)?;
+    info!(out = %dir.display(), "🧪 Dry run enabled, nothing will be written to the bucket");
+
+    OUTPUT_DIR
+        .set(dir)
+        .map_err(|_| crate::infrastructure::error::invalid_input("Dry run output directory already set"))
+}
+
+/// The local output directory, if running a dry run
+pub fn output_dir() -> Option<&'static Path> {
+    OUTPUT_DIR.get().map(PathBuf::as_path)
+}
+
+pub fn is_enabled() -> bool {
+    output_dir().is_some()
+}
+
+/// Local file standing in for a bucket path
+fn local_path(dir: &Path, path: &str) -> PathBuf {
+    dir.join(path.trim_start_matches('/'))
+}
+
+/// Write an object that would have been uploaded to `path`
+pub async fn write(dir: &Path, path: &str, bytes: &[u8]) -> Result<(), Error> {
+    let target = local_path(dir, path);
+    if let Some(parent) = target.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(&target, bytes).await?;
+
+    FILES_WRITTEN.fetch_add(1, Ordering::Relaxed);
+    BYTES_WRITTEN.fetch_add(bytes.len() as u64, Ordering::Relaxed);
+    debug!(path = %path, size = bytes.len(), "Dry run: wrote object locally");
+
+    Ok(())
+}
+
+/// Read an object written earlier in this dry run
+pub async fn read(dir: &Path, path: &str) -> Option<Vec<u8>> {
+    tokio::fs::read(local_path(dir, path)).await.ok()
+}
+
+/// Print what the cycle would have published
+pub fn report(published: Option<&RootManifest>, root: &RootManifest, changelog: &Changelog) {
+    let dir = output_dir().map(|dir| dir.display().to_string()).unwrap_or_default();
+
+    println!();
+    println!(
+        "Dry run wrote {} objects ({} bytes) to {}",
+        FILES_WRITTEN.load(Ordering::Relaxed),
+        BYTES_WRITTEN.load(Ordering::Relaxed),
+        dir
+    );
+    println!();
+
+    match published {
+        Some(_) => println!("Root manifest compared to the published one:"),
+        None => println!("No root manifest is published yet, everything would be new:"),
+    }
+    print!("{}", RootDiff::between(published, root));
+    println!();
+
+    if changelog.is_empty() {
+        println!("No version changes");
+    } else {
+        println!("Version changes:");
+        print!("{}", changelog.body());
+    }
+}
@@ -0,0 +1,419 @@
+//! Root manifest history snapshots
+//!
+//! Every committed root manifest is also written to
+//! `v{CAS_VERSION}/history/manifest-<timestamp>.json`. Because loader
+//! manifests and CAS objects are immutable, any of these snapshots can be made
+//! live again by re-publishing it as the root manifest.
+
+use crate::infrastructure::config::UploadConfig;
+use crate::infrastructure::error::{invalid_input, Error, ErrorKind};
+use crate::services::bucket::{get_object, list_objects, put_object_if, WriteCondition};
+use crate::services::cas::{
+    root_manifest_path, LoaderManifest, LoaderReference, RootManifest, CAS_VERSION,
+};
+use backon::{ExponentialBuilder, Retryable};
+use chrono::{DateTime, SubsecRound, Utc};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tracing::{info, instrument, warn};
+
+/// Format of commit timestamps, which name snapshots and changelogs
+///
+/// Millisecond precision keeps commits made within the same second apart.
+pub const TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H-%M-%S%.3fZ";
+
+/// Last timestamp handed out by [`commit_timestamp`]
+static LAST_COMMIT: Mutex<Option<DateTime<Utc>>> = Mutex::new(None);
+
+/// Timestamp of a new root manifest commit
+///
+/// Take it once the commit lock is held. Timestamps are strictly increasing
+/// within the process, so consecutive commits never share a snapshot or
+/// changelog path and stay in order even if the clock does not advance.
+pub fn commit_timestamp() -> String {
+    let mut last = LAST_COMMIT.lock().unwrap_or_else(PoisonError::into_inner);
+    let now = Utc::now().trunc_subsecs(3);
+    let timestamp = match *last {
+        Some(previous) if now <= previous => previous + chrono::Duration::milliseconds(1),
+        _ => now,
+    };
+    *last = Some(timestamp);
+    timestamp.format(TIMESTAMP_FORMAT).to_string()
+}
+
+/// Bucket prefix holding all root manifest snapshots
+pub fn history_prefix() -> String {
+    format!("v{}/history/", CAS_VERSION)
+}
+
+/// Bucket path of the root manifest snapshot for `snapshot_id`
+pub fn history_path(snapshot_id: &str) -> String {
+    format!("{}manifest-{}.json", history_prefix(), snapshot_id)
+}
+
+/// Extract the snapshot id (its timestamp) from a history object key
+///
+/// Accepts full keys (`v5/history/manifest-<ts>.json`), file names
+/// (`manifest-<ts>.json`) and bare ids (`<ts>`).
+pub fn snapshot_id(key: &str) -> &str {
+    let name = key.rsplit('/').next().unwrap_or(key);
+    let name = name.strip_prefix("manifest-").unwrap_or(name);
+    name.strip_suffix(".json").unwrap_or(name)
+}
+
+/// A root manifest snapshot stored under `history/`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    /// Snapshot id, the commit timestamp (e.g. `2024-01-15T10-30-00.123Z`)
+    pub id: String,
+    /// Full bucket key
+    pub key: String,
+    /// Size in bytes
+    pub size: u64,
+}
+
+/// List all root manifest snapshots, oldest first
+#[instrument(skip(s3_client))]
+pub async fn list_snapshots(s3_client: &s3::Bucket) -> Result<Vec<HistoryEntry>, Error> {
+    let mut entries: Vec<HistoryEntry> = list_objects(s3_client, &history_prefix())
+        .await?
+        .into_iter()
+        .filter(|object| object.key.ends_with(".json"))
+        .map(|object| HistoryEntry {
+            id: snapshot_id(&object.key).to_string(),
+            key: object.key,
+            size: object.size,
+        })
+        .collect();
+
+    // Timestamps are zero-padded, so lexicographic order is chronological
+    entries.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(entries)
+}
+
+/// Load a root manifest snapshot by id
+#[instrument(skip(s3_client))]
+pub async fn load_snapshot(
+    s3_client: &s3::Bucket,
+    snapshot: &str,
+) -> Result<RootManifest, Error> {
+    let path = history_path(snapshot_id(snapshot));
+    let bytes = get_object(s3_client, &path)
+        .await?
+        .ok_or_else(|| invalid_input(format!("History snapshot {} does not exist", path)))?;
+
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+/// Load the live root manifest, if one has been published
+#[instrument(skip(s3_client))]
+pub async fn load_current_root(s3_client: &s3::Bucket) -> Result<Option<RootManifest>, Error> {
+    match get_object(s3_client, &root_manifest_path()).await? {
+        Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+        None => Ok(None),
+    }
+}
+
+/// Load a snapshot, or the live root manifest when `snapshot` is `None`
+pub async fn load_root(
+    s3_client: &s3::Bucket,
+    snapshot: Option<&str>,
+) -> Result<RootManifest, Error> {
+    match snapshot {
+        Some(snapshot) => load_snapshot(s3_client, snapshot).await,
+        None => load_current_root(s3_client)
+            .await?
+            .ok_or_else(|| invalid_input("No root manifest has been published yet")),
+    }
+}
+
+/// Load a loader manifest referenced by a root manifest
+#[instrument(skip(s3_client))]
+pub async fn load_loader_manifest(
+    s3_client: &s3::Bucket,
+    path: &str,
+) -> Result<LoaderManifest, Error> {
+    let bytes = get_object(s3_client, path)
+        .await?
+        .ok_or_else(|| invalid_input(format!("Loader manifest {} does not exist", path)))?;
+
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+/// How a single loader reference differs between two root manifests
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoaderChange {
+    /// Same loader manifest on both sides
+    Unchanged { timestamp: String },
+    /// Loader only present in the target
+    Added { to: String },
+    /// Loader only present in the source
+    Removed { from: String },
+    /// Loader manifest timestamp differs
+    Changed { from: String, to: String },
+}
+
+impl fmt::Display for LoaderChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoaderChange::Unchanged { timestamp } => write!(f, "  {}", timestamp),
+            LoaderChange::Added { to } => write!(f, "+ {}", to),
+            LoaderChange::Removed { from } => write!(f, "- {}", from),
+            LoaderChange::Changed { from, to } => write!(f, "~ {} -> {}", from, to),
+        }
+    }
+}
+
+/// How the index reference differs between two root manifests
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexChange {
+    /// Indexes only advertised by the target
+    Added,
+    /// Indexes only advertised by the source
+    Removed,
+    /// Both advertise indexes, at different paths
+    Changed,
+}
+
+impl fmt::Display for IndexChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IndexChange::Added => write!(f, "+ published"),
+            IndexChange::Removed => write!(f, "- dropped"),
+            IndexChange::Changed => write!(f, "~ moved"),
+        }
+    }
+}
+
+/// Per-loader difference between two root manifests
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RootDiff {
+    /// Loader name → change, sorted by loader name
+    pub loaders: BTreeMap<String, LoaderChange>,
+    /// Change of the index reference, if any
+    pub indexes: Option<IndexChange>,
+}
+
+impl RootDiff {
+    /// Compute what changes when going from `from` to `to`
+    pub fn between(from: Option<&RootManifest>, to: &RootManifest) -> Self {
+        let empty = HashMap::new();
+        let from_loaders = from.map(|root| &root.loaders).unwrap_or(&empty);
+
+        let mut loaders = BTreeMap::new();
+
+        for (loader, reference) in &to.loaders {
+            let change = match from_loaders.get(loader) {
+                Some(old) if old.timestamp == reference.timestamp => LoaderChange::Unchanged {
+                    timestamp: reference.timestamp.clone(),
+                },
+                Some(old) => LoaderChange::Changed {
+                    from: old.timestamp.clone(),
+                    to: reference.timestamp.clone(),
+                },
+                None => LoaderChange::Added {
+                    to: reference.timestamp.clone(),
+                },
+            };
+            loaders.insert(loader.clone(), change);
+        }
+
+        for (loader, reference) in from_loaders {
+            if !to.loaders.contains_key(loader) {
+                loaders.insert(
+                    loader.clone(),
+                    LoaderChange::Removed {
+                        from: reference.timestamp.clone(),
+                    },
+                );
+            }
+        }
+
+        let indexes = match (from.and_then(|root| root.indexes.as_ref()), to.indexes.as_ref()) {
+            (None, Some(_)) => Some(IndexChange::Added),
+            (Some(_), None) => Some(IndexChange::Removed),
+            (Some(old), Some(new)) if old != new => Some(IndexChange::Changed),
+            _ => None,
+        };
+
+        Self { loaders, indexes }
+    }
+
+    /// Whether both manifests point at exactly the same loader manifests and
+    /// indexes
+    pub fn is_empty(&self) -> bool {
+        self.indexes.is_none()
+            && self
+                .loaders
+                .values()
+                .all(|change| matches!(change, LoaderChange::Unchanged { .. }))
+    }
+}
+
+impl fmt::Display for RootDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (loader, change) in &self.loaders {
+            writeln!(f, "  {:<10} {}", loader, change)?;
+        }
+        if let Some(change) = self.indexes {
+            writeln!(f, "  {:<10} {}", "indexes", change)?;
+        }
+        Ok(())
+    }
+}
+
+/// Build a new root manifest from `base`, replacing the given loaders
+///
+/// The index reference of `base` is kept.
+pub fn compose_root(
+    base: &RootManifest,
+    overrides: impl IntoIterator<Item = (String, LoaderReference)>,
+) -> RootManifest {
+    let mut loaders = base.loaders.clone();
+    loaders.extend(overrides);
+
+    let mut root = RootManifest::new(loaders);
+    root.indexes = base.indexes.clone();
+    root
+}
+
+/// Upload a root manifest (the atomic commit point) and record it in history
+///
+/// The root manifest is written with a single PUT, so readers see either the
+/// old or the new root. The write only happens if `condition` holds and
+/// fails with `ErrorKind::Conflict` otherwise, so a commit never silently
+/// replaces a root it has not seen. The history backup is best effort.
+///
+/// # Returns
+///
+/// The public URLs that need a CDN purge (the root manifest)
+#[instrument(skip(root, retries, semaphore))]
+pub async fn publish_root_manifest(
+    root: &RootManifest,
+    snapshot_id: &str,
+    condition: &WriteCondition,
+    retries: &UploadConfig,
+    semaphore: Arc<Semaphore>,
+) -> Result<Vec<String>, Error> {
+    let root_path = root_manifest_path();
+    let root_bytes = crate::common::to_canonical_vec(root)?;
+
+    {
+        let _permit = crate::infrastructure::metrics::acquire_permit(&semaphore).await?;
+
+        (|| put_object_if(&crate::CLIENT, &root_path, &root_bytes, "application/json", condition))
+            .retry(
+                ExponentialBuilder::default()
+                    .with_max_times(retries.max_retries)
+                    .with_max_delay(Duration::from_secs(retries.max_retry_delay_secs)),
+            )
+            // A conflict is resolved by the caller on top of the new root
+            .when(|err| {
+                !matches!(err, ErrorKind::Conflict { .. })
+                    && !crate::infrastructure::shutdown::is_requested()
+            })
+            .notify(|_, _| crate::infrastructure::metrics::record_retry("root_commit"))
+            .await?;
+    }
+
+    info!(path = %root_path, "Root manifest published");
+
+    // Snapshots are read by operators during incidents, keep them readable
+    let backup_path = history_path(snapshot_id);
+    info!(backup_path = %backup_path, "Creating backup of root manifest");
+
+    match crate::upload_file_to_bucket(
+        backup_path,
+        crate::common::to_canonical_vec_pretty(root)?,
+        Some("application/json".to_string()),
+        &tokio::sync::Mutex::new(Vec::new()),
+        semaphore,
+    )
+    .await
+    {
+        Ok(_) => info!("Backup created successfully"),
+        Err(e) => warn!(error = %e, "Failed to create backup (non-fatal)"),
+    }
+
+    Ok(vec![crate::format_url(&root_path)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn root(loaders: &[(&str, &str)]) -> RootManifest {
+        RootManifest::new(
+            loaders
+                .iter()
+                .map(|(loader, ts)| (loader.to_string(), LoaderReference::new(loader, ts.to_string())))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_snapshot_id() {
+        let id = "2024-01-15T10-30-00Z";
+
+        assert_eq!(snapshot_id(&history_path(id)), id);
+        assert_eq!(snapshot_id("manifest-2024-01-15T10-30-00Z.json"), id);
+        assert_eq!(snapshot_id(id), id);
+    }
+
+    #[test]
+    fn test_commit_timestamp() {
+        let timestamps: Vec<String> = (0..100).map(|_| commit_timestamp()).collect();
+
+        assert!(timestamps.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(crate::services::retention::parse_timestamp(&timestamps[0]).is_some());
+    }
+
+    #[test]
+    fn test_root_diff() {
+        let current = root(&[("minecraft", "t1"), ("forge", "t1"), ("quilt", "t1")]);
+        let target = root(&[("minecraft", "t1"), ("forge", "t0"), ("fabric", "t0")]);
+
+        let diff = RootDiff::between(Some(&current), &target);
+
+        assert_eq!(diff.loaders["minecraft"], LoaderChange::Unchanged { timestamp: "t1".to_string() });
+        assert_eq!(
+            diff.loaders["forge"],
+            LoaderChange::Changed { from: "t1".to_string(), to: "t0".to_string() }
+        );
+        assert_eq!(diff.loaders["fabric"], LoaderChange::Added { to: "t0".to_string() });
+        assert_eq!(diff.loaders["quilt"], LoaderChange::Removed { from: "t1".to_string() });
+        assert!(!diff.is_empty());
+        assert_eq!(diff.indexes, None);
+        assert!(RootDiff::between(Some(&current), &current).is_empty());
+
+        let mut indexed = current.clone();
+        indexed.indexes = Some(crate::services::index::index_reference());
+        let diff = RootDiff::between(Some(&current), &indexed);
+        assert_eq!(diff.indexes, Some(IndexChange::Added));
+        assert!(!diff.is_empty());
+        assert_eq!(RootDiff::between(Some(&indexed), &current).indexes, Some(IndexChange::Removed));
+    }
+
+    #[test]
+    fn test_compose_root() {
+        let mut current = root(&[("minecraft", "t2"), ("forge", "t2")]);
+        current.indexes = Some(crate::services::index::index_reference());
+        let snapshot = root(&[("minecraft", "t1"), ("forge", "t1")]);
+
+        let mixed = compose_root(
+            &current,
+            [("forge".to_string(), snapshot.loaders["forge"].clone())],
+        );
+
+        assert_eq!(mixed.loaders["minecraft"].timestamp, "t2");
+        assert_eq!(mixed.loaders["forge"].timestamp, "t1");
+        assert_eq!(
+            mixed.loaders["forge"].url,
+            format!("v{}/manifests/forge/t1.json", CAS_VERSION)
+        );
+        assert_eq!(mixed.indexes, current.indexes);
+    }
+}
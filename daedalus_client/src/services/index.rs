@@ -0,0 +1,451 @@
+//! Per-game-version indexes derived from the loader manifests
+//!
+//! Loader manifests list every game version, so a launcher looking for the
+//! NeoForge builds of one Minecraft version has to download all of them. The
+//! indexes published here are small documents answering exactly that:
+//!
+//! - `v{N}/index/<loader>/<game_version>.json`: the builds of one loader for
+//!   one game version, newest first, with `latest`/`recommended` markers
+//! - `v{N}/index/game/<game_version>.json`: every loader available for one
+//!   game version with the same markers, plus its Minecraft entry
+//!
+//! The root manifest points at them through [`IndexReference`]. Indexes live
+//! at fixed paths, so they are published only after the root manifest deriving
+//! them was committed. Only the ones whose content changed are re-uploaded (and
+//! purged), and indexes of game versions that disappeared are deleted.
+
+use crate::infrastructure::error::Error;
+use crate::services::cas::{IndexReference, LoaderManifest, RootManifest, CAS_VERSION};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tracing::{info, instrument, warn};
+
+/// Set when a publish failed halfway, the next one rewrites every index
+static STALE: AtomicBool = AtomicBool::new(false);
+
+/// Bucket prefix of the indexes
+pub fn index_prefix() -> String {
+    format!("v{}/index/", CAS_VERSION)
+}
+
+/// Bucket path of the index of one loader for one game version
+pub fn loader_index_path(loader: &str, game_version: &str) -> String {
+    format!("{}{}/{}.json", index_prefix(), loader, game_version)
+}
+
+/// Bucket path of the cross-loader index of one game version
+pub fn game_index_path(game_version: &str) -> String {
+    format!("{}game/{}.json", index_prefix(), game_version)
+}
+
+/// Path templates advertised in the root manifest
+pub fn index_reference() -> IndexReference {
+    IndexReference {
+        game: game_index_path("{game_version}"),
+        loader: loader_index_path("{loader}", "{game_version}"),
+    }
+}
+
+/// Builds of one loader for one game version
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LoaderIndex {
+    pub schema_version: u32,
+    pub loader: String,
+    pub game_version: String,
+    /// Newest build
+    pub latest: Option<String>,
+    /// Newest build marked stable
+    pub recommended: Option<String>,
+    /// Loader version entries (`id`, `url`, `stable`), newest first
+    pub versions: Vec<Value>,
+}
+
+/// Summary of one loader inside a game index
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GameLoaderEntry {
+    pub latest: Option<String>,
+    pub recommended: Option<String>,
+    /// Number of builds
+    pub count: usize,
+    /// Path of the loader index
+    pub url: String,
+}
+
+/// Every loader available for one game version
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct GameIndex {
+    pub schema_version: u32,
+    pub game_version: String,
+    /// Entry of the Minecraft loader manifest for this version, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub minecraft: Option<Value>,
+    /// Loader name → summary
+    pub loaders: BTreeMap<String, GameLoaderEntry>,
+}
+
+/// All index documents derived from a set of loader manifests
+#[derive(Debug, Default)]
+pub struct IndexSet {
+    /// Bucket path → canonical JSON
+    documents: BTreeMap<String, Vec<u8>>,
+}
+
+impl IndexSet {
+    /// Derive the indexes of a set of loader manifests
+    pub fn build(manifests: &HashMap<String, &LoaderManifest>) -> Result<Self, Error> {
+        let mut games: BTreeMap<String, GameIndex> = BTreeMap::new();
+        let mut documents = BTreeMap::new();
+
+        let mut loaders: Vec<_> = manifests.iter().collect();
+        loaders.sort_by(|a, b| a.0.cmp(b.0));
+
+        for (loader, manifest) in loaders {
+            for (game_version, builds) in game_versions(&manifest.versions) {
+                let game = games.entry(game_version.clone()).or_insert_with(|| GameIndex {
+                    schema_version: 1,
+                    game_version: game_version.clone(),
+                    minecraft: None,
+                    loaders: BTreeMap::new(),
+                });
+
+                match builds {
+                    GameBuilds::Minecraft(entry) => game.minecraft = Some(entry),
+                    GameBuilds::Loader(mut builds) => {
+                        builds.sort_by(|a, b| compare_versions(entry_id(b), entry_id(a)));
+
+                        let latest = builds.first().map(|build| entry_id(build).to_string());
+                        let recommended = builds
+                            .iter()
+                            .find(|build| build.get("stable").and_then(Value::as_bool).unwrap_or(false))
+                            .map(|build| entry_id(build).to_string());
+                        let path = loader_index_path(loader, &game_version);
+
+                        game.loaders.insert(
+                            loader.clone(),
+                            GameLoaderEntry {
+                                latest: latest.clone(),
+                                recommended: recommended.clone(),
+                                count: builds.len(),
+                                url: path.clone(),
+                            },
+                        );
+
+                        let index = LoaderIndex {
+                            schema_version: 1,
+                            loader: loader.clone(),
+                            game_version: game_version.clone(),
+                            latest,
+                            recommended,
+                            versions: builds,
+                        };
+                        documents.insert(path, crate::common::to_canonical_vec(&index)?);
+                    }
+                }
+            }
+        }
+
+        for (game_version, game) in games {
+            // A Minecraft entry alone is already in the Minecraft manifest
+            if game.loaders.is_empty() {
+                continue;
+            }
+            documents.insert(game_index_path(&game_version), crate::common::to_canonical_vec(&game)?);
+        }
+
+        Ok(Self { documents })
+    }
+
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    /// Paths whose content differs from (or is missing in) `previous`
+    pub fn changed_since<'a>(&'a self, previous: &IndexSet) -> Vec<(&'a String, &'a Vec<u8>)> {
+        self.documents
+            .iter()
+            .filter(|(path, bytes)| previous.documents.get(*path) != Some(*bytes))
+            .collect()
+    }
+
+    /// Paths present in `previous` but no longer derived
+    pub fn removed_since<'a>(&self, previous: &'a IndexSet) -> Vec<&'a String> {
+        previous
+            .documents
+            .keys()
+            .filter(|path| !self.documents.contains_key(*path))
+            .collect()
+    }
+}
+
+/// What a loader manifest contributes to one game version
+enum GameBuilds {
+    /// A Minecraft version entry
+    Minecraft(Value),
+    /// Loader builds for the game version
+    Loader(Vec<Value>),
+}
+
+fn entry_id(entry: &Value) -> &str {
+    entry.get("id").and_then(Value::as_str).unwrap_or_default()
+}
+
+/// Whether a game version id is a `${<brand>.gameVersion}` placeholder
+///
+/// Fabric and Quilt publish their loaders once under a placeholder game
+/// version that applies to every game version they list.
+fn is_placeholder(id: &str) -> bool {
+    id.starts_with("${") && id.ends_with(".gameVersion}")
+}
+
+/// Split a loader manifest `versions` array by game version
+fn game_versions(versions: &Value) -> Vec<(String, GameBuilds)> {
+    let Some(entries) = versions.as_array() else {
+        return Vec::new();
+    };
+
+    let shared: Vec<Value> = entries
+        .iter()
+        .filter(|entry| is_placeholder(entry_id(entry)))
+        .filter_map(|entry| entry.get("loaders").and_then(Value::as_array))
+        .flatten()
+        .cloned()
+        .collect();
+
+    entries
+        .iter()
+        .filter(|entry| !entry_id(entry).is_empty() && !is_placeholder(entry_id(entry)))
+        .filter_map(|entry| {
+            let id = entry_id(entry).to_string();
+
+            match entry.get("loaders").and_then(Value::as_array) {
+                Some(loaders) if !loaders.is_empty() => Some((id, GameBuilds::Loader(loaders.clone()))),
+                Some(_) if !shared.is_empty() => Some((id, GameBuilds::Loader(shared.clone()))),
+                Some(_) => None,
+                None => Some((id, GameBuilds::Minecraft(entry.clone()))),
+            }
+        })
+        .collect()
+}
+
+/// Compare two version ids by their numeric and textual segments
+///
+/// Loader manifests are not consistently ordered (Forge lists builds newest
+/// first, NeoForge oldest first), so `latest` is computed instead of taken
+/// from the position. `47.1.10` sorts after `47.1.9`; at equal segments a
+/// release sorts after its pre-release (`20.4.80` > `20.4.80-beta`).
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    fn segments(s: &str) -> Vec<&str> {
+        let mut segments = Vec::new();
+        let mut start = 0;
+        let bytes = s.as_bytes();
+        for i in 1..=bytes.len() {
+            if i == bytes.len() || bytes[i].is_ascii_digit() != bytes[i - 1].is_ascii_digit() {
+                segments.push(&s[start..i]);
+                start = i;
+            }
+        }
+        segments
+    }
+
+    let (a_segments, b_segments) = (segments(a), segments(b));
+
+    for (x, y) in a_segments.iter().zip(&b_segments) {
+        let ordering = match (x.parse::<u64>(), y.parse::<u64>()) {
+            (Ok(x), Ok(y)) => x.cmp(&y),
+            _ => x.cmp(y),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+
+    // Extra segments mark a pre-release suffix (`-beta`) or a longer
+    // numeric version (`1.20` < `1.20.1`)
+    let suffix_is_numeric = |segments: &[&str], len: usize| {
+        segments
+            .get(len)
+            .is_some_and(|segment| segment == &"." || segment.parse::<u64>().is_ok())
+    };
+
+    match a_segments.len().cmp(&b_segments.len()) {
+        Ordering::Greater if !suffix_is_numeric(&a_segments, b_segments.len()) => Ordering::Less,
+        Ordering::Less if !suffix_is_numeric(&b_segments, a_segments.len()) => Ordering::Greater,
+        ordering => ordering,
+    }
+}
+
+/// Derive the indexes of the loader manifests a root manifest points at
+#[instrument(skip_all, fields(loaders = root.loaders.len()))]
+pub async fn load(s3_client: &s3::Bucket, root: &RootManifest) -> Result<IndexSet, Error> {
+    let mut manifests = HashMap::new();
+    for (loader, reference) in &root.loaders {
+        manifests.insert(
+            loader.clone(),
+            crate::services::history::load_loader_manifest(s3_client, &reference.url).await?,
+        );
+    }
+
+    IndexSet::build(&manifests.iter().map(|(loader, manifest)| (loader.clone(), manifest)).collect())
+}
+
+/// Upload the indexes that changed since the previous cycle
+///
+/// `previous` is derived from the previously live loader manifests, or empty
+/// when the previous root did not advertise indexes (first deployment,
+/// rollback), in which case everything is uploaded. Everything is also
+/// uploaded after a publish that failed, as the bucket may then hold indexes of
+/// neither set.
+///
+/// # Returns
+///
+/// The public URLs of the indexes that were rewritten or deleted, for the CDN
+/// purge
+#[instrument(skip_all, fields(indexes = current.len()))]
+pub async fn publish(
+    s3_client: &s3::Bucket,
+    current: &IndexSet,
+    previous: &IndexSet,
+    semaphore: Arc<Semaphore>,
+) -> Result<Vec<String>, Error> {
+    let empty = IndexSet::default();
+    let stale = STALE.swap(true, AtomicOrdering::SeqCst);
+    let changed = current.changed_since(if stale { &empty } else { previous });
+    let removed = current.removed_since(previous);
+    let mut urls = Vec::new();
+
+    for (path, bytes) in &changed {
+        crate::upload_file_to_bucket(
+            (*path).clone(),
+            (*bytes).clone(),
+            Some("application/json".to_string()),
+            &tokio::sync::Mutex::new(Vec::new()),
+            semaphore.clone(),
+        )
+        .await?;
+        urls.push(crate::format_url(path));
+    }
+    STALE.store(false, AtomicOrdering::SeqCst);
+
+    for path in &removed {
+        match crate::services::bucket::delete_object(s3_client, path).await {
+            Ok(()) => urls.push(crate::format_url(path)),
+            Err(err) => warn!(path = %path, error = %err, "Failed to delete stale index"),
+        }
+    }
+
+    info!(
+        total = current.len(),
+        uploaded = changed.len(),
+        removed = removed.len(),
+        "🗂️ Indexes published"
+    );
+
+    Ok(urls)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(loader: &str, versions: Value) -> LoaderManifest {
+        LoaderManifest::new(loader.to_string(), versions)
+    }
+
+    fn document(set: &IndexSet, path: &str) -> Value {
+        serde_json::from_slice(&set.documents[path]).unwrap()
+    }
+
+    #[test]
+    fn test_compare_versions() {
+        assert_eq!(compare_versions("1.20.1-47.1.10", "1.20.1-47.1.9"), Ordering::Greater);
+        assert_eq!(compare_versions("20.4.80-beta", "20.4.80"), Ordering::Less);
+        assert_eq!(compare_versions("1.20", "1.20.1"), Ordering::Less);
+        assert_eq!(compare_versions("0.15.7", "0.15.7"), Ordering::Equal);
+        assert_eq!(compare_versions("0.16.0", "0.15.11"), Ordering::Greater);
+    }
+
+    #[test]
+    fn test_build_indexes() {
+        let neoforge = manifest(
+            "neoforge",
+            serde_json::json!([
+                { "id": "1.20.4", "stable": true, "loaders": [
+                    { "id": "20.4.80-beta", "url": "https://cdn/a", "stable": false },
+                    { "id": "20.4.190", "url": "https://cdn/b", "stable": true },
+                    { "id": "20.4.200-beta", "url": "https://cdn/c", "stable": false },
+                ]},
+                { "id": "1.20.3", "stable": true, "loaders": [] },
+            ]),
+        );
+        let fabric = manifest(
+            "fabric",
+            serde_json::json!([
+                { "id": "${modrinth.gameVersion}", "stable": true, "loaders": [
+                    { "id": "0.15.7", "url": "https://cdn/f", "stable": true },
+                ]},
+                { "id": "1.20.4", "stable": true, "loaders": [] },
+            ]),
+        );
+        let minecraft = manifest(
+            "minecraft",
+            serde_json::json!([
+                { "id": "1.20.4", "type": "release", "url": "https://cdn/mc" },
+                { "id": "1.20.3", "type": "release", "url": "https://cdn/mc2" },
+            ]),
+        );
+
+        let manifests = HashMap::from([
+            ("neoforge".to_string(), &neoforge),
+            ("fabric".to_string(), &fabric),
+            ("minecraft".to_string(), &minecraft),
+        ]);
+        let set = IndexSet::build(&manifests).unwrap();
+
+        let neoforge_index = document(&set, &loader_index_path("neoforge", "1.20.4"));
+        assert_eq!(neoforge_index["latest"], "20.4.200-beta");
+        assert_eq!(neoforge_index["recommended"], "20.4.190");
+        assert_eq!(neoforge_index["versions"][0]["id"], "20.4.200-beta");
+
+        let game = document(&set, &game_index_path("1.20.4"));
+        assert_eq!(game["minecraft"]["type"], "release");
+        assert_eq!(game["loaders"]["fabric"]["latest"], "0.15.7");
+        assert_eq!(game["loaders"]["neoforge"]["count"], 3);
+
+        // Only a Minecraft entry and no loaders
+        assert!(!set.documents.contains_key(&game_index_path("1.20.3")));
+        assert!(!set.documents.contains_key(&loader_index_path("fabric", "${modrinth.gameVersion}")));
+        assert_eq!(set.len(), 3);
+    }
+
+    #[test]
+    fn test_changes_since_previous() {
+        let old = manifest(
+            "forge",
+            serde_json::json!([
+                { "id": "1.20.1", "stable": true, "loaders": [{ "id": "47.1.0", "url": "https://cdn/a", "stable": true }] },
+                { "id": "1.19.4", "stable": true, "loaders": [{ "id": "45.0.0", "url": "https://cdn/b", "stable": true }] },
+            ]),
+        );
+        let new = manifest(
+            "forge",
+            serde_json::json!([
+                { "id": "1.20.1", "stable": true, "loaders": [{ "id": "47.1.0", "url": "https://cdn/a", "stable": true }] },
+            ]),
+        );
+
+        let previous = IndexSet::build(&HashMap::from([("forge".to_string(), &old)])).unwrap();
+        let current = IndexSet::build(&HashMap::from([("forge".to_string(), &new)])).unwrap();
+
+        assert!(current.changed_since(&previous).is_empty());
+        assert_eq!(current.changed_since(&IndexSet::default()).len(), 2);
+        assert_eq!(
+            current.removed_since(&previous),
+            vec![&loader_index_path("forge", "1.19.4"), &game_index_path("1.19.4")]
+        );
+    }
+}
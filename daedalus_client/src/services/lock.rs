@@ -0,0 +1,371 @@
+//! Bucket-wide run lock
+//!
+//! Two instances publishing to the same bucket race on the root manifest and
+//! one can revert what the other just added, which happens briefly during
+//! every deploy. Commands that publish therefore hold a lease stored next to
+//! the root manifest:
+//!
+//! ```text
+//! v{CAS_VERSION}/lock.json   {"owner": "...", "id": "...", "acquired_at": "...", "expires_at": "..."}
+//! ```
+//!
+//! The lease is written with conditional PUTs (`If-None-Match: *` when there
+//! is none, `If-Match: <etag>` to renew it or take over an expired one), so
+//! of two instances racing for it exactly one wins. The holder renews it every
+//! third of `lock.ttl_secs`; a crashed holder simply stops renewing and its
+//! lease expires. A holder that cannot renew in time has lost the lock and
+//! requests a shutdown, which skips the commit of the running cycle.
+
+use crate::infrastructure::config::LockConfig;
+use crate::infrastructure::error::{Error, ErrorKind};
+use crate::infrastructure::shutdown;
+use crate::services::bucket::{get_object_with_etag, put_object_if, WriteCondition};
+use crate::services::cas::CAS_VERSION;
+use chrono::{DateTime, Utc};
+use s3::Bucket;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{error, info, instrument, warn};
+
+/// How often a waiting instance checks the lock again
+const POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Bucket path of the lock object
+pub fn lock_path() -> String {
+    format!("v{}/lock.json", CAS_VERSION)
+}
+
+/// Content of the lock object
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lease {
+    /// Human readable holder, `<owner>:<pid>`
+    pub owner: String,
+    /// Unique per process, tells our own lease apart from any other
+    pub id: String,
+    pub acquired_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Lease {
+    fn new(owner: String, id: String, now: DateTime<Utc>, ttl: Duration) -> Self {
+        Self {
+            owner,
+            id,
+            acquired_at: now,
+            expires_at: expiry(now, ttl),
+        }
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at <= now
+    }
+
+    /// The same lease with its expiry pushed out from `now`
+    fn renewed(&self, now: DateTime<Utc>, ttl: Duration) -> Self {
+        Self {
+            expires_at: expiry(now, ttl),
+            ..self.clone()
+        }
+    }
+}
+
+fn expiry(now: DateTime<Utc>, ttl: Duration) -> DateTime<Utc> {
+    now + chrono::Duration::from_std(ttl).unwrap_or(chrono::Duration::MAX)
+}
+
+/// What to do about the lock object as it is now
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step {
+    /// No lock object, create it
+    Create,
+    /// The lease expired (or is unreadable), replace it
+    TakeOver,
+    /// Another instance holds a live lease
+    Wait { owner: String, expires_at: DateTime<Utc> },
+}
+
+/// `current` is the lease found in the bucket, `Some(None)` when the object
+/// exists but cannot be parsed
+fn next_step(current: Option<Option<&Lease>>, now: DateTime<Utc>) -> Step {
+    match current {
+        None => Step::Create,
+        Some(Some(lease)) if !lease.is_expired(now) => Step::Wait {
+            owner: lease.owner.clone(),
+            expires_at: lease.expires_at,
+        },
+        Some(_) => Step::TakeOver,
+    }
+}
+
+/// The run lock of this process, released with [`RunLock::release`]
+///
+/// Dropping it without releasing stops the renewals, so the lease expires
+/// after its TTL.
+pub struct RunLock {
+    held: Option<Held>,
+}
+
+struct Held {
+    s3_client: Bucket,
+    /// Current lease and the ETag it was written with
+    lease: Arc<Mutex<(Lease, String)>>,
+    heartbeat: tokio::task::JoinHandle<()>,
+}
+
+impl RunLock {
+    /// Take the run lock, waiting for another holder when `lock.wait` is set
+    ///
+    /// Does nothing when the lock is disabled or during a dry run, which
+    /// never writes to the bucket.
+    #[instrument(skip_all)]
+    pub async fn acquire(s3_client: &Bucket, config: &LockConfig) -> Result<Self, Error> {
+        if !config.enabled || crate::services::dry_run::is_enabled() {
+            return Ok(Self { held: None });
+        }
+
+        let ttl = Duration::from_secs(config.ttl_secs);
+        let owner = format!("{}:{}", owner_name(config), std::process::id());
+        let id = lease_id();
+        let path = lock_path();
+        let mut announced_wait = false;
+
+        loop {
+            shutdown::check()?;
+
+            let current = read(s3_client).await?;
+            let now = Utc::now();
+            let lease = Lease::new(owner.clone(), id.clone(), now, ttl);
+
+            let written = match next_step(
+                current.as_ref().map(|(lease, _)| lease.as_ref()),
+                now,
+            ) {
+                Step::Create => write(s3_client, &lease, None).await?,
+                Step::TakeOver => {
+                    let (previous, etag) = current.expect("take over requires a lock object");
+                    if let Some(previous) = previous {
+                        warn!(
+                            previous_owner = %previous.owner,
+                            expired_at = %previous.expires_at,
+                            "Taking over an expired run lock"
+                        );
+                    }
+                    write(s3_client, &lease, Some(&etag)).await?
+                }
+                Step::Wait { owner: holder, expires_at } => {
+                    if !config.wait {
+                        return Err(ErrorKind::LockHeld {
+                            owner: holder,
+                            expires_at: expires_at.to_rfc3339(),
+                        });
+                    }
+                    if !announced_wait {
+                        info!(holder = %holder, expires_at = %expires_at, "⏳ Run lock held by another instance, waiting");
+                        announced_wait = true;
+                    }
+
+                    tokio::select! {
+                        _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                        _ = shutdown::requested() => return Err(ErrorKind::Cancelled),
+                    }
+                    continue;
+                }
+            };
+
+            // Another instance won the race, look again
+            let Some(etag) = written else {
+                continue;
+            };
+
+            info!(path = %path, owner = %owner, ttl_secs = config.ttl_secs, "🔒 Run lock acquired");
+            let lease = Arc::new(Mutex::new((lease, etag)));
+            let heartbeat = tokio::spawn(renew_loop(s3_client.clone(), Arc::clone(&lease), ttl));
+
+            return Ok(Self {
+                held: Some(Held {
+                    s3_client: s3_client.clone(),
+                    lease,
+                    heartbeat,
+                }),
+            });
+        }
+    }
+
+    /// Stop renewing and delete the lock object if it is still ours
+    pub async fn release(mut self) {
+        let Some(held) = self.held.take() else {
+            return;
+        };
+        held.heartbeat.abort();
+
+        let (ours, _) = held.lease.lock().expect("lease mutex poisoned").clone();
+        match read(&held.s3_client).await {
+            Ok(Some((Some(current), _))) if current.id == ours.id => {
+                match crate::services::bucket::delete_object(&held.s3_client, &lock_path()).await {
+                    Ok(()) => info!("🔓 Run lock released"),
+                    Err(err) => warn!(error = %err, "Failed to release the run lock, it expires on its own"),
+                }
+            }
+            Ok(_) => warn!("Run lock is no longer ours, leaving it in place"),
+            Err(err) => warn!(error = %err, "Failed to read the run lock, it expires on its own"),
+        }
+    }
+}
+
+/// Run `work` while holding the run lock
+///
+/// The lock is released whether `work` succeeds or fails.
+pub async fn with_lock<T>(
+    s3_client: &Bucket,
+    config: &LockConfig,
+    work: impl Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+    let lock = RunLock::acquire(s3_client, config).await?;
+    let result = work.await;
+    lock.release().await;
+    result
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        if let Some(held) = &self.held {
+            held.heartbeat.abort();
+        }
+    }
+}
+
+/// Renew the lease every third of its TTL until aborted
+///
+/// Losing the lease requests a shutdown so nothing is committed without it.
+async fn renew_loop(s3_client: Bucket, lease: Arc<Mutex<(Lease, String)>>, ttl: Duration) {
+    let mut timer = tokio::time::interval(ttl / 3);
+    timer.tick().await;
+
+    loop {
+        timer.tick().await;
+
+        let (current, etag) = lease.lock().expect("lease mutex poisoned").clone();
+        let renewed = current.renewed(Utc::now(), ttl);
+
+        match write(&s3_client, &renewed, Some(&etag)).await {
+            Ok(Some(etag)) => {
+                *lease.lock().expect("lease mutex poisoned") = (renewed, etag);
+            }
+            Ok(None) => {
+                error!("Run lock was taken over by another instance, shutting down");
+                shutdown::request();
+                return;
+            }
+            Err(err) if current.is_expired(Utc::now()) => {
+                error!(error = %err, "Run lock expired before it could be renewed, shutting down");
+                shutdown::request();
+                return;
+            }
+            Err(err) => {
+                warn!(error = %err, "Failed to renew the run lock, retrying");
+            }
+        }
+    }
+}
+
+/// The lock object and its ETag, `None` when there is no lock
+///
+/// The lease is `None` when the object exists but cannot be parsed.
+async fn read(s3_client: &Bucket) -> Result<Option<(Option<Lease>, String)>, Error> {
+    let path = lock_path();
+    let Some((bytes, etag)) = get_object_with_etag(s3_client, &path).await? else {
+        return Ok(None);
+    };
+
+    let lease = match serde_json::from_slice(&bytes) {
+        Ok(lease) => Some(lease),
+        Err(err) => {
+            warn!(path = %path, error = %err, "Unreadable run lock, treating it as expired");
+            None
+        }
+    };
+
+    Ok(Some((lease, etag.unwrap_or_default())))
+}
+
+/// Write `lease` if the lock object is still at `etag` (or absent for `None`)
+///
+/// Returns the new ETag, or `None` when the precondition failed because
+/// another instance wrote the lock first.
+async fn write(s3_client: &Bucket, lease: &Lease, etag: Option<&str>) -> Result<Option<String>, Error> {
+    let condition = match etag {
+        Some(etag) => WriteCondition::IfMatch(etag.to_string()),
+        None => WriteCondition::IfAbsent,
+    };
+
+    match put_object_if(
+        s3_client,
+        &lock_path(),
+        &serde_json::to_vec(lease)?,
+        "application/json",
+        &condition,
+    )
+    .await
+    {
+        Ok(etag) => Ok(Some(etag)),
+        Err(ErrorKind::Conflict { .. }) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+fn owner_name(config: &LockConfig) -> String {
+    config
+        .owner
+        .clone()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .or_else(|| {
+            std::fs::read_to_string("/etc/hostname")
+                .ok()
+                .map(|name| name.trim().to_string())
+        })
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "daedalus".to_string())
+}
+
+fn lease_id() -> String {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default();
+    format!("{:x}-{:x}", std::process::id(), nanos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_next_step() {
+        let now = Utc.with_ymd_and_hms(2024, 1, 15, 10, 0, 0).unwrap();
+        let lease = Lease::new(
+            "worker-a:1".to_string(),
+            "1-abc".to_string(),
+            now - chrono::Duration::minutes(2),
+            Duration::from_secs(300),
+        );
+
+        assert_eq!(next_step(None, now), Step::Create);
+        assert_eq!(
+            next_step(Some(Some(&lease)), now),
+            Step::Wait {
+                owner: "worker-a:1".to_string(),
+                expires_at: now + chrono::Duration::minutes(3),
+            }
+        );
+        // A crashed holder's lease runs out
+        assert_eq!(next_step(Some(Some(&lease)), now + chrono::Duration::minutes(3)), Step::TakeOver);
+        assert_eq!(next_step(Some(None), now), Step::TakeOver);
+
+        let renewed = lease.renewed(now, Duration::from_secs(300));
+        assert_eq!(renewed.acquired_at, lease.acquired_at);
+        assert_eq!(renewed.expires_at, now + chrono::Duration::minutes(5));
+    }
+}
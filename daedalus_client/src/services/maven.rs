@@ -0,0 +1,612 @@
+//! Maven repository view of rehosted artifacts
+//!
+//! Libraries are stored in the CAS under their hash, which Maven tooling
+//! cannot resolve. Every artifact rehosted through
+//! [`BatchUploader::upload_maven_artifact`](crate::services::upload::BatchUploader::upload_maven_artifact)
+//! is therefore also published in the standard layout under `maven/`:
+//!
+//! ```text
+//! maven/<group>/<artifact>/maven-metadata.xml(.sha1|.sha256)
+//! maven/<group>/<artifact>/<version>/<artifact>-<version>.pom(.sha1|.sha256)
+//! maven/<group>/<artifact>/<version>/<file>(.sha1|.sha256)
+//! ```
+//!
+//! Artifact files are server-side copies of their CAS object. POMs are
+//! generated (no dependencies are declared, launchers resolve those from the
+//! version JSONs), and `maven-metadata.xml` lists every version present in
+//! the bucket, not only the ones rehosted this cycle. Maven paths never
+//! change content, so files already published are left alone.
+//!
+//! Versions skipped by change detection rehost nothing, so [`backfill`]
+//! (the `maven-backfill` command) publishes every library the version JSONs
+//! of the live root manifest reference.
+
+use crate::infrastructure::error::Error;
+use crate::services::bucket::{copy_object, get_object, list_objects, object_metadata};
+use crate::services::cas::{RootManifest, CAS_VERSION};
+use crate::services::history::load_loader_manifest;
+use crate::services::index::compare_versions;
+use crate::services::metadata::sha1_hex;
+use crate::services::migration::{manifest_hashes, object_path, referenced_hashes};
+use crate::services::upload::BatchUploader;
+use daedalus::{Branding, BRANDING};
+use futures::stream::{self, StreamExt};
+use s3::Bucket;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tracing::{error, info, instrument, warn};
+
+/// Bucket prefix of the maven repository
+pub const MAVEN_PREFIX: &str = "maven/";
+
+/// Number of artifacts published concurrently
+const MAX_CONCURRENT_ARTIFACTS: usize = 10;
+
+/// Number of version JSONs read concurrently by [`backfill`]
+const MAX_CONCURRENT_DOCUMENTS: usize = 20;
+
+/// A rehosted artifact and the CAS object holding it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MavenArtifact {
+    /// SHA-256 of the content, which is also its CAS hash
+    pub hash: String,
+    /// SHA-1 of the content
+    pub sha1: String,
+}
+
+/// Maven coordinates parsed from an artifact path
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Coordinates {
+    pub group: String,
+    pub artifact: String,
+    pub version: String,
+    pub classifier: Option<String>,
+    pub extension: String,
+}
+
+impl Coordinates {
+    /// Parse a maven layout path such as
+    /// `net/minecraftforge/forge/1.20.1-47.1.0/forge-1.20.1-47.1.0-universal.jar`
+    pub fn parse(path: &str) -> Option<Self> {
+        let parts: Vec<&str> = path.split('/').collect();
+        if parts.len() < 4 || parts.iter().any(|part| part.is_empty()) {
+            return None;
+        }
+
+        let n = parts.len();
+        let (file, version, artifact) = (parts[n - 1], parts[n - 2], parts[n - 3]);
+        let rest = file.strip_prefix(&format!("{}-{}", artifact, version))?;
+
+        let (classifier, extension) = if let Some(rest) = rest.strip_prefix('-') {
+            let (classifier, extension) = rest.split_once('.')?;
+            (Some(classifier.to_string()), extension)
+        } else {
+            (None, rest.strip_prefix('.')?)
+        };
+
+        Some(Self {
+            group: parts[..n - 3].join("."),
+            artifact: artifact.to_string(),
+            version: version.to_string(),
+            classifier,
+            extension: extension.to_string(),
+        })
+    }
+
+    /// `maven/<group>/<artifact>/`
+    pub fn artifact_dir(&self) -> String {
+        format!("{}{}/{}/", MAVEN_PREFIX, self.group.replace('.', "/"), self.artifact)
+    }
+
+    /// `maven/<group>/<artifact>/<version>/<artifact>-<version>.pom`
+    pub fn pom_path(&self) -> String {
+        format!(
+            "{}{}/{}-{}.pom",
+            self.artifact_dir(),
+            self.version,
+            self.artifact,
+            self.version
+        )
+    }
+
+    /// `maven/<group>/<artifact>/maven-metadata.xml`
+    pub fn metadata_path(&self) -> String {
+        format!("{}maven-metadata.xml", self.artifact_dir())
+    }
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Minimal POM for a rehosted artifact
+pub fn render_pom(coordinates: &Coordinates) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<project xmlns="http://maven.apache.org/POM/4.0.0" xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:schemaLocation="http://maven.apache.org/POM/4.0.0 http://maven.apache.org/xsd/maven-4.0.0.xsd">
+  <modelVersion>4.0.0</modelVersion>
+  <groupId>{}</groupId>
+  <artifactId>{}</artifactId>
+  <version>{}</version>
+</project>
+"#,
+        escape_xml(&coordinates.group),
+        escape_xml(&coordinates.artifact),
+        escape_xml(&coordinates.version)
+    )
+}
+
+/// `maven-metadata.xml` listing `versions`
+///
+/// `last_updated` uses the maven `yyyyMMddHHmmss` format.
+pub fn render_metadata(group: &str, artifact: &str, versions: &[String], last_updated: &str) -> String {
+    let mut versions = versions.to_vec();
+    versions.sort_by(|a, b| compare_versions(a, b));
+    versions.dedup();
+
+    let latest = versions.last().cloned().unwrap_or_default();
+    let release = versions
+        .iter()
+        .rev()
+        .find(|version| !version.ends_with("-SNAPSHOT"))
+        .cloned()
+        .unwrap_or_default();
+
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<metadata>\n");
+    xml.push_str(&format!("  <groupId>{}</groupId>\n", escape_xml(group)));
+    xml.push_str(&format!("  <artifactId>{}</artifactId>\n", escape_xml(artifact)));
+    xml.push_str("  <versioning>\n");
+    xml.push_str(&format!("    <latest>{}</latest>\n", escape_xml(&latest)));
+    xml.push_str(&format!("    <release>{}</release>\n", escape_xml(&release)));
+    xml.push_str("    <versions>\n");
+    for version in &versions {
+        xml.push_str(&format!("      <version>{}</version>\n", escape_xml(version)));
+    }
+    xml.push_str("    </versions>\n");
+    xml.push_str(&format!("    <lastUpdated>{}</lastUpdated>\n", last_updated));
+    xml.push_str("  </versioning>\n");
+    xml.push_str("</metadata>\n");
+    xml
+}
+
+/// Upload a file together with its `.sha1` and `.sha256` checksum files
+async fn upload_with_checksums(
+    path: &str,
+    bytes: Vec<u8>,
+    content_type: &str,
+    semaphore: Arc<Semaphore>,
+) -> Result<(), Error> {
+    let sha1 = sha1_hex(&bytes);
+    let sha256 = BatchUploader::compute_hash(&bytes);
+
+    crate::upload_file_to_bucket(
+        path.to_string(),
+        bytes,
+        Some(content_type.to_string()),
+        &tokio::sync::Mutex::new(Vec::new()),
+        semaphore.clone(),
+    )
+    .await?;
+    upload_checksums(path, &sha1, &sha256, semaphore).await
+}
+
+async fn upload_checksums(
+    path: &str,
+    sha1: &str,
+    sha256: &str,
+    semaphore: Arc<Semaphore>,
+) -> Result<(), Error> {
+    for (extension, checksum) in [("sha1", sha1), ("sha256", sha256)] {
+        crate::upload_file_to_bucket(
+            format!("{}.{}", path, extension),
+            checksum.as_bytes().to_vec(),
+            Some("text/plain".to_string()),
+            &tokio::sync::Mutex::new(Vec::new()),
+            semaphore.clone(),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Publish one artifact file and its checksums
+async fn publish_artifact(
+    s3_client: &Bucket,
+    destination: &str,
+    artifact: &MavenArtifact,
+    semaphore: Arc<Semaphore>,
+) -> Result<(), Error> {
+    copy_object(s3_client, &object_path(CAS_VERSION, &artifact.hash), destination).await?;
+    upload_checksums(destination, &artifact.sha1, &artifact.hash, semaphore).await
+}
+
+/// Keys of every object under `maven/`
+async fn published_keys(s3_client: &Bucket) -> Result<BTreeSet<String>, Error> {
+    Ok(list_objects(s3_client, MAVEN_PREFIX)
+        .await?
+        .into_iter()
+        .map(|object| object.key)
+        .collect())
+}
+
+/// Versions present under an artifact directory among `keys`
+fn published_versions(keys: &BTreeSet<String>, artifact_dir: &str) -> Vec<String> {
+    let versions: BTreeSet<&str> = keys
+        .range(artifact_dir.to_string()..)
+        .take_while(|key| key.starts_with(artifact_dir))
+        .filter_map(|key| {
+            let (version, file) = key[artifact_dir.len()..].split_once('/')?;
+            (!file.is_empty()).then_some(version)
+        })
+        .collect();
+
+    versions.into_iter().map(str::to_string).collect()
+}
+
+/// Publish the maven view of the artifacts rehosted this cycle
+///
+/// Failures of individual artifacts are logged and do not stop the others.
+///
+/// # Returns
+///
+/// The public URLs of the rewritten `maven-metadata.xml` files and their
+/// checksums, which need a CDN purge
+#[instrument(skip_all, fields(artifacts = artifacts.len()))]
+pub async fn publish(
+    s3_client: &Bucket,
+    artifacts: Vec<(String, MavenArtifact)>,
+    semaphore: Arc<Semaphore>,
+) -> Result<Vec<String>, Error> {
+    if artifacts.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let keys = published_keys(s3_client).await?;
+    publish_missing(s3_client, artifacts, keys, semaphore).await
+}
+
+/// Publish the artifacts, POMs and `maven-metadata.xml` files missing from
+/// `keys`, the objects under `maven/`
+async fn publish_missing(
+    s3_client: &Bucket,
+    artifacts: Vec<(String, MavenArtifact)>,
+    mut keys: BTreeSet<String>,
+    semaphore: Arc<Semaphore>,
+) -> Result<Vec<String>, Error> {
+    let artifacts: Vec<(Coordinates, String, MavenArtifact)> = artifacts
+        .into_iter()
+        .filter_map(|(path, artifact)| match Coordinates::parse(&path) {
+            Some(coordinates) => Some((coordinates, format!("{}{}", MAVEN_PREFIX, path), artifact)),
+            None => {
+                info!(path = %path, "Not a maven layout path, skipping maven publication");
+                None
+            }
+        })
+        .filter(|(_, destination, _)| !keys.contains(destination))
+        .collect();
+
+    if artifacts.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // 1. Artifact files
+    let results: Vec<(&String, Result<(), Error>)> = stream::iter(&artifacts)
+        .map(|(_, destination, artifact)| {
+            let semaphore = semaphore.clone();
+            async move {
+                let result = publish_artifact(s3_client, destination, artifact, semaphore).await;
+                if let Err(err) = &result {
+                    error!(path = %destination, error = %err, "Failed to publish maven artifact");
+                }
+                (destination, result)
+            }
+        })
+        .buffer_unordered(MAX_CONCURRENT_ARTIFACTS)
+        .collect()
+        .await;
+
+    let failed = results.iter().filter(|(_, result)| result.is_err()).count();
+    keys.extend(
+        results
+            .iter()
+            .filter(|(_, result)| result.is_ok())
+            .map(|(destination, _)| (*destination).clone()),
+    );
+
+    // 2. POMs, one per version
+    let versions: BTreeSet<Coordinates> = artifacts
+        .iter()
+        .map(|(coordinates, _, _)| Coordinates {
+            classifier: None,
+            extension: "pom".to_string(),
+            ..coordinates.clone()
+        })
+        .collect();
+
+    for coordinates in &versions {
+        let path = coordinates.pom_path();
+        if keys.contains(&path) {
+            continue;
+        }
+        match upload_with_checksums(
+            &path,
+            render_pom(coordinates).into_bytes(),
+            "application/xml",
+            semaphore.clone(),
+        )
+        .await
+        {
+            Ok(()) => {
+                keys.insert(path);
+            }
+            Err(err) => error!(path = %path, error = %err, "Failed to publish POM"),
+        }
+    }
+
+    // 3. maven-metadata.xml, one per artifact
+    let last_updated = chrono::Utc::now().format("%Y%m%d%H%M%S").to_string();
+    let mut purge_urls = Vec::new();
+    let mut artifact_ids: BTreeMap<(&str, &str), &Coordinates> = BTreeMap::new();
+    for coordinates in &versions {
+        artifact_ids
+            .entry((coordinates.group.as_str(), coordinates.artifact.as_str()))
+            .or_insert(coordinates);
+    }
+
+    for coordinates in artifact_ids.values() {
+        let path = coordinates.metadata_path();
+        let published = published_versions(&keys, &coordinates.artifact_dir());
+        let xml = render_metadata(&coordinates.group, &coordinates.artifact, &published, &last_updated);
+
+        match upload_with_checksums(&path, xml.into_bytes(), "application/xml", semaphore.clone()).await {
+            Ok(()) => {
+                purge_urls.push(crate::format_url(&path));
+                purge_urls.push(crate::format_url(&format!("{}.sha1", path)));
+                purge_urls.push(crate::format_url(&format!("{}.sha256", path)));
+            }
+            Err(err) => error!(path = %path, error = %err, "Failed to publish maven-metadata.xml"),
+        }
+    }
+
+    info!(
+        artifacts = artifacts.len(),
+        failed,
+        poms = versions.len(),
+        metadata_files = artifact_ids.len(),
+        "📦 Maven repository updated"
+    );
+
+    Ok(purge_urls)
+}
+
+/// Hash addressed by a CAS object URL
+fn cas_hash(url: &str) -> Option<String> {
+    referenced_hashes(&Value::String(url.to_string()), CAS_VERSION).pop_first()
+}
+
+/// Maven paths and CAS hashes of the rehosted libraries of a version JSON
+///
+/// Libraries rehosted per game version (`version_hashes`) have a
+/// `${<brand>.gameVersion}` placeholder in their name, which is replaced by
+/// each game version.
+pub fn library_artifacts(document: &Value) -> Vec<(String, String)> {
+    let placeholder = &BRANDING.get_or_init(Branding::default).dummy_replace_string;
+    let mut artifacts = Vec::new();
+
+    for library in document.get("libraries").and_then(Value::as_array).into_iter().flatten() {
+        let Some(name) = library.get("name").and_then(Value::as_str) else {
+            continue;
+        };
+
+        if let Some(hashes) = library.get("version_hashes").and_then(Value::as_object) {
+            for (game_version, hash) in hashes {
+                if let (Ok(path), Some(hash)) = (
+                    daedalus::get_path_from_artifact(&name.replace(placeholder.as_str(), game_version)),
+                    hash.as_str(),
+                ) {
+                    artifacts.push((path, hash.to_string()));
+                }
+            }
+        } else if let Some(hash) = library.get("url").and_then(Value::as_str).and_then(cas_hash) {
+            if let Ok(path) = daedalus::get_path_from_artifact(name) {
+                artifacts.push((path, hash));
+            }
+        }
+
+        let artifact = library.get("downloads").and_then(|downloads| downloads.get("artifact"));
+        if let Some(artifact) = artifact {
+            let path = artifact.get("path").and_then(Value::as_str);
+            let hash = artifact.get("url").and_then(Value::as_str).and_then(cas_hash);
+            if let (Some(path), Some(hash)) = (path, hash) {
+                artifacts.push((path.to_string(), hash));
+            }
+        }
+    }
+
+    artifacts
+}
+
+/// Maven artifacts referenced by the version JSONs of a loader manifest
+async fn manifest_artifacts(s3_client: &Bucket, url: &str) -> Result<BTreeMap<String, String>, Error> {
+    let manifest = load_loader_manifest(s3_client, url).await?;
+    let documents: Vec<Vec<(String, String)>> = stream::iter(manifest_hashes(&manifest.versions, CAS_VERSION))
+        .map(|hash| async move {
+            match get_object(s3_client, &object_path(CAS_VERSION, &hash)).await {
+                Ok(Some(bytes)) => serde_json::from_slice::<Value>(&bytes)
+                    .map(|document| library_artifacts(&document))
+                    .unwrap_or_default(),
+                Ok(None) => Vec::new(),
+                Err(err) => {
+                    warn!(hash = %hash, error = %err, "Failed to read version JSON");
+                    Vec::new()
+                }
+            }
+        })
+        .buffer_unordered(MAX_CONCURRENT_DOCUMENTS)
+        .collect()
+        .await;
+
+    Ok(documents.into_iter().flatten().collect())
+}
+
+/// SHA-1 of a CAS object, from its metadata or else its content
+async fn object_sha1(s3_client: &Bucket, hash: &str) -> Result<Option<String>, Error> {
+    let path = object_path(CAS_VERSION, hash);
+    if let Some(sha1) = object_metadata(s3_client, &path)
+        .await?
+        .and_then(|mut metadata| metadata.remove("sha1"))
+    {
+        return Ok(Some(sha1));
+    }
+    Ok(get_object(s3_client, &path).await?.map(|bytes| sha1_hex(&bytes)))
+}
+
+/// Publish every library referenced by the live loader manifests that is
+/// missing under `maven/`
+///
+/// Covers artifacts rehosted before the maven view existed and those whose
+/// publication failed in an earlier cycle. The Minecraft manifest is skipped,
+/// its libraries are served by Mojang.
+///
+/// # Returns
+///
+/// The public URLs that need a CDN purge
+#[instrument(skip_all)]
+pub async fn backfill(s3_client: &Bucket, root: &RootManifest, semaphore: Arc<Semaphore>) -> Result<Vec<String>, Error> {
+    let mut referenced = BTreeMap::new();
+    for (loader, reference) in root.loaders.iter().filter(|(loader, _)| *loader != "minecraft") {
+        match manifest_artifacts(s3_client, &reference.url).await {
+            Ok(artifacts) => referenced.extend(artifacts),
+            Err(err) => warn!(loader = %loader, error = %err, "Failed to read loader manifest for the maven backfill"),
+        }
+    }
+
+    let keys = published_keys(s3_client).await?;
+    let missing: Vec<(String, String)> = referenced
+        .into_iter()
+        .filter(|(path, _)| !keys.contains(&format!("{}{}", MAVEN_PREFIX, path)))
+        .collect();
+    info!(missing = missing.len(), "Backfilling the maven repository");
+
+    let artifacts: Vec<(String, MavenArtifact)> = stream::iter(missing)
+        .map(|(path, hash)| async move {
+            match object_sha1(s3_client, &hash).await {
+                Ok(Some(sha1)) => Some((path, MavenArtifact { hash, sha1 })),
+                Ok(None) => {
+                    warn!(path = %path, hash = %hash, "Referenced maven artifact is missing from CAS");
+                    None
+                }
+                Err(err) => {
+                    warn!(path = %path, error = %err, "Failed to read maven artifact");
+                    None
+                }
+            }
+        })
+        .buffer_unordered(MAX_CONCURRENT_DOCUMENTS)
+        .filter_map(std::future::ready)
+        .collect()
+        .await;
+
+    publish_missing(s3_client, artifacts, keys, semaphore).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_coordinates() {
+        let coordinates = Coordinates::parse(
+            "net/minecraftforge/forge/1.20.1-47.1.0/forge-1.20.1-47.1.0-universal.jar",
+        )
+        .unwrap();
+
+        assert_eq!(coordinates.group, "net.minecraftforge");
+        assert_eq!(coordinates.artifact, "forge");
+        assert_eq!(coordinates.version, "1.20.1-47.1.0");
+        assert_eq!(coordinates.classifier.as_deref(), Some("universal"));
+        assert_eq!(coordinates.extension, "jar");
+        assert_eq!(
+            coordinates.pom_path(),
+            "maven/net/minecraftforge/forge/1.20.1-47.1.0/forge-1.20.1-47.1.0.pom"
+        );
+        assert_eq!(
+            coordinates.metadata_path(),
+            "maven/net/minecraftforge/forge/maven-metadata.xml"
+        );
+
+        let intermediary =
+            Coordinates::parse("net/fabricmc/intermediary/1.20.1/intermediary-1.20.1.jar").unwrap();
+        assert_eq!(intermediary.classifier, None);
+
+        assert_eq!(Coordinates::parse("forge-1.20.1.jar"), None);
+        assert_eq!(Coordinates::parse("net/example/lib/1.0/other-1.0.jar"), None);
+    }
+
+    #[test]
+    fn test_render_metadata() {
+        let versions = vec![
+            "1.20.1-47.1.10".to_string(),
+            "1.20.1-47.1.9".to_string(),
+            "1.21-SNAPSHOT".to_string(),
+        ];
+        let xml = render_metadata("net.minecraftforge", "forge", &versions, "20240115103000");
+
+        assert!(xml.contains("<latest>1.21-SNAPSHOT</latest>"));
+        assert!(xml.contains("<release>1.20.1-47.1.10</release>"));
+        let versions = &xml[xml.find("<versions>").unwrap()..];
+        assert!(versions.find("47.1.9").unwrap() < versions.find("47.1.10").unwrap());
+        assert!(xml.contains("<lastUpdated>20240115103000</lastUpdated>"));
+    }
+
+    #[test]
+    fn test_library_artifacts() {
+        let cas = |hash: &str| format!("https://cdn.example.com/v{}/objects/{}/{}", CAS_VERSION, &hash[..2], &hash[2..]);
+        let placeholder = &BRANDING.get_or_init(Branding::default).dummy_replace_string;
+        let document = serde_json::json!({
+            "libraries": [
+                { "name": "net.fabricmc:fabric-loader:0.15.0", "url": cas("aabb") },
+                { "name": format!("net.fabricmc:intermediary:{}", placeholder), "version_hashes": { "1.20.1": "ccdd" } },
+                { "name": "org.ow2.asm:asm:9.6", "url": "https://maven.fabricmc.net/" },
+                { "name": "net.minecraftforge:forge:1.20.1-47.1.0:universal", "downloads": { "artifact": {
+                    "path": "net/minecraftforge/forge/1.20.1-47.1.0/forge-1.20.1-47.1.0-universal.jar",
+                    "url": cas("eeff"),
+                }}},
+            ]
+        });
+
+        assert_eq!(
+            library_artifacts(&document),
+            [
+                ("net/fabricmc/fabric-loader/0.15.0/fabric-loader-0.15.0.jar".to_string(), "aabb".to_string()),
+                ("net/fabricmc/intermediary/1.20.1/intermediary-1.20.1.jar".to_string(), "ccdd".to_string()),
+                (
+                    "net/minecraftforge/forge/1.20.1-47.1.0/forge-1.20.1-47.1.0-universal.jar".to_string(),
+                    "eeff".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_published_versions() {
+        let keys: BTreeSet<String> = [
+            "maven/net/example/lib/1.0/lib-1.0.jar",
+            "maven/net/example/lib/1.1/lib-1.1.pom",
+            "maven/net/example/lib/maven-metadata.xml",
+            "maven/net/example/library/2.0/library-2.0.jar",
+        ]
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+
+        assert_eq!(published_versions(&keys, "maven/net/example/lib/"), ["1.0", "1.1"]);
+    }
+}
@@ -0,0 +1,223 @@
+//! HTTP metadata attached to uploaded objects
+//!
+//! The bucket is served straight through the CDN, so the headers stored with
+//! each object decide how long clients and edges may cache it. They are
+//! derived from the object class, which follows from its path:
+//!
+//! | Class            | Path                                 | Cache-Control                  |
+//! |------------------|--------------------------------------|--------------------------------|
+//! | CAS object       | `v{N}/objects/..`                    | 1 year, `immutable`            |
+//! | Loader manifest  | `v{N}/manifests/<loader>/<ts>.json`  | 1 day                          |
+//! | History snapshot | `v{N}/history/manifest-<ts>.json`    | 1 day                          |
+//! | Root manifest    | `v{N}/manifest.json`                 | 1 minute, revalidate           |
+//! | Index            | `v{N}/index/..`                      | 5 minutes, revalidate          |
+//! | Static file      | anything else                        | 1 hour                         |
+//!
+//! Every object also carries its SHA-1 as `x-amz-meta-sha1` (served as a
+//! response header), and jars get a `Content-Disposition` with a readable
+//! file name since CAS keys are bare hashes.
+
+use crate::services::cas::{root_manifest_path, CAS_VERSION};
+use reqwest::header::{HeaderMap, HeaderValue, CACHE_CONTROL, CONTENT_DISPOSITION};
+use sha1::{Digest, Sha1};
+use tracing::warn;
+
+/// Custom header carrying the hex SHA-1 of the object content
+pub const SHA1_HEADER: &str = "x-amz-meta-sha1";
+
+/// Hex SHA-1 of `bytes`
+pub fn sha1_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha1::digest(bytes))
+}
+
+/// Kind of object, which decides its caching behaviour
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectClass {
+    /// Content-addressed object, never changes
+    CasObject,
+    /// Timestamped loader manifest
+    LoaderManifest,
+    /// Root manifest backup under `history/`
+    HistorySnapshot,
+    /// The live root manifest, rewritten every cycle
+    RootManifest,
+    /// Derived per-game-version index, rewritten when its content changes
+    Index,
+    /// Anything else (static CDN files, maven artifacts, ...)
+    Static,
+}
+
+impl ObjectClass {
+    /// Classify an object by its bucket path
+    pub fn from_path(path: &str) -> Self {
+        let versioned = |dir: &str| path.starts_with(&format!("v{}/{}/", CAS_VERSION, dir));
+
+        if path == root_manifest_path() {
+            ObjectClass::RootManifest
+        } else if versioned("objects") {
+            ObjectClass::CasObject
+        } else if versioned("manifests") {
+            ObjectClass::LoaderManifest
+        } else if versioned("history") {
+            ObjectClass::HistorySnapshot
+        } else if versioned("index") {
+            ObjectClass::Index
+        } else {
+            ObjectClass::Static
+        }
+    }
+
+    /// Cache-Control header value for this class
+    pub fn cache_control(self) -> &'static str {
+        match self {
+            ObjectClass::CasObject => "public, max-age=31536000, immutable",
+            ObjectClass::LoaderManifest | ObjectClass::HistorySnapshot => "public, max-age=86400",
+            ObjectClass::RootManifest => "public, max-age=60, must-revalidate",
+            ObjectClass::Index => "public, max-age=300, must-revalidate",
+            ObjectClass::Static => "public, max-age=3600",
+        }
+    }
+}
+
+/// Headers stored alongside an uploaded object
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectMetadata {
+    pub class: ObjectClass,
+    pub cache_control: &'static str,
+    pub content_disposition: Option<String>,
+    pub sha1: String,
+}
+
+impl ObjectMetadata {
+    /// Derive the metadata for an object from its path and content
+    ///
+    /// Jars outside of CAS get a `Content-Disposition` from their path; CAS
+    /// jars need [`ObjectMetadata::with_filename`].
+    pub fn new(path: &str, bytes: &[u8]) -> Self {
+        let class = ObjectClass::from_path(path);
+
+        let content_disposition = match class {
+            ObjectClass::CasObject => None,
+            _ => path
+                .rsplit('/')
+                .next()
+                .filter(|name| name.ends_with(".jar"))
+                .map(attachment),
+        };
+
+        Self {
+            class,
+            cache_control: class.cache_control(),
+            content_disposition,
+            sha1: sha1_hex(bytes),
+        }
+    }
+
+    /// Set the download file name
+    pub fn with_filename(mut self, filename: &str) -> Self {
+        self.content_disposition = Some(attachment(filename));
+        self
+    }
+
+    /// Request headers to send with the PUT
+    pub fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static(self.cache_control));
+        if let Ok(value) = HeaderValue::from_str(&self.sha1) {
+            headers.insert(SHA1_HEADER, value);
+        }
+
+        if let Some(disposition) = &self.content_disposition {
+            match HeaderValue::from_str(disposition) {
+                Ok(value) => {
+                    headers.insert(CONTENT_DISPOSITION, value);
+                }
+                Err(err) => {
+                    warn!(disposition = %disposition, error = %err, "Skipping invalid Content-Disposition");
+                }
+            }
+        }
+
+        headers
+    }
+
+    /// A bucket handle that sends these headers with every request
+    pub fn apply(&self, s3_client: &s3::Bucket) -> s3::Bucket {
+        let mut headers = s3_client.extra_headers().clone();
+        headers.extend(self.headers());
+        s3_client.with_extra_headers(headers)
+    }
+}
+
+/// `attachment` disposition with a quoted, header-safe file name
+fn attachment(filename: &str) -> String {
+    let sanitized: String = filename
+        .chars()
+        .map(|c| if c.is_ascii_graphic() && c != '"' && c != '\\' { c } else { '_' })
+        .collect();
+
+    format!("attachment; filename=\"{}\"", sanitized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_object_class_from_path() {
+        assert_eq!(
+            ObjectClass::from_path(&format!("v{}/objects/ab/cdef", CAS_VERSION)),
+            ObjectClass::CasObject
+        );
+        assert_eq!(
+            ObjectClass::from_path(&format!("v{}/manifests/forge/2024-01-15T10-30-00Z.json", CAS_VERSION)),
+            ObjectClass::LoaderManifest
+        );
+        assert_eq!(
+            ObjectClass::from_path(&format!("v{}/history/manifest-2024-01-15T10-30-00Z.json", CAS_VERSION)),
+            ObjectClass::HistorySnapshot
+        );
+        assert_eq!(ObjectClass::from_path(&root_manifest_path()), ObjectClass::RootManifest);
+        assert_eq!(
+            ObjectClass::from_path(&format!("v{}/index/game/1.20.1.json", CAS_VERSION)),
+            ObjectClass::Index
+        );
+        assert_eq!(ObjectClass::from_path("maven/net/minecraftforge/forge.jar"), ObjectClass::Static);
+        // Older layout versions are not ours to classify
+        assert_eq!(ObjectClass::from_path("v1/objects/ab/cdef"), ObjectClass::Static);
+    }
+
+    #[test]
+    fn test_metadata_headers() {
+        let path = format!("v{}/objects/2a/ae6c35", CAS_VERSION);
+        let metadata = ObjectMetadata::new(&path, b"hello world")
+            .with_filename("forge-1.20.1-47.1.0-universal.jar");
+        let headers = metadata.headers();
+
+        assert_eq!(headers[CACHE_CONTROL], "public, max-age=31536000, immutable");
+        assert_eq!(headers[SHA1_HEADER], "2aae6c35c94fcfb415dbe95f408b9ce91ee846ed");
+        assert_eq!(
+            headers[CONTENT_DISPOSITION],
+            "attachment; filename=\"forge-1.20.1-47.1.0-universal.jar\""
+        );
+    }
+
+    #[test]
+    fn test_static_jar_gets_disposition_from_path() {
+        let metadata = ObjectMetadata::new("maven/com/example/lib-1.0.jar", b"");
+        assert_eq!(
+            metadata.content_disposition.as_deref(),
+            Some("attachment; filename=\"lib-1.0.jar\"")
+        );
+
+        let metadata = ObjectMetadata::new(&root_manifest_path(), b"{}");
+        assert!(metadata.content_disposition.is_none());
+        assert_eq!(metadata.class, ObjectClass::RootManifest);
+    }
+
+    #[test]
+    fn test_attachment_sanitizes_filename() {
+        assert_eq!(attachment("a \"b\".jar"), "attachment; filename=\"a__b_.jar\"");
+    }
+}
@@ -0,0 +1,312 @@
+//! Rewriting CAS references when `CAS_VERSION` is bumped
+//!
+//! Objects are addressed by content, so almost everything under
+//! `v{old}/objects/` can be reused as-is under `v{new}/objects/`. The
+//! exceptions are JSON documents (version JSONs) that embed URLs into the old
+//! tree: rewriting those URLs changes their content and therefore their hash,
+//! which in turn has to be reflected in the loader manifests pointing at them.
+//!
+//! The functions here are pure; the bucket side lives in
+//! `commands::migrate`.
+
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+
+/// Bucket prefix of the object tree of a CAS version
+pub fn objects_prefix(version: u32) -> String {
+    format!("v{}/objects/", version)
+}
+
+/// Bucket path of an object in the tree of a CAS version
+pub fn object_path(version: u32, hash: &str) -> String {
+    format!("{}{}/{}", objects_prefix(version), &hash[..2], &hash[2..])
+}
+
+/// Split a string at the first reference to the object tree of `from`
+///
+/// Returns the text before `v{from}/objects/` and the text after it.
+fn split_reference(s: &str, from: u32) -> Option<(&str, &str)> {
+    let prefix = objects_prefix(from);
+    let index = s.find(&prefix)?;
+    Some((&s[..index], &s[index + prefix.len()..]))
+}
+
+/// Hash addressed by the part of a URL after `objects/` (`ab/cdef...`)
+fn hash_from_tail(tail: &str) -> Option<String> {
+    let (prefix, suffix) = tail.split_once('/')?;
+    let is_hex = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_hexdigit());
+
+    if prefix.len() == 2 && is_hex(prefix) && is_hex(suffix) {
+        Some(format!("{}{}", prefix, suffix))
+    } else {
+        None
+    }
+}
+
+/// Rewrite a single string from the `from` tree to the `to` tree
+///
+/// * URLs pointing into `v{from}/objects/` are moved to `v{to}/objects/`,
+///   swapping the hash if it is in `hashes`
+/// * a string that is exactly a hash in `hashes` (e.g. a `sha1` field holding
+///   the CAS hash) is replaced by the new hash
+pub fn rewrite_str(s: &str, from: u32, to: u32, hashes: &HashMap<String, String>) -> String {
+    if let Some(new_hash) = hashes.get(s) {
+        return new_hash.clone();
+    }
+
+    let Some((head, tail)) = split_reference(s, from) else {
+        return s.to_string();
+    };
+
+    let tail = match hash_from_tail(tail).and_then(|hash| hashes.get(&hash)) {
+        Some(new_hash) => format!("{}/{}", &new_hash[..2], &new_hash[2..]),
+        None => tail.to_string(),
+    };
+
+    format!("{}{}{}", head, objects_prefix(to), tail)
+}
+
+/// Rewrite every string value of a JSON document (object keys are kept)
+///
+/// Returns the rewritten value and whether anything changed.
+pub fn rewrite_value(
+    value: Value,
+    from: u32,
+    to: u32,
+    hashes: &HashMap<String, String>,
+) -> (Value, bool) {
+    match value {
+        Value::String(s) => {
+            let rewritten = rewrite_str(&s, from, to, hashes);
+            let changed = rewritten != s;
+            (Value::String(rewritten), changed)
+        }
+        Value::Array(values) => {
+            let mut changed = false;
+            let values = values
+                .into_iter()
+                .map(|value| {
+                    let (value, value_changed) = rewrite_value(value, from, to, hashes);
+                    changed |= value_changed;
+                    value
+                })
+                .collect();
+            (Value::Array(values), changed)
+        }
+        Value::Object(map) => {
+            let mut changed = false;
+            let map = map
+                .into_iter()
+                .map(|(key, value)| {
+                    let (value, value_changed) = rewrite_value(value, from, to, hashes);
+                    changed |= value_changed;
+                    (key, value)
+                })
+                .collect();
+            (Value::Object(map), changed)
+        }
+        other => (other, false),
+    }
+}
+
+/// Hashes of all objects in the `from` tree referenced by full URL
+pub fn referenced_hashes(value: &Value, from: u32) -> BTreeSet<String> {
+    let mut hashes = BTreeSet::new();
+    collect_hashes(value, from, &mut hashes);
+    hashes
+}
+
+/// Hashes in the `version` tree referenced by a loader manifest `versions` array
+///
+/// Covers full CAS URLs as well as bare `hash` fields of simple entries.
+pub fn manifest_hashes(versions: &Value, version: u32) -> BTreeSet<String> {
+    let mut hashes = referenced_hashes(versions, version);
+
+    for entry in versions.as_array().into_iter().flatten() {
+        if let Some(hash) = entry.get("hash").and_then(Value::as_str) {
+            hashes.insert(hash.to_string());
+        }
+    }
+
+    hashes
+}
+
+/// Order in which documents have to be rewritten, referenced documents first
+///
+/// A document embeds the hashes of the documents it references, so their new
+/// hashes have to be known before it is rewritten itself. `documents` maps the
+/// hashes of the JSON documents in the `from` tree to their content.
+pub fn rewrite_order(documents: &HashMap<String, Value>, from: u32) -> Vec<String> {
+    fn visit(
+        hash: &str,
+        documents: &HashMap<String, Value>,
+        from: u32,
+        visited: &mut BTreeSet<String>,
+        order: &mut Vec<String>,
+    ) {
+        if !visited.insert(hash.to_string()) {
+            return;
+        }
+        let Some(document) = documents.get(hash) else {
+            return;
+        };
+        for reference in referenced_hashes(document, from) {
+            visit(&reference, documents, from, visited, order);
+        }
+        order.push(hash.to_string());
+    }
+
+    let mut hashes: Vec<&String> = documents.keys().collect();
+    hashes.sort();
+
+    let mut visited = BTreeSet::new();
+    let mut order = Vec::new();
+    for hash in hashes {
+        visit(hash, documents, from, &mut visited, &mut order);
+    }
+    order
+}
+
+/// Correct the `size` of entries that point at rewritten documents
+///
+/// `sizes` maps the new hashes of rewritten documents to their length. Every
+/// object with a `size` field whose `hash` or `url` (into the `to` tree)
+/// addresses one of them gets the new length.
+pub fn update_sizes(value: &mut Value, to: u32, sizes: &HashMap<String, u64>) {
+    match value {
+        Value::Array(values) => values.iter_mut().for_each(|value| update_sizes(value, to, sizes)),
+        Value::Object(map) => {
+            let hash = map
+                .get("hash")
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .or_else(|| {
+                    map.get("url")
+                        .and_then(Value::as_str)
+                        .and_then(|url| split_reference(url, to))
+                        .and_then(|(_, tail)| hash_from_tail(tail))
+                });
+            let size = hash.and_then(|hash| sizes.get(&hash));
+
+            if let (Some(size), Some(field)) = (size, map.get_mut("size")) {
+                *field = Value::from(*size);
+            }
+            map.values_mut().for_each(|value| update_sizes(value, to, sizes));
+        }
+        _ => {}
+    }
+}
+
+fn collect_hashes(value: &Value, from: u32, hashes: &mut BTreeSet<String>) {
+    match value {
+        Value::String(s) => {
+            if let Some(hash) = split_reference(s, from).and_then(|(_, tail)| hash_from_tail(tail)) {
+                hashes.insert(hash);
+            }
+        }
+        Value::Array(values) => values.iter().for_each(|value| collect_hashes(value, from, hashes)),
+        Value::Object(map) => map.values().for_each(|value| collect_hashes(value, from, hashes)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::cas::CAS_VERSION;
+
+    const OLD: &str = "aabbccddeeff00112233445566778899aabbccddeeff00112233445566778899";
+    const NEW: &str = "0011223344556677889900112233445566778899001122334455667788990011";
+
+    #[test]
+    fn test_rewrite_str() {
+        let hashes = HashMap::from([(OLD.to_string(), NEW.to_string())]);
+        let url = format!("https://cdn.example.com/v4/objects/{}/{}", &OLD[..2], &OLD[2..]);
+
+        assert_eq!(
+            rewrite_str(&url, 4, 5, &hashes),
+            format!("https://cdn.example.com/v5/objects/{}/{}", &NEW[..2], &NEW[2..])
+        );
+        // Unchanged objects keep their hash but move to the new tree
+        assert_eq!(
+            rewrite_str(&url, 4, 5, &HashMap::new()),
+            format!("https://cdn.example.com/v5/objects/{}/{}", &OLD[..2], &OLD[2..])
+        );
+        // Prefix-only library URLs
+        assert_eq!(
+            rewrite_str("https://cdn.example.com/v4/objects/", 4, 5, &hashes),
+            "https://cdn.example.com/v5/objects/"
+        );
+        assert_eq!(rewrite_str(OLD, 4, 5, &hashes), NEW);
+        assert_eq!(
+            rewrite_str("https://libraries.minecraft.net/", 4, 5, &hashes),
+            "https://libraries.minecraft.net/"
+        );
+    }
+
+    #[test]
+    fn test_rewrite_value_and_references() {
+        let document = serde_json::json!({
+            "id": "1.20.1",
+            "url": format!("https://cdn.example.com/v4/objects/{}/{}", &OLD[..2], &OLD[2..]),
+            "sha1": OLD,
+            "libraries": [{ "url": "https://maven.fabricmc.net/" }],
+        });
+
+        assert_eq!(referenced_hashes(&document, 4), BTreeSet::from([OLD.to_string()]));
+        assert!(referenced_hashes(&document, 3).is_empty());
+
+        let hashes = HashMap::from([(OLD.to_string(), NEW.to_string())]);
+        let (rewritten, changed) = rewrite_value(document.clone(), 4, 5, &hashes);
+
+        assert!(changed);
+        assert_eq!(rewritten["sha1"], NEW);
+        assert_eq!(rewritten["libraries"], document["libraries"]);
+        assert_eq!(referenced_hashes(&rewritten, 5), BTreeSet::from([NEW.to_string()]));
+
+        let (_, changed) = rewrite_value(rewritten, 4, 5, &hashes);
+        assert!(!changed);
+    }
+
+    #[test]
+    fn test_rewrite_order() {
+        let url = |hash: &str| format!("https://cdn.example.com/v4/objects/{}/{}", &hash[..2], &hash[2..]);
+        let documents = HashMap::from([
+            ("aa01".to_string(), serde_json::json!({ "profile": url("bb02") })),
+            ("bb02".to_string(), serde_json::json!({ "data": url("cc03"), "jar": url("ff00") })),
+            ("cc03".to_string(), serde_json::json!({ "id": "leaf" })),
+        ]);
+
+        assert_eq!(rewrite_order(&documents, 4), vec!["cc03", "bb02", "aa01"]);
+    }
+
+    #[test]
+    fn test_update_sizes() {
+        let mut versions = serde_json::json!([
+            { "id": "1.20.4", "hash": NEW, "size": 1 },
+            { "id": "1.20.1", "hash": OLD, "size": 2 },
+            { "id": "47.1.0", "url": format!("https://cdn.example.com/v5/objects/{}/{}", &NEW[..2], &NEW[2..]), "size": 3 },
+        ]);
+
+        update_sizes(&mut versions, 5, &HashMap::from([(NEW.to_string(), 42)]));
+
+        assert_eq!(versions[0]["size"], 42);
+        assert_eq!(versions[1]["size"], 2);
+        assert_eq!(versions[2]["size"], 42);
+    }
+
+    #[test]
+    fn test_manifest_hashes() {
+        let versions = serde_json::json!([
+            { "id": "1.20.4", "hash": "aabb", "size": 1 },
+            { "id": "1.20.1", "loaders": [
+                { "id": "47.1.0", "url": format!("https://cdn.example.com/v{}/objects/cc/dd", CAS_VERSION) },
+            ]},
+        ]);
+
+        assert_eq!(
+            manifest_hashes(&versions, CAS_VERSION),
+            BTreeSet::from(["aabb".to_string(), "ccdd".to_string()])
+        );
+    }
+}
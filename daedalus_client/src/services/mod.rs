@@ -1,5 +1,19 @@
 pub mod betterstack;
+pub mod bucket;
 pub mod cas;
+pub mod changelog;
 pub mod cloudflare;
 pub mod download;
+pub mod dry_run;
+pub mod history;
+pub mod index;
+pub mod lock;
+pub mod maven;
+pub mod metadata;
+pub mod migration;
+pub mod previous_state;
+pub mod report;
+pub mod retention;
+pub mod quarantine;
 pub mod upload;
+pub mod webhooks;
@@ -0,0 +1,213 @@
+//! Previously published metadata, resolved through the live root manifest
+//!
+//! Processors compare what they generate against what is currently live to
+//! skip unchanged versions. The live layout is:
+//!
+//! ```text
+//! v{CAS_VERSION}/manifest.json                      (RootManifest)
+//!   └─> v{CAS_VERSION}/manifests/<loader>/<ts>.json (LoaderManifest)
+//! ```
+//!
+//! [`PreviousState::load`] resolves the root once per cycle and fetches every
+//! loader manifest it references, so each processor can ask for its typed
+//! previous version list without knowing about the layout.
+
+use crate::infrastructure::error::Error;
+use crate::services::bucket::{get_object, get_object_with_etag, WriteCondition};
+use crate::services::cas::{root_manifest_path, LoaderManifest, RootManifest};
+use futures::future::join_all;
+use s3::Bucket;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use tracing::{info, instrument, warn};
+
+/// Snapshot of the live root manifest and the loader manifests it points to
+#[derive(Debug, Clone, Default)]
+pub struct PreviousState {
+    /// The root manifest that was live when the snapshot was taken
+    root: Option<RootManifest>,
+    /// ETag of `root`, the precondition for committing on top of it
+    root_etag: Option<String>,
+    /// Loader name → loader manifest referenced by `root`
+    loaders: HashMap<String, LoaderManifest>,
+}
+
+impl PreviousState {
+    /// An empty state, which makes every processor treat all versions as new
+    pub fn empty() -> Self {
+        Self::default()
+    }
+
+    /// Build a state from already fetched manifests
+    pub fn from_parts(
+        root: Option<RootManifest>,
+        loaders: HashMap<String, LoaderManifest>,
+    ) -> Self {
+        Self {
+            root,
+            root_etag: None,
+            loaders,
+        }
+    }
+
+    /// Load the live root manifest and every loader manifest it references
+    ///
+    /// A missing root manifest is not an error (fresh bucket). A loader
+    /// manifest that cannot be fetched or parsed is logged and left out, so
+    /// that loader is fully reprocessed instead of failing the whole cycle.
+    #[instrument(skip(s3_client))]
+    pub async fn load(s3_client: &Bucket) -> Result<Self, Error> {
+        let root_path = root_manifest_path();
+
+        let Some((root_bytes, root_etag)) = get_object_with_etag(s3_client, &root_path).await? else {
+            info!(path = %root_path, "No root manifest found, starting from empty state");
+            return Ok(Self::empty());
+        };
+
+        let root: RootManifest = serde_json::from_slice(&root_bytes)?;
+
+        let fetches = root.loaders.iter().map(|(loader, reference)| async move {
+            let manifest = match get_object(s3_client, &reference.url).await {
+                Ok(Some(bytes)) => serde_json::from_slice::<LoaderManifest>(&bytes)
+                    .map_err(Error::from),
+                Ok(None) => Err(crate::infrastructure::error::invalid_input(format!(
+                    "Loader manifest {} referenced by root manifest does not exist",
+                    reference.url
+                ))),
+                Err(err) => Err(err),
+            };
+
+            (loader.clone(), manifest)
+        });
+
+        let mut loaders = HashMap::new();
+        for (loader, manifest) in join_all(fetches).await {
+            match manifest {
+                Ok(manifest) => {
+                    loaders.insert(loader, manifest);
+                }
+                Err(err) => {
+                    warn!(
+                        loader = %loader,
+                        error = %err,
+                        "Failed to load previous loader manifest, loader will be fully reprocessed"
+                    );
+                }
+            }
+        }
+
+        info!(
+            root_created_at = %root.created_at,
+            loader_count = loaders.len(),
+            "Loaded previous state from root manifest"
+        );
+
+        Ok(Self {
+            root_etag,
+            ..Self::from_parts(Some(root), loaders)
+        })
+    }
+
+    /// Precondition for replacing the root manifest this state was loaded from
+    ///
+    /// A commit under it fails if another commit happened in the meantime.
+    pub fn root_condition(&self) -> WriteCondition {
+        match (&self.root, &self.root_etag) {
+            (None, _) => WriteCondition::IfAbsent,
+            (Some(_), Some(etag)) => WriteCondition::IfMatch(etag.clone()),
+            (Some(_), None) => WriteCondition::Always,
+        }
+    }
+
+    /// The root manifest that was live, if any
+    pub fn root(&self) -> Option<&RootManifest> {
+        self.root.as_ref()
+    }
+
+    /// The previously live manifest of a loader, if any
+    pub fn loader_manifest(&self, loader: &str) -> Option<&LoaderManifest> {
+        self.loaders.get(loader)
+    }
+
+    /// Decode the previous `versions` array of a loader manifest
+    ///
+    /// Returns an empty list if the loader has no previous manifest or its
+    /// schema does not match `T`.
+    pub fn versions<T: DeserializeOwned>(&self, loader: &str) -> Vec<T> {
+        let Some(manifest) = self.loaders.get(loader) else {
+            return Vec::new();
+        };
+
+        match serde_json::from_value(manifest.versions.clone()) {
+            Ok(versions) => versions,
+            Err(err) => {
+                warn!(
+                    loader = %loader,
+                    error = %err,
+                    "Previous loader manifest has an unexpected schema, ignoring it"
+                );
+                Vec::new()
+            }
+        }
+    }
+
+    /// Previous Minecraft version entries
+    pub fn minecraft_versions(&self) -> Vec<daedalus::minecraft::Version> {
+        self.versions("minecraft")
+    }
+
+    /// Previous game versions (with nested loader versions) for a mod loader
+    pub fn game_versions(&self, loader: &str) -> Vec<daedalus::modded::Version> {
+        self.versions(loader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state_with(loader: &str, versions: serde_json::Value) -> PreviousState {
+        let mut loaders = HashMap::new();
+        loaders.insert(
+            loader.to_string(),
+            LoaderManifest::new(loader.to_string(), versions),
+        );
+        PreviousState::from_parts(Some(RootManifest::empty()), loaders)
+    }
+
+    #[test]
+    fn test_empty_state() {
+        let state = PreviousState::empty();
+
+        assert!(state.root().is_none());
+        assert!(state.minecraft_versions().is_empty());
+        assert!(state.game_versions("forge").is_empty());
+        // Committing onto an empty bucket must not replace a root created meanwhile
+        assert_eq!(state.root_condition(), WriteCondition::IfAbsent);
+    }
+
+    #[test]
+    fn test_game_versions() {
+        let state = state_with(
+            "forge",
+            serde_json::json!([{
+                "id": "1.20.1",
+                "stable": true,
+                "loaders": [{ "id": "1.20.1-47.1.0", "url": "https://example.com/v5/objects/ab/cd", "stable": false }]
+            }]),
+        );
+
+        let versions = state.game_versions("forge");
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions[0].loaders[0].id, "1.20.1-47.1.0");
+        assert!(state.game_versions("neoforge").is_empty());
+    }
+
+    #[test]
+    fn test_schema_mismatch_is_ignored() {
+        let state = state_with("minecraft", serde_json::json!({ "unexpected": true }));
+
+        assert!(state.root().is_some());
+        assert!(state.minecraft_versions().is_empty());
+    }
+}
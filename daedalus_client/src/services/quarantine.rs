@@ -0,0 +1,457 @@
+//! Quarantine of failing loader versions
+//!
+//! A loader version that fails to process is quarantined with its error, the
+//! number of failed attempts and the time of its next retry. Until then it is
+//! skipped; the delay starts at `quarantine.base_delay_secs` and doubles with
+//! every failure up to `quarantine.max_delay_secs`. A successful retry lifts
+//! the quarantine. Only upstream and processing errors quarantine a version:
+//! failures of the bucket itself (and shutdowns) leave it alone.
+//!
+//! Entries are in one of two states:
+//!
+//! - `retrying`: failed, skipped until `next_retry_at`
+//! - `skipped`: known broken, skipped until an operator clears it
+//!
+//! The store is kept at `v{CAS_VERSION}/quarantine.json` and is created with
+//! the versions known to be broken upstream as `skipped` entries:
+//!
+//! ```text
+//! {"schema_version": 1, "loaders": {"forge": {"1.7.10-10.13.4.1614": {
+//!     "state": "retrying", "error": "...", "attempts": 2,
+//!     "first_failed_at": "...", "last_failed_at": "...", "next_retry_at": "..."}}}}
+//! ```
+//!
+//! Every cycle re-reads it, and writes it back after changing it, as do
+//! changes through the admin API. Writes are conditional on the ETag it was
+//! read at; on a conflict the entries changed by this process are applied on
+//! top of the stored ones. The `quarantine` command writes the same way, so it
+//! can be used while the daemon runs.
+
+use crate::infrastructure::config::QuarantineConfig;
+use crate::infrastructure::error::{Error, ErrorKind};
+use crate::services::bucket::{get_object_with_etag, put_object_if, WriteCondition};
+use crate::services::cas::CAS_VERSION;
+use chrono::{DateTime, Utc};
+use s3::Bucket;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{LazyLock, Mutex};
+use tracing::info;
+
+/// Writes attempted before a save gives up on conflicts
+const MAX_WRITE_ATTEMPTS: u32 = 3;
+
+/// Versions skipped by a new store, with the reason
+const BROKEN_VERSIONS: &[(&str, &str, &str)] = &[
+    ("forge", "1.12.2-14.23.5.2851", "`data` of the install profile is `[]` instead of a map"),
+    ("forge", "1.6.1-8.9.0.749", "Malformed archive"),
+    ("forge", "1.6.1-8.9.0.751", "Malformed archive"),
+    ("forge", "1.6.4-9.11.1.960", "Malformed archive"),
+    ("forge", "1.6.4-9.11.1.961", "Malformed archive"),
+    ("forge", "1.6.4-9.11.1.963", "Malformed archive"),
+    ("forge", "1.6.4-9.11.1.964", "Malformed archive"),
+    ("neoforge", "1.20.1-47.1.7", "Unreachable upstream (404)"),
+    ("neoforge", "47.1.82", "Unreachable upstream (404)"),
+];
+
+static STORE: LazyLock<Mutex<Store>> = LazyLock::new(Mutex::default);
+
+#[derive(Debug, Default)]
+struct Store {
+    quarantine: Quarantine,
+    /// Read from the bucket, until then nothing is written back
+    loaded: bool,
+    /// ETag of the stored quarantine, `None` while there is none
+    etag: Option<String>,
+    /// Loader and version of the entries changed since the last read or write
+    changed: BTreeSet<(String, String)>,
+}
+
+/// Bucket path of the quarantine store
+pub fn quarantine_path() -> String {
+    format!("v{}/quarantine.json", CAS_VERSION)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Quarantine {
+    pub schema_version: u32,
+    /// Loader → version → entry
+    pub loaders: BTreeMap<String, BTreeMap<String, Entry>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum State {
+    Retrying,
+    Skipped,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub state: State,
+    /// Error of the last failed attempt, or why the version is skipped
+    pub error: Option<String>,
+    /// Failed attempts since the version was quarantined
+    pub attempts: u32,
+    pub first_failed_at: Option<DateTime<Utc>>,
+    pub last_failed_at: Option<DateTime<Utc>>,
+    /// When a `retrying` version is processed again
+    pub next_retry_at: Option<DateTime<Utc>>,
+}
+
+impl Entry {
+    fn with_state(state: State) -> Self {
+        Self {
+            state,
+            error: None,
+            attempts: 0,
+            first_failed_at: None,
+            last_failed_at: None,
+            next_retry_at: None,
+        }
+    }
+}
+
+/// Loader names are matched case-insensitively
+fn loader_key(loader: &str) -> String {
+    loader.to_lowercase()
+}
+
+impl Quarantine {
+    /// A new store, skipping the versions known to be broken
+    pub fn seeded() -> Self {
+        let mut quarantine = Self::default();
+        for (loader, version, reason) in BROKEN_VERSIONS {
+            quarantine.skip(loader, version);
+            quarantine.entry_mut(loader, version).error = Some(reason.to_string());
+        }
+        quarantine
+    }
+
+    fn entry(&self, loader: &str, version: &str) -> Option<&Entry> {
+        self.loaders.get(&loader_key(loader)).and_then(|versions| versions.get(version))
+    }
+
+    fn entry_mut(&mut self, loader: &str, version: &str) -> &mut Entry {
+        self.loaders
+            .entry(loader_key(loader))
+            .or_default()
+            .entry(version.to_string())
+            .or_insert_with(|| Entry::with_state(State::Retrying))
+    }
+
+    fn skips(&self, loader: &str, version: &str, now: DateTime<Utc>) -> bool {
+        self.entry(loader, version).is_some_and(|entry| match entry.state {
+            State::Retrying => entry.next_retry_at.is_some_and(|at| now < at),
+            State::Skipped => true,
+        })
+    }
+
+    fn record_failure(&mut self, loader: &str, version: &str, error: String, config: &QuarantineConfig, now: DateTime<Utc>) {
+        let entry = self.entry_mut(loader, version);
+        // Skipped versions stay skipped
+        if entry.state != State::Retrying {
+            return;
+        }
+
+        entry.attempts += 1;
+        entry.error = Some(error);
+        entry.first_failed_at.get_or_insert(now);
+        entry.last_failed_at = Some(now);
+        entry.next_retry_at = Some(now + retry_delay(config, entry.attempts));
+    }
+
+    /// Lift the quarantine of a version that was processed, returns whether
+    /// anything changed
+    fn record_success(&mut self, loader: &str, version: &str) -> bool {
+        match self.entry(loader, version).map(|entry| entry.state) {
+            Some(State::Retrying) => self.clear(loader, version),
+            _ => false,
+        }
+    }
+
+    /// Skip `version` until it is cleared
+    pub fn skip(&mut self, loader: &str, version: &str) {
+        let entry = self.entry_mut(loader, version);
+        entry.state = State::Skipped;
+        entry.next_retry_at = None;
+    }
+
+    /// Process `version` again, returns whether it was quarantined
+    pub fn clear(&mut self, loader: &str, version: &str) -> bool {
+        let loader = loader_key(loader);
+        let Some(versions) = self.loaders.get_mut(&loader) else {
+            return false;
+        };
+        let removed = versions.remove(version).is_some();
+        if versions.is_empty() {
+            self.loaders.remove(&loader);
+        }
+        removed
+    }
+
+    /// Take the entries `changed` from `local`, removing those it no longer has
+    fn apply(&mut self, local: &Quarantine, changed: &BTreeSet<(String, String)>) {
+        for (loader, version) in changed {
+            match local.entry(loader, version) {
+                Some(entry) => *self.entry_mut(loader, version) = entry.clone(),
+                None => {
+                    self.clear(loader, version);
+                }
+            }
+        }
+    }
+}
+
+/// Delay before the retry following the `attempts`th failure
+fn retry_delay(config: &QuarantineConfig, attempts: u32) -> chrono::Duration {
+    let factor = 1u64.checked_shl(attempts.saturating_sub(1)).unwrap_or(u64::MAX);
+    let secs = config.base_delay_secs.saturating_mul(factor).min(config.max_delay_secs);
+    chrono::Duration::seconds(i64::try_from(secs).unwrap_or(i64::MAX))
+}
+
+/// Whether a failure says something about the version rather than about the
+/// bucket or the process
+fn quarantines(error: &Error) -> bool {
+    !error.is_cancelled() && !error.is_storage_error()
+}
+
+fn update<T>(loader: &str, version: &str, f: impl FnOnce(&mut Quarantine) -> T) -> T {
+    let mut store = STORE.lock().expect("quarantine mutex poisoned");
+    store.changed.insert((loader_key(loader), version.to_string()));
+    f(&mut store.quarantine)
+}
+
+/// Whether `version` of `loader` is quarantined or skipped
+pub fn skips(loader: &str, version: &str) -> bool {
+    STORE
+        .lock()
+        .expect("quarantine mutex poisoned")
+        .quarantine
+        .skips(loader, version, Utc::now())
+}
+
+/// Quarantine `version` of `loader` after it failed with `error`
+pub fn record_failure(config: &QuarantineConfig, loader: &str, version: &str, error: &Error) {
+    if !config.enabled || !quarantines(error) {
+        return;
+    }
+
+    update(loader, version, |quarantine| {
+        quarantine.record_failure(loader, version, error.to_string(), config, Utc::now())
+    });
+}
+
+/// Lift the quarantine of `version` of `loader` after it was processed
+pub fn record_success(loader: &str, version: &str) {
+    let mut store = STORE.lock().expect("quarantine mutex poisoned");
+    if store.quarantine.record_success(loader, version) {
+        store.changed.insert((loader_key(loader), version.to_string()));
+    }
+}
+
+/// Skip `version` of `loader` until it is cleared
+pub fn skip(loader: &str, version: &str) {
+    update(loader, version, |quarantine| quarantine.skip(loader, version));
+}
+
+/// Process `version` of `loader` again
+pub fn clear(loader: &str, version: &str) {
+    update(loader, version, |quarantine| quarantine.clear(loader, version));
+}
+
+/// Entries of every loader
+pub fn entries() -> Quarantine {
+    STORE.lock().expect("quarantine mutex poisoned").quarantine.clone()
+}
+
+/// Read the store and the ETag it was read at
+///
+/// A store that does not exist yet is returned [seeded](Quarantine::seeded),
+/// without an ETag.
+pub async fn fetch(s3_client: &Bucket) -> Result<(Quarantine, Option<String>), Error> {
+    match get_object_with_etag(s3_client, &quarantine_path()).await? {
+        Some((bytes, etag)) => Ok((serde_json::from_slice(&bytes)?, etag)),
+        None => Ok((Quarantine::seeded(), None)),
+    }
+}
+
+/// Write `quarantine` to the bucket if it is still at `etag`, or does not
+/// exist without one
+///
+/// # Returns
+///
+/// The ETag of the written store
+pub async fn publish(s3_client: &Bucket, quarantine: &Quarantine, etag: Option<&str>) -> Result<String, Error> {
+    let quarantine = Quarantine {
+        schema_version: 1,
+        ..quarantine.clone()
+    };
+    let condition = match etag {
+        Some(etag) => WriteCondition::IfMatch(etag.to_string()),
+        None => WriteCondition::IfAbsent,
+    };
+    let bytes = crate::common::to_canonical_vec(&quarantine)?;
+    put_object_if(s3_client, &quarantine_path(), &bytes, "application/json", &condition).await
+}
+
+/// Pick up changes made to the store by others
+///
+/// Entries this process changed since its last write are kept. Until the
+/// store has been read once, failures are recorded in memory only.
+pub async fn refresh(s3_client: &Bucket) -> Result<(), Error> {
+    let (mut stored, etag) = fetch(s3_client).await?;
+
+    let mut store = STORE.lock().expect("quarantine mutex poisoned");
+    if store.loaded && store.etag.is_some() && store.etag == etag {
+        return Ok(());
+    }
+
+    stored.apply(&store.quarantine, &store.changed);
+    let count: usize = stored.loaders.values().map(BTreeMap::len).sum();
+    store.quarantine = stored;
+    store.etag = etag;
+    if !store.loaded {
+        info!(entries = count, "Quarantine loaded");
+    }
+    store.loaded = true;
+    Ok(())
+}
+
+/// Write the store back if it changed since it was read or last written
+///
+/// A store that was never read is read first, so the stored entries are not
+/// replaced by the local ones. Fails with `ErrorKind::Conflict` when the
+/// store kept changing while writing.
+pub async fn save(s3_client: &Bucket) -> Result<(), Error> {
+    let loaded = STORE.lock().expect("quarantine mutex poisoned").loaded;
+    if !loaded {
+        refresh(s3_client).await?;
+    }
+
+    for _ in 0..MAX_WRITE_ATTEMPTS {
+        let (quarantine, etag) = {
+            let store = STORE.lock().expect("quarantine mutex poisoned");
+            if store.changed.is_empty() && store.etag.is_some() {
+                return Ok(());
+            }
+            (store.quarantine.clone(), store.etag.clone())
+        };
+
+        match publish(s3_client, &quarantine, etag.as_deref()).await {
+            Ok(etag) => {
+                let mut store = STORE.lock().expect("quarantine mutex poisoned");
+                // Entries changed again while writing are still to be saved
+                let Store { quarantine: current, changed, .. } = &mut *store;
+                changed.retain(|(loader, version)| current.entry(loader, version) != quarantine.entry(loader, version));
+                store.etag = Some(etag);
+                return Ok(());
+            }
+            Err(ErrorKind::Conflict { .. }) => {
+                info!("Quarantine changed since it was read, merging");
+                refresh(s3_client).await?;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Err(ErrorKind::Conflict { path: quarantine_path() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_backoff_and_release() {
+        let config = QuarantineConfig {
+            enabled: true,
+            base_delay_secs: 3600,
+            max_delay_secs: 4 * 3600,
+        };
+        let now = Utc.with_ymd_and_hms(2024, 1, 15, 10, 0, 0).unwrap();
+        let hours = chrono::Duration::hours;
+        let mut quarantine = Quarantine::default();
+
+        quarantine.record_failure("forge", "1.0", "broken".to_string(), &config, now);
+        assert!(quarantine.skips("forge", "1.0", now + hours(0)));
+        assert!(!quarantine.skips("forge", "1.0", now + hours(1)));
+        assert!(!quarantine.skips("neoforge", "1.0", now));
+
+        // 1h, 2h, 4h, then capped at 4h
+        for _ in 0..3 {
+            quarantine.record_failure("forge", "1.0", "still broken".to_string(), &config, now);
+        }
+        let entry = &quarantine.loaders["forge"]["1.0"];
+        assert_eq!(entry.attempts, 4);
+        assert_eq!(entry.next_retry_at, Some(now + hours(4)));
+        assert_eq!(entry.error.as_deref(), Some("still broken"));
+
+        assert!(quarantine.record_success("forge", "1.0"));
+        assert!(quarantine.loaders.is_empty());
+    }
+
+    #[test]
+    fn test_skip_and_clear() {
+        let config = QuarantineConfig::default();
+        let now = Utc.with_ymd_and_hms(2024, 1, 15, 10, 0, 0).unwrap();
+        let mut quarantine = Quarantine::default();
+
+        quarantine.skip("forge", "3.0");
+        assert!(quarantine.skips("forge", "3.0", now + chrono::Duration::days(1000)));
+        // Failures and successes leave a skipped version alone
+        quarantine.record_failure("forge", "3.0", "broken".to_string(), &config, now);
+        assert_eq!(quarantine.loaders["forge"]["3.0"].attempts, 0);
+        assert!(!quarantine.record_success("forge", "3.0"));
+
+        assert!(quarantine.clear("forge", "3.0"));
+        assert!(!quarantine.skips("forge", "3.0", now));
+        assert!(!quarantine.clear("forge", "3.0"));
+        assert!(quarantine.loaders.is_empty());
+
+        let seeded = Quarantine::seeded();
+        assert!(seeded.skips("forge", "1.12.2-14.23.5.2851", now));
+        assert!(seeded.skips("neoforge", "47.1.82", now));
+    }
+
+    #[test]
+    fn test_loader_names_ignore_case() {
+        let now = Utc.with_ymd_and_hms(2024, 1, 15, 10, 0, 0).unwrap();
+        let mut quarantine = Quarantine::default();
+
+        quarantine.skip("Forge", "1.0");
+        assert!(quarantine.loaders.contains_key("forge"));
+        assert!(quarantine.skips("forge", "1.0", now));
+        assert!(quarantine.skips("FORGE", "1.0", now));
+        assert!(quarantine.clear("forge", "1.0"));
+
+        quarantine.skip("NeoForge", "2.0");
+        assert!(quarantine.clear("neoForge", "2.0"));
+        assert!(quarantine.loaders.is_empty());
+    }
+
+    #[test]
+    fn test_apply_local_changes() {
+        let config = QuarantineConfig::default();
+        let now = Utc.with_ymd_and_hms(2024, 1, 15, 10, 0, 0).unwrap();
+
+        // Stored by someone else: 1.0 skipped, 2.0 and 3.0 retrying
+        let mut stored = Quarantine::default();
+        stored.skip("forge", "1.0");
+        stored.record_failure("forge", "2.0", "broken".to_string(), &config, now);
+        stored.record_failure("forge", "3.0", "broken".to_string(), &config, now);
+
+        // This process recorded 4.0 and cleared 2.0 before reading the store
+        let mut local = Quarantine::default();
+        local.record_failure("forge", "4.0", "broken".to_string(), &config, now);
+        let changed = BTreeSet::from([
+            ("forge".to_string(), "2.0".to_string()),
+            ("forge".to_string(), "4.0".to_string()),
+        ]);
+
+        stored.apply(&local, &changed);
+        let versions: Vec<_> = stored.loaders["forge"].keys().map(String::as_str).collect();
+        assert_eq!(versions, ["1.0", "3.0", "4.0"]);
+        assert_eq!(stored.loaders["forge"]["1.0"].state, State::Skipped);
+    }
+}
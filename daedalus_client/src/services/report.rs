@@ -0,0 +1,317 @@
+//! Report of the processing cycles
+//!
+//! Every cycle fills a [`CycleReport`] as its loaders finish: how each loader
+//! run ended, how many versions it processed, left unchanged, uploaded or
+//! failed, the bytes transferred and the root manifest committed. Finished
+//! reports are uploaded to `v{N}/reports/<timestamp>.json`, the most recent
+//! one is also kept in memory for the admin API.
+//!
+//! Loaders count their versions with [`record_processed`] and
+//! [`record_failure`] as they go; the counts are moved into the report when
+//! the loader run is recorded. Bytes are counted per cycle through a task
+//! local set by [`count_bytes`], since the cycles of different loaders run
+//! concurrently.
+
+use crate::infrastructure::error::Error;
+use crate::infrastructure::metrics::{self, Outcome};
+use crate::services::cas::CAS_VERSION;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::Semaphore;
+use tracing::{info, warn};
+
+/// Failures kept per loader run, the rest are only counted
+const MAX_FAILURES: usize = 100;
+
+static LATEST: Mutex<Option<CycleReport>> = Mutex::new(None);
+
+/// Versions of the loader runs in progress, by loader
+static VERSIONS: Mutex<BTreeMap<String, VersionStats>> = Mutex::new(BTreeMap::new());
+
+tokio::task_local! {
+    /// Byte counters of the cycle the current task runs
+    static CYCLE_BYTES: Arc<ByteCounters>;
+}
+
+/// Bytes downloaded and uploaded by one cycle
+#[derive(Debug, Default)]
+pub struct ByteCounters {
+    downloaded: AtomicU64,
+    uploaded: AtomicU64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CycleReport {
+    /// Identifies the cycle, also attached to its logs as `cycle_id`
+    pub id: String,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub loaders: BTreeMap<String, LoaderReport>,
+    /// Bytes downloaded and uploaded by the cycle
+    pub downloaded_bytes: u64,
+    pub uploaded_bytes: u64,
+    /// Timestamp of the root manifest the cycle committed, if it did
+    pub committed: Option<String>,
+    /// Counted while the cycle runs, see [`count_bytes`]
+    #[serde(skip)]
+    bytes: Arc<ByteCounters>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LoaderReport {
+    pub outcome: Outcome,
+    pub duration_secs: f64,
+    pub error: Option<String>,
+    /// The run was rejected by the open circuit breaker
+    pub breaker_rejected: bool,
+    #[serde(flatten)]
+    pub versions: VersionStats,
+}
+
+/// Versions handled by one loader run
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct VersionStats {
+    /// Versions processed successfully, `unchanged` plus `uploaded`
+    pub processed: u64,
+    /// Versions whose content matched the published one
+    pub unchanged: u64,
+    /// New or changed versions that were uploaded
+    pub uploaded: u64,
+    pub failed: u64,
+    /// The first failures of the run
+    pub failures: Vec<VersionFailure>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VersionFailure {
+    pub version: String,
+    /// The error followed by its sources
+    pub errors: Vec<String>,
+}
+
+fn update(loader: &str, f: impl FnOnce(&mut VersionStats)) {
+    let mut versions = VERSIONS.lock().expect("report mutex poisoned");
+    f(versions.entry(loader.to_lowercase()).or_default());
+}
+
+/// Count a version `loader` processed, `uploaded` when it was new or changed
+pub fn record_processed(loader: &str, uploaded: bool) {
+    update(loader, |stats| {
+        stats.processed += 1;
+        if uploaded {
+            stats.uploaded += 1;
+        } else {
+            stats.unchanged += 1;
+        }
+    });
+}
+
+/// The message of `error` followed by those of its sources
+///
+/// Messages that embed their source (`context: source`) are cut down to their
+/// own part, so every message appears once.
+fn error_chain(error: &Error) -> Vec<String> {
+    let messages: Vec<String> =
+        std::iter::successors(Some(error as &(dyn std::error::Error + 'static)), |error| error.source())
+            .map(ToString::to_string)
+            .collect();
+
+    messages
+        .iter()
+        .enumerate()
+        .filter_map(|(i, message)| {
+            let own = match messages.get(i + 1) {
+                Some(source) => message.strip_suffix(source.as_str()).map_or(message.as_str(), |own| own.trim_end_matches([':', ' '])),
+                None => message,
+            };
+            // A wrapper that only forwards its source adds nothing
+            (!own.is_empty()).then(|| own.to_string())
+        })
+        .collect()
+}
+
+/// Record a version `loader` failed to process
+pub fn record_failure(loader: &str, version: &str, error: &Error) {
+    let errors = error_chain(error);
+    update(loader, |stats| {
+        stats.failed += 1;
+        if stats.failures.len() < MAX_FAILURES {
+            stats.failures.push(VersionFailure {
+                version: version.to_string(),
+                errors,
+            });
+        }
+    });
+}
+
+/// Count `bytes` downloaded, for the process and the current cycle
+pub fn record_downloaded(bytes: usize) {
+    metrics::DOWNLOADED_BYTES.inc_by(bytes as u64);
+    let _ = CYCLE_BYTES.try_with(|counters| counters.downloaded.fetch_add(bytes as u64, Ordering::Relaxed));
+}
+
+/// Count `bytes` uploaded, for the process and the current cycle
+pub fn record_uploaded(bytes: usize) {
+    metrics::UPLOADED_BYTES.inc_by(bytes as u64);
+    let _ = CYCLE_BYTES.try_with(|counters| counters.uploaded.fetch_add(bytes as u64, Ordering::Relaxed));
+}
+
+/// Run `cycle`, counting the bytes it transfers into `counters` (see
+/// [`CycleReport::byte_counters`])
+///
+/// Work spawned onto other tasks is not counted.
+pub async fn count_bytes<T>(counters: Arc<ByteCounters>, cycle: impl Future<Output = T>) -> T {
+    CYCLE_BYTES.scope(counters, cycle).await
+}
+
+/// Bucket path of the report of a cycle
+pub fn report_path(timestamp: &str) -> String {
+    format!("v{}/reports/{}.json", CAS_VERSION, timestamp)
+}
+
+impl CycleReport {
+    pub fn start() -> Self {
+        let started_at = Utc::now();
+        Self {
+            id: format!("{:x}", started_at.timestamp_micros()),
+            started_at,
+            finished_at: None,
+            loaders: BTreeMap::new(),
+            downloaded_bytes: 0,
+            uploaded_bytes: 0,
+            committed: None,
+            bytes: Arc::default(),
+        }
+    }
+
+    /// Counters to run the cycle with, see [`count_bytes`]
+    pub fn byte_counters(&self) -> Arc<ByteCounters> {
+        Arc::clone(&self.bytes)
+    }
+
+    /// Record a loader run that started at `started`, with the versions it
+    /// counted so far
+    pub fn record_loader(&mut self, loader: &str, started: Instant, outcome: Outcome, error: Option<String>) {
+        let versions = VERSIONS
+            .lock()
+            .expect("report mutex poisoned")
+            .remove(loader)
+            .unwrap_or_default();
+        self.loaders.insert(
+            loader.to_string(),
+            LoaderReport {
+                outcome,
+                duration_secs: started.elapsed().as_secs_f64(),
+                error,
+                breaker_rejected: outcome == Outcome::Skipped,
+                versions,
+            },
+        );
+    }
+
+    /// Close the report, make it the latest one and upload it
+    ///
+    /// A report that cannot be uploaded is only logged.
+    pub async fn finish(mut self, committed: Option<String>, semaphore: Arc<Semaphore>) {
+        let finished_at = Utc::now();
+        self.downloaded_bytes = self.bytes.downloaded.load(Ordering::Relaxed);
+        self.uploaded_bytes = self.bytes.uploaded.load(Ordering::Relaxed);
+        self.finished_at = Some(finished_at);
+        self.committed = committed;
+        *LATEST.lock().expect("report mutex poisoned") = Some(self.clone());
+
+        let path = report_path(&finished_at.format(crate::services::history::TIMESTAMP_FORMAT).to_string());
+        let result = match crate::common::to_canonical_vec(&self) {
+            Ok(bytes) => {
+                crate::upload_file_to_bucket(
+                    path.clone(),
+                    bytes,
+                    Some("application/json".to_string()),
+                    &tokio::sync::Mutex::new(Vec::new()),
+                    semaphore,
+                )
+                .await
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => info!(path = %path, "Cycle report uploaded"),
+            Err(e) => warn!(path = %path, error = %e, "Failed to upload cycle report (non-fatal)"),
+        }
+    }
+}
+
+/// Report of the most recent finished cycle
+pub fn latest() -> Option<CycleReport> {
+    LATEST.lock().expect("report mutex poisoned").clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_loader_report_takes_version_counts() {
+        // A loader name no other test records versions for
+        let loader = "ReportTestLoader";
+        record_processed(loader, true);
+        record_processed(loader, false);
+        record_processed(loader, false);
+        let error = Error::Generic {
+            context: "Failed to read installer".to_string(),
+            source: Box::new(std::io::Error::other("unexpected end of file")),
+        };
+        for _ in 0..=MAX_FAILURES {
+            record_failure(loader, "1.0.0", &error);
+        }
+
+        let mut report = CycleReport::start();
+        report.record_loader("reporttestloader", Instant::now(), Outcome::Success, None);
+        let versions = &report.loaders["reporttestloader"].versions;
+        assert_eq!((versions.processed, versions.unchanged, versions.uploaded), (3, 2, 1));
+        assert_eq!(versions.failed, MAX_FAILURES as u64 + 1);
+        assert_eq!(versions.failures.len(), MAX_FAILURES);
+        assert_eq!(
+            versions.failures[0].errors,
+            ["Failed to read installer", "unexpected end of file"]
+        );
+        assert!(!report.loaders["reporttestloader"].breaker_rejected);
+
+        // The counts belong to the run that recorded them
+        report.record_loader("reporttestloader", Instant::now(), Outcome::Skipped, None);
+        assert_eq!(report.loaders["reporttestloader"].versions.processed, 0);
+        assert!(report.loaders["reporttestloader"].breaker_rejected);
+    }
+
+    #[tokio::test]
+    async fn test_bytes_counted_per_cycle() {
+        let forge = CycleReport::start();
+        let fabric = CycleReport::start();
+
+        // Concurrent cycles only count their own transfers
+        tokio::join!(
+            count_bytes(forge.byte_counters(), async {
+                record_downloaded(100);
+                tokio::task::yield_now().await;
+                record_uploaded(10);
+            }),
+            count_bytes(fabric.byte_counters(), async {
+                record_downloaded(7);
+                tokio::task::yield_now().await;
+            }),
+        );
+        // Outside of a cycle only the process-wide metrics count
+        record_uploaded(1000);
+
+        let counted = |report: &CycleReport| {
+            (report.bytes.downloaded.load(Ordering::Relaxed), report.bytes.uploaded.load(Ordering::Relaxed))
+        };
+        assert_eq!(counted(&forge), (100, 10));
+        assert_eq!(counted(&fabric), (7, 0));
+    }
+}
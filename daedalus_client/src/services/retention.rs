@@ -0,0 +1,453 @@
+//! Retention policy for root manifest history and timestamped loader manifests
+//!
+//! Every cycle writes a new `history/manifest-<ts>.json` and one
+//! `manifests/<loader>/<ts>.json` per loader. Without pruning these grow by a
+//! full set every hour. Snapshots are thinned out with age:
+//!
+//! - everything younger than `keep_all` is kept
+//! - up to `hourly` old, the newest snapshot of each hour is kept
+//! - up to `daily` old, the newest snapshot of each day is kept
+//! - anything older is deleted unless it is pinned
+//!
+//! A loader manifest is only deleted if neither the live root manifest nor
+//! any retained snapshot references it, and it is older than `keep_all`. CAS
+//! objects are never touched here.
+
+use crate::infrastructure::config::RetentionConfig;
+use crate::infrastructure::error::{invalid_input, Error};
+use crate::services::bucket::{delete_object, list_objects};
+use crate::services::cas::{RootManifest, CAS_VERSION};
+use crate::services::history::{history_path, list_snapshots, load_snapshot, snapshot_id};
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use dashmap::DashMap;
+use futures::stream::{self, StreamExt};
+use s3::Bucket;
+use std::collections::{HashMap, HashSet};
+use std::sync::LazyLock;
+use tracing::{info, instrument, warn};
+
+/// Number of concurrent delete requests
+const MAX_CONCURRENT_DELETES: usize = 10;
+
+/// Loader manifest URLs referenced by each history snapshot
+///
+/// Snapshots are immutable, so this only grows and saves re-reading hundreds
+/// of snapshots every cycle.
+static SNAPSHOT_REFERENCES: LazyLock<DashMap<String, HashSet<String>>> =
+    LazyLock::new(DashMap::new);
+
+/// How long snapshots are kept at each granularity
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Keep every snapshot younger than this
+    pub keep_all: Duration,
+    /// Keep one snapshot per hour up to this age
+    pub hourly: Duration,
+    /// Keep one snapshot per day up to this age
+    pub daily: Duration,
+    /// Snapshot ids that are never deleted
+    pub pinned: HashSet<String>,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            keep_all: Duration::hours(48),
+            hourly: Duration::days(14),
+            daily: Duration::days(365),
+            pinned: HashSet::new(),
+        }
+    }
+}
+
+impl RetentionPolicy {
+    /// Build the policy from the `retention` config section
+    ///
+    /// Returns `None` when retention is disabled.
+    pub fn from_config(config: &RetentionConfig) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+
+        Some(Self {
+            keep_all: Duration::hours(config.keep_all_hours),
+            hourly: Duration::days(config.hourly_days),
+            daily: Duration::days(config.daily_days),
+            pinned: config
+                .pinned
+                .iter()
+                .map(|id| snapshot_id(id).to_string())
+                .collect(),
+        })
+    }
+
+    /// Select the snapshot ids to keep
+    ///
+    /// The newest snapshot, pinned snapshots and snapshots whose id cannot be
+    /// parsed as a timestamp are always kept.
+    pub fn snapshots_to_keep(&self, ids: &[String], now: DateTime<Utc>) -> HashSet<String> {
+        let mut parsed: Vec<(&String, Option<DateTime<Utc>>)> =
+            ids.iter().map(|id| (id, parse_timestamp(id))).collect();
+        // Newest first, so the first snapshot seen in each bucket is kept
+        parsed.sort_by(|a, b| b.1.cmp(&a.1));
+
+        let mut keep = HashSet::new();
+        let mut seen_buckets = HashSet::new();
+
+        if let Some((newest, _)) = parsed.iter().find(|(_, ts)| ts.is_some()) {
+            keep.insert((*newest).clone());
+        }
+
+        for (id, timestamp) in parsed {
+            let Some(timestamp) = timestamp else {
+                keep.insert(id.clone());
+                continue;
+            };
+
+            let age = now - timestamp;
+            let retained = if self.pinned.contains(id) || age <= self.keep_all {
+                true
+            } else if age <= self.hourly {
+                seen_buckets.insert(timestamp.format("hour-%Y-%m-%dT%H").to_string())
+            } else if age <= self.daily {
+                seen_buckets.insert(timestamp.format("day-%Y-%m-%d").to_string())
+            } else {
+                false
+            };
+
+            if retained {
+                keep.insert(id.clone());
+            }
+        }
+
+        keep
+    }
+}
+
+/// Parse a `%Y-%m-%dT%H-%M-%SZ` timestamp as used in snapshot and manifest
+/// names, with or without fractional seconds
+pub fn parse_timestamp(id: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(id, "%Y-%m-%dT%H-%M-%S%.fZ")
+        .ok()
+        .map(|naive| naive.and_utc())
+}
+
+/// Objects selected for deletion
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RetentionPlan {
+    /// History snapshot keys to delete
+    pub snapshots: Vec<String>,
+    /// Loader manifest keys to delete
+    pub loader_manifests: Vec<String>,
+}
+
+/// Decide which snapshots and loader manifests to delete
+///
+/// * `snapshot_ids` - all history snapshot ids
+/// * `manifest_keys` - all loader manifest keys under `manifests/`
+/// * `references` - loader manifest URLs referenced by each retained snapshot
+/// * `live_root` - the root manifest that was just committed
+///
+/// Fails if a retained snapshot has no entry in `references`, since we then
+/// cannot prove which loader manifests are still needed.
+pub fn plan(
+    policy: &RetentionPolicy,
+    now: DateTime<Utc>,
+    snapshot_ids: &[String],
+    manifest_keys: &[String],
+    references: &HashMap<String, HashSet<String>>,
+    live_root: &RootManifest,
+) -> Result<RetentionPlan, Error> {
+    let keep = policy.snapshots_to_keep(snapshot_ids, now);
+
+    let mut protected: HashSet<&str> = live_root
+        .loaders
+        .values()
+        .map(|reference| reference.url.as_str())
+        .collect();
+
+    for id in &keep {
+        let urls = references.get(id).ok_or_else(|| {
+            invalid_input(format!("References of retained snapshot {} are unknown", id))
+        })?;
+        protected.extend(urls.iter().map(String::as_str));
+    }
+
+    let mut snapshots: Vec<String> = snapshot_ids
+        .iter()
+        .filter(|id| !keep.contains(*id))
+        .map(|id| history_path(id))
+        .collect();
+    snapshots.sort();
+
+    let mut loader_manifests: Vec<String> = manifest_keys
+        .iter()
+        .filter(|key| !protected.contains(key.as_str()))
+        .filter(|key| {
+            // Unknown names and young manifests (possibly from a cycle that
+            // has not committed yet) are kept
+            manifest_timestamp(key)
+                .and_then(parse_timestamp)
+                .map(|timestamp| now - timestamp > policy.keep_all)
+                .unwrap_or(false)
+        })
+        .cloned()
+        .collect();
+    loader_manifests.sort();
+
+    Ok(RetentionPlan {
+        snapshots,
+        loader_manifests,
+    })
+}
+
+/// Timestamp part of a `manifests/<loader>/<ts>.json` key
+fn manifest_timestamp(key: &str) -> Option<&str> {
+    key.rsplit('/').next()?.strip_suffix(".json")
+}
+
+/// Resolve what the retention policy would delete from the bucket
+///
+/// Any failure to resolve what a retained snapshot references is an error,
+/// so nothing is deleted based on an incomplete picture.
+#[instrument(skip(s3_client, policy, live_root))]
+pub async fn resolve(
+    s3_client: &Bucket,
+    policy: &RetentionPolicy,
+    live_root: &RootManifest,
+) -> Result<RetentionPlan, Error> {
+    let now = Utc::now();
+
+    let snapshot_ids: Vec<String> = list_snapshots(s3_client)
+        .await?
+        .into_iter()
+        .map(|entry| entry.id)
+        .collect();
+
+    let manifest_prefix = format!("v{}/manifests/", CAS_VERSION);
+    let manifest_keys: Vec<String> = list_objects(s3_client, &manifest_prefix)
+        .await?
+        .into_iter()
+        .map(|object| object.key)
+        .collect();
+
+    let keep = policy.snapshots_to_keep(&snapshot_ids, now);
+
+    let mut references = HashMap::new();
+    for id in &keep {
+        if let Some(urls) = SNAPSHOT_REFERENCES.get(id) {
+            references.insert(id.clone(), urls.clone());
+            continue;
+        }
+
+        let root = load_snapshot(s3_client, id).await?;
+        let urls: HashSet<String> = root
+            .loaders
+            .into_values()
+            .map(|reference| reference.url)
+            .collect();
+
+        SNAPSHOT_REFERENCES.insert(id.clone(), urls.clone());
+        references.insert(id.clone(), urls);
+    }
+
+    let plan = plan(
+        policy,
+        now,
+        &snapshot_ids,
+        &manifest_keys,
+        &references,
+        live_root,
+    )?;
+
+    info!(
+        snapshots_total = snapshot_ids.len(),
+        snapshots_kept = keep.len(),
+        snapshots_deleted = plan.snapshots.len(),
+        loader_manifests_total = manifest_keys.len(),
+        loader_manifests_deleted = plan.loader_manifests.len(),
+        "Resolved retention plan"
+    );
+
+    Ok(plan)
+}
+
+/// Apply the retention policy to the bucket
+///
+/// Called after a root manifest has been committed. Any failure to resolve
+/// what a retained snapshot references aborts the run before deleting.
+///
+/// # Returns
+///
+/// The plan that was executed
+#[instrument(skip(s3_client, policy, live_root))]
+pub async fn enforce(
+    s3_client: &Bucket,
+    policy: &RetentionPolicy,
+    live_root: &RootManifest,
+) -> Result<RetentionPlan, Error> {
+    let plan = resolve(s3_client, policy, live_root).await?;
+
+    let results: Vec<(String, Result<(), Error>)> = stream::iter(
+        plan.snapshots.iter().chain(plan.loader_manifests.iter()),
+    )
+    .map(|key| async move { (key.clone(), delete_object(s3_client, key).await) })
+    .buffer_unordered(MAX_CONCURRENT_DELETES)
+    .collect()
+    .await;
+
+    let mut failed = 0;
+    for (key, result) in results {
+        if let Err(err) = result {
+            failed += 1;
+            warn!(key = %key, error = %err, "Failed to delete expired object");
+        }
+    }
+
+    for key in &plan.snapshots {
+        SNAPSHOT_REFERENCES.remove(snapshot_id(key));
+    }
+
+    if failed > 0 {
+        warn!(failed, "Retention completed with some failed deletions");
+    } else {
+        info!("Retention completed successfully");
+    }
+
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::cas::LoaderReference;
+
+    fn id(timestamp: DateTime<Utc>) -> String {
+        timestamp.format("%Y-%m-%dT%H-%M-%SZ").to_string()
+    }
+
+    fn now() -> DateTime<Utc> {
+        parse_timestamp("2024-06-01T12-00-00Z").unwrap()
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        let timestamp = parse_timestamp("2024-01-15T10-30-00Z").unwrap();
+        assert_eq!(id(timestamp), "2024-01-15T10-30-00Z");
+        assert!(parse_timestamp("latest").is_none());
+        assert_eq!(
+            parse_timestamp("2024-01-15T10-30-00.250Z").unwrap(),
+            timestamp + chrono::Duration::milliseconds(250)
+        );
+    }
+
+    #[test]
+    fn test_snapshots_to_keep() {
+        let policy = RetentionPolicy::default();
+        let now = now();
+
+        let recent = id(now - Duration::hours(1));
+        // Two snapshots in the same hour, five days ago
+        let hourly_newer = id(now - Duration::days(5) + Duration::minutes(40));
+        let hourly_older = id(now - Duration::days(5) + Duration::minutes(10));
+        // Two snapshots on the same day, a month ago
+        let daily_newer = id(now - Duration::days(30));
+        let daily_older = id(now - Duration::days(30) - Duration::hours(3));
+        let expired = id(now - Duration::days(400));
+        let pinned = id(now - Duration::days(500));
+
+        let policy = RetentionPolicy {
+            pinned: HashSet::from([pinned.clone()]),
+            ..policy
+        };
+
+        let ids = vec![
+            recent.clone(),
+            hourly_newer.clone(),
+            hourly_older.clone(),
+            daily_newer.clone(),
+            daily_older.clone(),
+            expired.clone(),
+            pinned.clone(),
+            "not-a-timestamp".to_string(),
+        ];
+
+        let keep = policy.snapshots_to_keep(&ids, now);
+
+        assert!(keep.contains(&recent));
+        assert!(keep.contains(&hourly_newer));
+        assert!(!keep.contains(&hourly_older));
+        assert!(keep.contains(&daily_newer));
+        assert!(!keep.contains(&daily_older));
+        assert!(!keep.contains(&expired));
+        assert!(keep.contains(&pinned));
+        assert!(keep.contains("not-a-timestamp"));
+    }
+
+    #[test]
+    fn test_newest_snapshot_is_always_kept() {
+        let now = now();
+        let old = id(now - Duration::days(1000));
+
+        let keep = RetentionPolicy::default().snapshots_to_keep(&[old.clone()], now);
+
+        assert!(keep.contains(&old));
+    }
+
+    #[test]
+    fn test_plan_protects_referenced_manifests() {
+        let policy = RetentionPolicy::default();
+        let now = now();
+
+        let kept = id(now - Duration::hours(1));
+        let expired = id(now - Duration::days(400));
+        let newest = id(now);
+
+        let old_ts = id(now - Duration::days(400));
+        let live_ts = id(now - Duration::days(600));
+        let young_ts = id(now - Duration::minutes(5));
+
+        let referenced = LoaderReference::new("forge", old_ts.clone()).url;
+        let live = LoaderReference::new("fabric", live_ts.clone()).url;
+        let orphan = LoaderReference::new("quilt", old_ts.clone()).url;
+        let young = LoaderReference::new("quilt", young_ts).url;
+
+        let mut live_root = RootManifest::empty();
+        live_root.add_loader("fabric".to_string(), live_ts);
+
+        let references = HashMap::from([
+            (kept.clone(), HashSet::from([referenced.clone()])),
+            (newest.clone(), HashSet::new()),
+        ]);
+
+        let result = plan(
+            &policy,
+            now,
+            &[kept, expired.clone(), newest],
+            &[referenced, live, orphan.clone(), young],
+            &references,
+            &live_root,
+        )
+        .unwrap();
+
+        assert_eq!(result.snapshots, vec![history_path(&expired)]);
+        assert_eq!(result.loader_manifests, vec![orphan]);
+    }
+
+    #[test]
+    fn test_plan_requires_references_of_kept_snapshots() {
+        let now = now();
+        let kept = id(now - Duration::hours(1));
+
+        let result = plan(
+            &RetentionPolicy::default(),
+            now,
+            &[kept],
+            &[],
+            &HashMap::new(),
+            &RootManifest::empty(),
+        );
+
+        assert!(result.is_err());
+    }
+}
@@ -1,6 +1,10 @@
+use crate::infrastructure::config::UploadConfig;
+use crate::services::maven::MavenArtifact;
+use crate::services::metadata::ObjectMetadata;
 use backon::{ExponentialBuilder, Retryable};
 use s3::Bucket;
 use sha2::{Digest, Sha256};
+use dashmap::{DashMap, DashSet};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::Semaphore;
@@ -20,7 +24,7 @@ use tracing::{error, info, instrument};
 /// # Example
 ///
 /// ```no_run
-/// let uploader = BatchUploader::new();
+/// let uploader = BatchUploader::new(&config.upload);
 ///
 /// // Upload content to CAS and get its hash
 /// let hash = uploader.upload_cas(
@@ -33,12 +37,23 @@ use tracing::{error, info, instrument};
 /// // Hash can be used in manifests to reference the content
 /// println!("Content stored at hash: {}", hash);
 /// ```
-pub struct BatchUploader;
+pub struct BatchUploader {
+    /// CAS objects uploaded through this uploader, by hash
+    uploaded: DashSet<String>,
+    /// Maven artifacts uploaded through this uploader (artifact path → object)
+    maven_artifacts: DashMap<String, MavenArtifact>,
+    /// Retries of every upload
+    retries: UploadConfig,
+}
 
 impl BatchUploader {
-    /// Create a new batch uploader
-    pub fn new() -> Self {
-        Self
+    /// Create a new batch uploader retrying uploads as configured
+    pub fn new(retries: &UploadConfig) -> Self {
+        Self {
+            uploaded: DashSet::new(),
+            maven_artifacts: DashMap::new(),
+            retries: retries.clone(),
+        }
     }
 
     /// Compute SHA256 hash of content
@@ -78,8 +93,31 @@ impl BatchUploader {
         content_type: Option<String>,
         s3_client: &Bucket,
         semaphore: Arc<Semaphore>,
+    ) -> Result<String, crate::infrastructure::error::Error> {
+        self.upload_cas_named(content, content_type, None, s3_client, semaphore)
+            .await
+    }
+
+    /// Upload content to CAS with a download file name
+    ///
+    /// Same as [`BatchUploader::upload_cas`], but the object is served with a
+    /// `Content-Disposition` carrying `filename` (e.g. `forge-1.20.1-47.1.0.jar`)
+    /// instead of its bare hash.
+    #[instrument(skip(self, content, s3_client, semaphore), fields(size = content.len()))]
+    pub async fn upload_cas_named(
+        &self,
+        content: Vec<u8>,
+        content_type: Option<String>,
+        filename: Option<&str>,
+        s3_client: &Bucket,
+        semaphore: Arc<Semaphore>,
     ) -> Result<String, crate::infrastructure::error::Error> {
         let hash = Self::compute_hash(&content);
+        if self.uploaded.contains(&hash) {
+            crate::infrastructure::metrics::CAS_DEDUPE_HITS.inc();
+            return Ok(hash);
+        }
+
         let path = format!(
             "v{}/objects/{}/{}",
             crate::services::cas::CAS_VERSION,
@@ -89,26 +127,77 @@ impl BatchUploader {
 
         info!(hash = %hash, path = %path, "Uploading to CAS");
 
+        let mut metadata = ObjectMetadata::new(&path, &content);
+        if let Some(filename) = filename {
+            metadata = metadata.with_filename(filename);
+        }
+
         upload_single_file(
             &path,
             &content,
             content_type.as_deref(),
+            &metadata,
+            &self.retries,
             s3_client,
             semaphore,
         )
         .await?;
 
         info!(hash = %hash, "CAS upload completed");
+        self.uploaded.insert(hash.clone());
         Ok(hash)
     }
-}
 
-impl Default for BatchUploader {
-    fn default() -> Self {
-        Self::new()
+    /// Upload a rehosted maven artifact to CAS and record it for the maven
+    /// repository
+    ///
+    /// `artifact_path` is the maven layout path of the artifact (e.g.
+    /// `net/minecraftforge/forge/1.20.1-47.1.0/forge-1.20.1-47.1.0-universal.jar`).
+    /// The artifacts recorded during a cycle are published under `maven/` by
+    /// [`crate::services::maven::publish`].
+    pub async fn upload_maven_artifact(
+        &self,
+        content: Vec<u8>,
+        artifact_path: &str,
+        s3_client: &Bucket,
+        semaphore: Arc<Semaphore>,
+    ) -> Result<String, crate::infrastructure::error::Error> {
+        let sha1 = crate::services::metadata::sha1_hex(&content);
+
+        let hash = self
+            .upload_cas_named(
+                content,
+                Some("application/java-archive".to_string()),
+                artifact_path.rsplit('/').next(),
+                s3_client,
+                semaphore,
+            )
+            .await?;
+
+        self.maven_artifacts.insert(
+            artifact_path.to_string(),
+            MavenArtifact {
+                hash: hash.clone(),
+                sha1,
+            },
+        );
+
+        Ok(hash)
+    }
+
+    /// Maven artifacts uploaded so far, sorted by path
+    pub fn maven_artifacts(&self) -> Vec<(String, MavenArtifact)> {
+        let mut artifacts: Vec<_> = self
+            .maven_artifacts
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect();
+        artifacts.sort_by(|a, b| a.0.cmp(&b.0));
+        artifacts
     }
 }
 
+
 /// Upload a single file to S3 with retry logic
 ///
 /// Internal helper function that handles the actual S3 upload with
@@ -119,17 +208,26 @@ impl Default for BatchUploader {
 /// * `path` - S3 object path
 /// * `bytes` - File content
 /// * `content_type` - Optional MIME type
+/// * `metadata` - Cache-Control and other headers stored with the object
+/// * `retries` - Retry attempts and backoff
 /// * `s3_client` - S3 bucket client
 /// * `semaphore` - Semaphore for concurrent upload limiting
-#[instrument(skip(bytes, s3_client, semaphore), fields(size = bytes.len()))]
+#[instrument(skip(bytes, metadata, retries, s3_client, semaphore), fields(size = bytes.len()))]
 async fn upload_single_file(
     path: &str,
     bytes: &[u8],
     content_type: Option<&str>,
+    metadata: &ObjectMetadata,
+    retries: &UploadConfig,
     s3_client: &Bucket,
     semaphore: Arc<Semaphore>,
 ) -> Result<(), crate::infrastructure::error::Error> {
-    let _permit = semaphore.acquire().await?;
+    if let Some(dir) = crate::services::dry_run::output_dir() {
+        return crate::services::dry_run::write(dir, path, bytes).await;
+    }
+
+    let _permit = crate::infrastructure::metrics::acquire_permit(&semaphore).await?;
+    let s3_client = &metadata.apply(s3_client);
 
     info!(path = %path, "Started uploading");
 
@@ -149,6 +247,7 @@ async fn upload_single_file(
         match result {
             Ok(_) => {
                 info!(path = %path, "Upload completed");
+                crate::services::report::record_uploaded(bytes.len());
                 Ok(())
             }
             Err(err) => {
@@ -159,9 +258,12 @@ async fn upload_single_file(
     })
     .retry(
         ExponentialBuilder::default()
-            .with_max_times(10)
-            .with_max_delay(Duration::from_secs(1800)),
+            .with_max_times(retries.max_retries)
+            .with_max_delay(Duration::from_secs(retries.max_retry_delay_secs)),
     )
+    // The upload in flight finishes on shutdown, failed ones are not retried
+    .when(|_| !crate::infrastructure::shutdown::is_requested())
+    .notify(|_, _| crate::infrastructure::metrics::record_retry("upload"))
     .await
 }
 
@@ -171,9 +273,9 @@ mod tests {
 
     #[test]
     fn test_batch_uploader_creation() {
-        let uploader = BatchUploader::new();
-        // Uploader is stateless, just verify it can be created
-        let _ = uploader;
+        let uploader = BatchUploader::new(&UploadConfig::default());
+        // Nothing has been uploaded yet
+        assert!(uploader.maven_artifacts().is_empty());
     }
 
     #[test]
@@ -0,0 +1,308 @@
+//! Webhook notifications
+//!
+//! Events are posted to every webhook of `notifications.webhooks` that
+//! subscribed to them:
+//!
+//! - `new_versions`: a commit published new Minecraft versions or loader
+//!   builds, taken from the changelog of the cycle
+//! - `loader_failing`: a loader failed `notifications.failure_threshold` runs
+//!   in a row, sent once per streak
+//! - `breaker_open`: a circuit breaker opened after consecutive failures
+//!
+//! The payload is the event as JSON, or a Discord or Slack message depending
+//! on the webhook's `format`. With a `secret` the body is signed with
+//! HMAC-SHA256 in `X-Daedalus-Signature: sha256=<hex>`. Failed deliveries are
+//! logged, not retried.
+
+use crate::infrastructure::config::{Config, WebhookConfig, WebhookEvent, WebhookFormat};
+use crate::infrastructure::error::{fetch_error, invalid_input, Error};
+use crate::services::changelog::{Changelog, VersionChange};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use serde_json::Value;
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::sync::LazyLock;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Versions listed per loader in Discord and Slack messages
+const LISTED_VERSIONS: usize = 20;
+
+static HTTP_CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()
+        .expect("Failed to build HTTP client")
+});
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    NewVersions {
+        /// Timestamp of the root manifest that published them
+        timestamp: String,
+        /// Loader → versions added
+        loaders: BTreeMap<String, Vec<VersionChange>>,
+    },
+    LoaderFailing {
+        loader: String,
+        consecutive_failures: u32,
+        error: String,
+    },
+    BreakerOpen {
+        breaker: String,
+        failures: u32,
+        error: String,
+    },
+}
+
+impl Event {
+    /// The versions `changelog` added, `None` when it added none
+    pub fn new_versions(changelog: &Changelog) -> Option<Self> {
+        let loaders: BTreeMap<_, _> = changelog
+            .loaders
+            .iter()
+            .filter(|(_, changes)| !changes.added.is_empty())
+            .map(|(loader, changes)| (loader.clone(), changes.added.clone()))
+            .collect();
+
+        (!loaders.is_empty()).then(|| Self::NewVersions {
+            timestamp: changelog.timestamp.clone(),
+            loaders,
+        })
+    }
+
+    fn kind(&self) -> WebhookEvent {
+        match self {
+            Self::NewVersions { .. } => WebhookEvent::NewVersions,
+            Self::LoaderFailing { .. } => WebhookEvent::LoaderFailing,
+            Self::BreakerOpen { .. } => WebhookEvent::BreakerOpen,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self.kind() {
+            WebhookEvent::NewVersions => "new_versions",
+            WebhookEvent::LoaderFailing => "loader_failing",
+            WebhookEvent::BreakerOpen => "breaker_open",
+        }
+    }
+
+    /// One line summary, e.g. `New versions: forge 2, minecraft 1`
+    fn title(&self) -> String {
+        match self {
+            Self::NewVersions { loaders, .. } => format!(
+                "New versions: {}",
+                loaders
+                    .iter()
+                    .map(|(loader, added)| format!("{} {}", loader, added.len()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Self::LoaderFailing {
+                loader,
+                consecutive_failures,
+                ..
+            } => format!("{} failed {} runs in a row", loader, consecutive_failures),
+            Self::BreakerOpen { breaker, failures, .. } => {
+                format!("{} circuit breaker opened after {} failures", breaker, failures)
+            }
+        }
+    }
+
+    /// Plain text details for chat messages
+    fn body(&self) -> String {
+        match self {
+            Self::NewVersions { loaders, .. } => {
+                let mut body = String::new();
+                for (loader, added) in loaders {
+                    let versions: Vec<_> = added
+                        .iter()
+                        .take(LISTED_VERSIONS)
+                        .map(|change| match &change.game_version {
+                            Some(game) => format!("{} ({})", change.id, game),
+                            None => change.id.clone(),
+                        })
+                        .collect();
+                    body.push_str(&format!("{}: {}", loader, versions.join(", ")));
+                    if added.len() > LISTED_VERSIONS {
+                        body.push_str(&format!(" and {} more", added.len() - LISTED_VERSIONS));
+                    }
+                    body.push('\n');
+                }
+                body
+            }
+            Self::LoaderFailing { error, .. } | Self::BreakerOpen { error, .. } => error.clone(),
+        }
+    }
+
+    /// Embed color of Discord messages
+    fn color(&self) -> u32 {
+        match self {
+            Self::NewVersions { .. } => 0x2ecc71,
+            Self::LoaderFailing { .. } => 0xe67e22,
+            Self::BreakerOpen { .. } => 0xe74c3c,
+        }
+    }
+
+    fn payload(&self, format: WebhookFormat) -> Value {
+        match format {
+            WebhookFormat::Json => {
+                let mut payload = serde_json::to_value(self).expect("events serialize to JSON");
+                payload["summary"] = Value::String(self.title());
+                payload
+            }
+            WebhookFormat::Discord => serde_json::json!({
+                "embeds": [{
+                    "title": truncate(&self.title(), 256),
+                    "description": truncate(&self.body(), 4096),
+                    "color": self.color(),
+                }]
+            }),
+            WebhookFormat::Slack => serde_json::json!({
+                "text": truncate(&format!("*{}*\n{}", self.title(), self.body()), 3000),
+            }),
+        }
+    }
+}
+
+/// Cut `text` to at most `max` characters
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(max - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// Value of `X-Daedalus-Signature` for `body`
+fn signature(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    format!("sha256={:x}", mac.finalize().into_bytes())
+}
+
+/// Post `event` to every webhook subscribed to it
+pub async fn notify(config: &Config, event: Event) {
+    let webhooks: Vec<_> = config
+        .notifications
+        .webhooks
+        .iter()
+        .filter(|webhook| webhook.wants(event.kind()))
+        .collect();
+    if webhooks.is_empty() {
+        return;
+    }
+
+    if crate::services::dry_run::is_enabled() {
+        info!(event = event.name(), "Dry run: skipping webhook notifications");
+        return;
+    }
+
+    let user_agent = config.user_agent();
+    let deliveries = webhooks.into_iter().map(|webhook| {
+        let event = &event;
+        let user_agent = &user_agent;
+        async move {
+            // The URL itself carries the credentials of Discord and Slack webhooks
+            let host = reqwest::Url::parse(&webhook.url)
+                .ok()
+                .and_then(|url| url.host_str().map(str::to_string))
+                .unwrap_or_default();
+            match send(event, webhook, user_agent).await {
+                Ok(()) => info!(event = event.name(), host = %host, "Webhook notified"),
+                Err(e) => warn!(event = event.name(), host = %host, error = %e, "Failed to notify webhook"),
+            }
+        }
+    });
+    futures::future::join_all(deliveries).await;
+}
+
+async fn send(event: &Event, webhook: &WebhookConfig, user_agent: &str) -> Result<(), Error> {
+    let body = serde_json::to_vec(&event.payload(webhook.format))?;
+
+    let mut request = HTTP_CLIENT
+        .post(&webhook.url)
+        .header("User-Agent", user_agent)
+        .header("Content-Type", "application/json")
+        .header("X-Daedalus-Event", event.name());
+    if let Some(secret) = &webhook.secret {
+        request = request.header("X-Daedalus-Signature", signature(secret, &body));
+    }
+
+    let response = request
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| fetch_error(e, "webhook"))?;
+    if !response.status().is_success() {
+        return Err(invalid_input(format!("webhook returned status {}", response.status())));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::changelog::LoaderChanges;
+
+    fn added(game_version: Option<&str>, id: &str) -> VersionChange {
+        VersionChange {
+            game_version: game_version.map(str::to_string),
+            id: id.to_string(),
+            old_hash: None,
+            new_hash: Some("abc".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_new_versions_payloads() {
+        let mut changelog = Changelog {
+            schema_version: 1,
+            timestamp: "2024-01-15T10-30-00Z".to_string(),
+            loaders: BTreeMap::new(),
+        };
+        changelog.loaders.insert(
+            "fabric".to_string(),
+            LoaderChanges {
+                removed: vec![added(None, "0.14.0")],
+                ..LoaderChanges::default()
+            },
+        );
+        assert_eq!(Event::new_versions(&changelog), None);
+
+        changelog.loaders.insert(
+            "forge".to_string(),
+            LoaderChanges {
+                added: vec![added(Some("1.21.1"), "1.21.1-52.0.2")],
+                ..LoaderChanges::default()
+            },
+        );
+        let event = Event::new_versions(&changelog).unwrap();
+
+        let json = event.payload(WebhookFormat::Json);
+        assert_eq!(json["event"], "new_versions");
+        assert_eq!(json["summary"], "New versions: forge 1");
+        assert_eq!(json["loaders"]["forge"][0]["id"], "1.21.1-52.0.2");
+        assert!(json["loaders"].get("fabric").is_none());
+
+        let discord = event.payload(WebhookFormat::Discord);
+        assert_eq!(discord["embeds"][0]["description"], "forge: 1.21.1-52.0.2 (1.21.1)\n");
+
+        let slack = event.payload(WebhookFormat::Slack);
+        assert_eq!(slack["text"], "*New versions: forge 1*\nforge: 1.21.1-52.0.2 (1.21.1)\n");
+    }
+
+    #[test]
+    fn test_signature() {
+        assert_eq!(
+            signature("key", b"The quick brown fox jumps over the lazy dog"),
+            "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+        assert_eq!(truncate("abcdef", 4), "abc…");
+        assert_eq!(truncate("abc", 4), "abc");
+    }
+}